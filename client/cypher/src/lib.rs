@@ -5,8 +5,17 @@ use crypto_box::{
 	PublicKey, SalsaBox, SecretKey,
 };
 
+pub mod stream;
+
 pub type SalsaNonce = Nonce<SalsaBox>;
 
+/// Size of the authentication tag appended to every ciphertext
+pub const MAC_SIZE: usize = 16;
+/// Size of a nonce in bytes
+pub const NONCE_SIZE: usize = 24;
+/// Size of a public key in bytes
+pub const KEY_SIZE: usize = 32;
+
 #[doc(inline)]
 pub use cypher_macro::Cypher;
 
@@ -20,6 +29,49 @@ pub enum CypherError {
 	UnexpectedNonceType(SalsaNonce),
 	#[error("Could not parse pubkey {0:?}")]
 	InvalidPubkey(Vec<u8>),
+	#[error("Could not parse nonce {0:?}")]
+	InvalidNonce(Vec<u8>),
+	#[error("Could not decode hex: {0}")]
+	InvalidHex(#[from] hex::FromHexError),
+	#[error("No metadata channel was decrypted")]
+	NotDecrypted,
+	#[error("Party {index} of a matched metadata channel could not be decrypted")]
+	PartyDecryptFailed { index: usize },
+	#[error("Root hash does not match metadata and message")]
+	HashMismatch,
+	#[error("Sealed message has no origin header")]
+	MissingOrigin,
+	#[error("Recipient {0:?} is duplicated or equal to the sender")]
+	DuplicateRecipient(PublicKey),
+	#[error("Ciphertext is empty")]
+	EmptyCiphertext,
+	#[error("Ciphertext is too short, got {got} bytes, expected at least {min}")]
+	CiphertextTooShort { got: usize, min: usize },
+	#[error("Nonce {0:?} was already used in this session")]
+	NonceReused(SalsaNonce),
+	#[error("Too many recipients, got {got}, expected at most {max}")]
+	TooManyRecipients { got: usize, max: usize },
+	#[error("Message entry value is not valid JSON")]
+	InvalidJson,
+	#[error("Message has no entries")]
+	EmptyMessage,
+	#[error("Message entry {0} has an empty key or value")]
+	EmptyEntry(usize),
+	#[error("Too many message entries, got {got}, expected at most {max}")]
+	TooManyEntries { got: usize, max: usize },
+	#[error("Metadata channel {index} has {parties} parties, expected {channels}")]
+	ChannelPartyMismatch { index: usize, parties: usize, channels: usize },
+	/// Carries the `MetadataError` of the rule broken, which this crate can't name
+	#[error("Metadata would be rejected by the chain: {0}")]
+	InvalidMetadata(#[source] Box<dyn std::error::Error + Send + Sync>),
+	#[error("Metadata could not be decoded: {0}")]
+	MetadataMalformed(String),
+	#[error("Could not read or write data stream: {0}")]
+	Io(#[from] std::io::Error),
+	#[error("Decompressed entry value exceeds {max} bytes")]
+	DecompressedTooLarge { max: usize },
+	#[error("Could not parse secret key of {len} bytes, expected {expected}")]
+	InvalidSecretKey { len: usize, expected: usize },
 }
 
 pub trait Cypher
@@ -93,7 +145,125 @@ impl BytesCypher for [u8] {
 		pk: &PublicKey,
 		sk: &SecretKey,
 	) -> Result<Vec<u8>, CypherError> {
+		if self.is_empty() {
+			return Err(CypherError::EmptyCiphertext)
+		}
+		if self.len() < MAC_SIZE {
+			return Err(CypherError::CiphertextTooShort { got: self.len(), min: MAC_SIZE })
+		}
+
 		let sbox = SalsaBox::new(pk, sk);
 		sbox.decrypt(nonce, self).map_err(|_| CypherError::DecryptionFailed(pk.clone()))
 	}
 }
+
+/// Decode a hex string, `0x` prefix is optional
+fn decode_hex(s: &str) -> Result<Vec<u8>, CypherError> {
+	let s = s.trim();
+	Ok(hex::decode(s.strip_prefix("0x").unwrap_or(s))?)
+}
+
+/// Parse a hex encoded nonce, `0x` prefix is optional
+pub fn parse_nonce_hex(s: &str) -> Result<SalsaNonce, CypherError> {
+	let bytes = decode_hex(s)?;
+	if bytes.len() != NONCE_SIZE {
+		return Err(CypherError::InvalidNonce(bytes))
+	}
+	Ok(*SalsaNonce::from_slice(&bytes))
+}
+
+/// Parse a hex encoded public key, `0x` prefix is optional
+pub fn parse_pubkey_hex(s: &str) -> Result<PublicKey, CypherError> {
+	let bytes = decode_hex(s)?;
+	let key: [u8; KEY_SIZE] = bytes
+		.as_slice()
+		.try_into()
+		.map_err(|_| CypherError::InvalidPubkey(bytes.clone()))?;
+	Ok(PublicKey::from(key))
+}
+
+/// Parse a hex encoded secret key, `0x` prefix is optional. Unlike `parse_pubkey_hex`, the error
+/// doesn't hold the key bytes
+pub fn parse_secret_key_hex(s: &str) -> Result<SecretKey, CypherError> {
+	let bytes = decode_hex(s)?;
+	let key: [u8; KEY_SIZE] = bytes
+		.as_slice()
+		.try_into()
+		.map_err(|_| CypherError::InvalidSecretKey { len: bytes.len(), expected: KEY_SIZE })?;
+	Ok(SecretKey::from(key))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crypto_box::aead::{AeadCore, OsRng};
+
+	#[test]
+	fn bytes_cypher_is_compatible_with_salsa_box() {
+		let sender_sk = SecretKey::generate(&mut OsRng);
+		let receiver_sk = SecretKey::generate(&mut OsRng);
+		let nonce = SalsaBox::generate_nonce(&mut OsRng);
+		let data = b"data shared between the CLI and the client".as_slice();
+
+		// encrypted with the cypher, decrypted with a plain `SalsaBox`
+		let encrypted = data.encrypt(&nonce, &receiver_sk.public_key(), &sender_sk).unwrap();
+		let sbox = SalsaBox::new(&sender_sk.public_key(), &receiver_sk);
+		assert_eq!(sbox.decrypt(&nonce, encrypted.as_slice()).unwrap(), data);
+
+		// encrypted with a plain `SalsaBox`, decrypted with the cypher
+		let sbox = SalsaBox::new(&receiver_sk.public_key(), &sender_sk);
+		let encrypted = sbox.encrypt(&nonce, data).unwrap();
+		let decrypted = encrypted.decrypt(&nonce, &sender_sk.public_key(), &receiver_sk).unwrap();
+		assert_eq!(decrypted, data);
+	}
+	#[test]
+	fn malformed_ciphertext_is_reported() {
+		let sender_sk = SecretKey::generate(&mut OsRng);
+		let receiver_sk = SecretKey::generate(&mut OsRng);
+		let nonce = SalsaBox::generate_nonce(&mut OsRng);
+		let decrypt = |data: &[u8]| data.decrypt(&nonce, &sender_sk.public_key(), &receiver_sk);
+
+		assert!(matches!(decrypt(&[]), Err(CypherError::EmptyCiphertext)));
+
+		let encrypted = b"data".encrypt(&nonce, &receiver_sk.public_key(), &sender_sk).unwrap();
+		assert!(matches!(
+			decrypt(&encrypted[..MAC_SIZE - 1]),
+			Err(CypherError::CiphertextTooShort { got, min: MAC_SIZE }) if got == MAC_SIZE - 1
+		));
+		assert!(matches!(
+			decrypt(&encrypted[..encrypted.len() - 1]),
+			Err(CypherError::DecryptionFailed(_))
+		));
+		assert_eq!(decrypt(&encrypted).unwrap(), b"data");
+	}
+	#[test]
+	fn parse_hex() {
+		let nonce = SalsaBox::generate_nonce(&mut OsRng);
+		assert_eq!(parse_nonce_hex(&hex::encode(nonce)).unwrap(), nonce);
+		assert_eq!(parse_nonce_hex(&format!("0x{}", hex::encode(nonce))).unwrap(), nonce);
+		assert!(matches!(
+			parse_nonce_hex(&hex::encode(&nonce[1..])),
+			Err(CypherError::InvalidNonce(bytes)) if bytes == nonce[1..]
+		));
+		assert!(matches!(parse_nonce_hex("0xnot hex"), Err(CypherError::InvalidHex(_))));
+
+		let pk = SecretKey::generate(&mut OsRng).public_key();
+		assert_eq!(
+			parse_pubkey_hex(&hex::encode(pk.as_bytes())).unwrap().as_bytes(),
+			pk.as_bytes()
+		);
+		assert!(matches!(
+			parse_pubkey_hex(&hex::encode(&pk.as_bytes()[1..])),
+			Err(CypherError::InvalidPubkey(bytes)) if bytes == pk.as_bytes()[1..]
+		));
+		assert!(matches!(parse_pubkey_hex("zz"), Err(CypherError::InvalidHex(_))));
+
+		let sk = SecretKey::generate(&mut OsRng);
+		let parsed = parse_secret_key_hex(&format!(" 0x{}\n", hex::encode(sk.as_bytes()))).unwrap();
+		assert_eq!(parsed.as_bytes(), sk.as_bytes());
+		assert!(matches!(
+			parse_secret_key_hex(&hex::encode(&sk.as_bytes()[1..])),
+			Err(CypherError::InvalidSecretKey { len: 31, expected: KEY_SIZE })
+		));
+	}
+}
@@ -0,0 +1,216 @@
+//! Chunked encryption for large attachments.
+//!
+//! Input is split into chunks of `CHUNK_SIZE` bytes, every chunk is encrypted with its own nonce
+//! derived from the chunk index, so a recipient is able to decrypt the data chunk-by-chunk. The
+//! last chunk is marked in its nonce, which makes a truncated stream fail to decrypt.
+//!
+//! Each chunk is framed as a little-endian `u32` ciphertext length followed by the ciphertext.
+
+use std::io::{self, Read, Write};
+
+use crypto_box::{PublicKey, SecretKey};
+
+use crate::{BytesCypher, CypherError, SalsaNonce, MAC_SIZE};
+
+/// The size of a plaintext chunk in bytes
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Encrypt all data from the `reader` to the `writer`. `progress` is called with a total number
+/// of encrypted bytes after every chunk.
+pub fn encrypt_stream(
+	mut reader: impl Read,
+	mut writer: impl Write,
+	nonce: &SalsaNonce,
+	pk: &PublicKey,
+	sk: &SecretKey,
+	mut progress: impl FnMut(u64),
+) -> Result<(), CypherError> {
+	let mut current = vec![0; CHUNK_SIZE];
+	let mut next = vec![0; CHUNK_SIZE];
+	let mut len = fill(&mut reader, &mut current)?;
+	let mut processed = 0;
+
+	for index in 0.. {
+		// read ahead to know if the current chunk is the last one
+		let next_len = if len == CHUNK_SIZE { fill(&mut reader, &mut next)? } else { 0 };
+		let last = next_len == 0;
+
+		let encrypted = current[..len].encrypt(&chunk_nonce(nonce, index, last), pk, sk)?;
+		writer.write_all(&(encrypted.len() as u32).to_le_bytes())?;
+		writer.write_all(&encrypted)?;
+
+		processed += len as u64;
+		progress(processed);
+		if last {
+			break
+		}
+
+		std::mem::swap(&mut current, &mut next);
+		len = next_len;
+	}
+
+	Ok(())
+}
+
+/// Decrypt all chunks produced by `encrypt_stream` from the `reader` to the `writer`. `progress`
+/// is called with a total number of decrypted bytes after every chunk.
+pub fn decrypt_stream(
+	mut reader: impl Read,
+	mut writer: impl Write,
+	nonce: &SalsaNonce,
+	pk: &PublicKey,
+	sk: &SecretKey,
+	mut progress: impl FnMut(u64),
+) -> Result<(), CypherError> {
+	let mut current = read_frame(&mut reader)?
+		.ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "No encrypted chunks"))?;
+	let mut processed = 0;
+
+	for index in 0.. {
+		// read ahead to know if the current chunk is the last one
+		let next = read_frame(&mut reader)?;
+
+		let decrypted = current.decrypt(&chunk_nonce(nonce, index, next.is_none()), pk, sk)?;
+		writer.write_all(&decrypted)?;
+
+		processed += decrypted.len() as u64;
+		progress(processed);
+
+		match next {
+			Some(next) => current = next,
+			None => break,
+		}
+	}
+
+	Ok(())
+}
+
+/// Derive a nonce of the chunk with the given `index`
+fn chunk_nonce(nonce: &SalsaNonce, index: u64, last: bool) -> SalsaNonce {
+	let mut chunk_nonce = *nonce;
+	for (byte, index_byte) in chunk_nonce.iter_mut().zip(index.to_le_bytes()) {
+		*byte ^= index_byte;
+	}
+	if last {
+		let last_byte = chunk_nonce.len() - 1;
+		chunk_nonce[last_byte] ^= 1;
+	}
+	chunk_nonce
+}
+
+/// Read a single encrypted chunk, returns `None` if there are no chunks left
+fn read_frame(reader: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+	let mut header = [0; 4];
+	match fill(reader, &mut header)? {
+		0 => return Ok(None),
+		4 => (),
+		_ => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated chunk header")),
+	}
+
+	let len = u32::from_le_bytes(header) as usize;
+	if len > CHUNK_SIZE + MAC_SIZE {
+		return Err(io::Error::new(io::ErrorKind::InvalidData, "Chunk is too big"))
+	}
+
+	let mut frame = vec![0; len];
+	reader.read_exact(&mut frame)?;
+	Ok(Some(frame))
+}
+
+/// Read until the `buf` is full or the `reader` is exhausted, returns a number of bytes read
+fn fill(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+	let mut len = 0;
+	while len < buf.len() {
+		match reader.read(&mut buf[len..]) {
+			Ok(0) => break,
+			Ok(n) => len += n,
+			Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+			Err(e) => return Err(e),
+		}
+	}
+	Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crypto_box::{
+		aead::{AeadCore, OsRng},
+		SalsaBox,
+	};
+
+	fn round_trip(data: &[u8]) -> (Vec<u8>, usize) {
+		let sender_sk = SecretKey::generate(&mut OsRng);
+		let receiver_sk = SecretKey::generate(&mut OsRng);
+		let nonce = SalsaBox::generate_nonce(&mut OsRng);
+
+		let mut encrypted = vec![];
+		let mut chunks = 0;
+		encrypt_stream(data, &mut encrypted, &nonce, &receiver_sk.public_key(), &sender_sk, |_| {
+			chunks += 1
+		})
+		.unwrap();
+
+		let mut decrypted = vec![];
+		let mut decrypted_len = 0;
+		decrypt_stream(
+			encrypted.as_slice(),
+			&mut decrypted,
+			&nonce,
+			&sender_sk.public_key(),
+			&receiver_sk,
+			|len| decrypted_len = len,
+		)
+		.unwrap();
+		assert_eq!(decrypted_len, data.len() as u64);
+
+		(decrypted, chunks)
+	}
+
+	#[test]
+	fn encrypt_decrypt_stream_larger_than_chunk() {
+		let data: Vec<u8> = (0..CHUNK_SIZE * 2 + 100).map(|i| i as u8).collect();
+		let (decrypted, chunks) = round_trip(&data);
+		assert_eq!(chunks, 3);
+		assert_eq!(decrypted, data);
+	}
+
+	#[test]
+	fn encrypt_decrypt_stream_on_chunk_boundary() {
+		let data: Vec<u8> = (0..CHUNK_SIZE * 2).map(|i| i as u8).collect();
+		let (decrypted, chunks) = round_trip(&data);
+		assert_eq!(chunks, 2);
+		assert_eq!(decrypted, data);
+	}
+
+	#[test]
+	fn truncated_stream_is_rejected() {
+		let sender_sk = SecretKey::generate(&mut OsRng);
+		let receiver_sk = SecretKey::generate(&mut OsRng);
+		let nonce = SalsaBox::generate_nonce(&mut OsRng);
+		let data = vec![7; CHUNK_SIZE * 2];
+
+		let mut encrypted = vec![];
+		encrypt_stream(
+			data.as_slice(),
+			&mut encrypted,
+			&nonce,
+			&receiver_sk.public_key(),
+			&sender_sk,
+			|_| (),
+		)
+		.unwrap();
+
+		// drop the last chunk, so the first one is not marked as the last
+		encrypted.truncate(4 + CHUNK_SIZE + MAC_SIZE);
+		let result = decrypt_stream(
+			encrypted.as_slice(),
+			io::sink(),
+			&nonce,
+			&sender_sk.public_key(),
+			&receiver_sk,
+			|_| (),
+		);
+		assert!(matches!(result, Err(CypherError::DecryptionFailed(_))));
+	}
+}
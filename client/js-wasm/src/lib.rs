@@ -15,8 +15,8 @@ use crypto_box::{
 };
 use js_sys::{Array, Map, Uint8Array};
 use nolik_metadata::{
-	Channel, Cypher, Message, MessageAction, MessageEntry, MessageMetadata, MessageType, KEY_SIZE,
-	NONCE_SIZE,
+	Channel, Cypher, HashAlgo, Message, MessageAction, MessageEntry, MessageMetadata, MessageType,
+	KEY_SIZE, MAC_SIZE, NONCE_SIZE, SUBJECT_SIZE,
 };
 
 fn js_value_to_array<const N: usize>(value: JsValue) -> Result<[u8; N], JsValue> {
@@ -40,6 +40,31 @@ fn metadata_to_js_map(meta: &MessageMetadata) -> Map {
 	let val = Uint8Array::from(meta.hash.as_slice());
 	map.set(&"hash".into(), &JsValue::from(val));
 
+	map.set(&"hash_algo".into(), &JsValue::from(meta.hash_algo as u8));
+
+	if let Some(in_reply_to) = &meta.in_reply_to {
+		let val = Uint8Array::from(in_reply_to.as_slice());
+		map.set(&"in_reply_to".into(), &JsValue::from(val));
+	}
+
+	if let Some(entry_count) = meta.entry_count {
+		map.set(&"entry_count".into(), &JsValue::from(entry_count));
+	}
+
+	if let Some(part_count) = meta.part_count {
+		map.set(&"part_count".into(), &JsValue::from(part_count));
+	}
+
+	if let Some(subject) = &meta.subject {
+		let val = Uint8Array::from(subject.as_slice());
+		map.set(&"subject".into(), &JsValue::from(val));
+	}
+
+	if let Some(signature) = &meta.signature {
+		let val = Uint8Array::from(signature.as_slice());
+		map.set(&"signature".into(), &JsValue::from(val));
+	}
+
 	let channels = Array::new();
 	for ch in &meta.channels {
 		let channel = Map::new();
@@ -79,38 +104,160 @@ fn js_map_to_metadata(map: Map) -> Result<MessageMetadata, JsValue> {
 	let nonce = js_value_to_array::<NONCE_SIZE>(map.get(&"nonce".into()))?;
 	let broker = js_value_to_array::<KEY_SIZE>(map.get(&"broker".into()))?;
 	let hash = js_value_to_array::<KEY_SIZE>(map.get(&"hash".into()))?;
+	let hash_algo = map.get(&"hash_algo".into());
+	let hash_algo = match hash_algo.is_undefined() {
+		true => HashAlgo::default(),
+		false => hash_algo
+			.as_f64()
+			.and_then(|algo| HashAlgo::try_from(algo as u8).ok())
+			.ok_or_else(|| JsValue::from("hash_algo is not a known digest"))?,
+	};
+	let in_reply_to = map.get(&"in_reply_to".into());
+	let in_reply_to = match in_reply_to.is_undefined() {
+		true => None,
+		false => Some(js_value_to_array::<KEY_SIZE>(in_reply_to)?),
+	};
+
+	let entry_count = map.get(&"entry_count".into());
+	let entry_count = match entry_count.is_undefined() {
+		true => None,
+		false => Some(
+			entry_count
+				.as_f64()
+				.ok_or_else(|| JsValue::from("entry_count is not a number"))? as u32,
+		),
+	};
+
+	let part_count = map.get(&"part_count".into());
+	let part_count = match part_count.is_undefined() {
+		true => None,
+		false => Some(
+			part_count
+				.as_f64()
+				.ok_or_else(|| JsValue::from("part_count is not a number"))? as u32,
+		),
+	};
+
+	let subject = map.get(&"subject".into());
+	let subject = match subject.is_undefined() {
+		true => None,
+		false => Some(js_value_to_array::<{ SUBJECT_SIZE + MAC_SIZE }>(subject)?),
+	};
+
+	let signature = map.get(&"signature".into());
+	let signature = match signature.is_undefined() {
+		true => None,
+		false => Some(js_value_to_array::<64>(signature)?),
+	};
 
-	let meta = MessageMetadata { nonce, broker, hash, channels };
+	let meta = MessageMetadata {
+		nonce,
+		broker,
+		hash,
+		hash_algo,
+		in_reply_to,
+		entry_count,
+		part_count,
+		subject,
+		signature,
+		channels,
+	};
 	Ok(meta)
 }
 
-fn message_from_js(map: Map) -> Result<Message, JsValue> {
+fn js_value_to_string(value: JsValue, name: &str) -> Result<String, JsValue> {
+	value
+		.as_string()
+		.ok_or_else(|| JsError::new(&format!("{} is not a string", name)).into())
+}
+
+/// A kind is a map with a `type` of `raw`, `file`, `binary`, `compressed` or `json`. A `file` also
+/// has a `filename` and a `mime`, a `compressed` has the original `kind`, which is not compressed
+/// itself
+fn kind_from_js(kind: JsValue) -> Result<MessageType, JsValue> {
+	let kind: Map = kind.dyn_into()?;
+	let kind = match js_value_to_string(kind.get(&"type".into()), "kind type")?.as_str() {
+		"raw" => MessageType::RawData,
+		"file" => MessageType::File {
+			filename: js_value_to_string(kind.get(&"filename".into()), "filename")?,
+			mime: js_value_to_string(kind.get(&"mime".into()), "mime")?,
+		},
+		"binary" => MessageType::Binary,
+		"compressed" => match kind_from_js(kind.get(&"kind".into()))? {
+			MessageType::Compressed(_) =>
+				return Err(JsError::new("compressed kinds can not be nested").into()),
+			inner => MessageType::Compressed(Box::new(inner)),
+		},
+		"json" => MessageType::Json,
+		other => return Err(JsError::new(&format!("unknown kind type {}", other)).into()),
+	};
+	Ok(kind)
+}
+
+fn kind_to_js(kind: &MessageType) -> Map {
+	let map = Map::new();
+	let kind_type = match kind {
+		MessageType::RawData => "raw",
+		MessageType::File { filename, mime } => {
+			map.set(&"filename".into(), &filename.into());
+			map.set(&"mime".into(), &mime.into());
+			"file"
+		},
+		MessageType::Binary => "binary",
+		MessageType::Compressed(kind) => {
+			map.set(&"kind".into(), &kind_to_js(kind));
+			"compressed"
+		},
+		MessageType::Json => "json",
+	};
+	map.set(&"type".into(), &kind_type.into());
+	map
+}
+
+fn entries_from_js(es: Array) -> Result<Vec<MessageEntry>, JsValue> {
 	let mut entries = vec![];
-	let es: Array = map.get(&"entries".into()).dyn_into()?;
 	for e in es.iter() {
 		let e: Map = e.dyn_into()?;
 		let key: Uint8Array = e.get(&"key".into()).dyn_into()?;
 		let value: Uint8Array = e.get(&"value".into()).dyn_into()?;
-		entries.push(MessageEntry {
-			key: key.to_vec(),
-			value: value.to_vec(),
-			kind: MessageType::RawData,
-		});
+		// kind is optional, raw data by default
+		let kind = e.get(&"kind".into());
+		let kind = match kind.is_undefined() {
+			true => MessageType::default(),
+			false => kind_from_js(kind)?,
+		};
+		entries.push(MessageEntry { key: key.to_vec(), value: value.to_vec(), kind });
 	}
-	Ok(Message { entries })
+	Ok(entries)
 }
 
-fn message_to_js(message: &Message) -> Result<Map, JsValue> {
-	let entries = Array::new();
-	for entry in &message.entries {
+fn message_from_js(map: Map) -> Result<Message, JsValue> {
+	let entries = entries_from_js(map.get(&"entries".into()).dyn_into()?)?;
+	// headers are optional
+	let headers = map.get(&"headers".into());
+	let headers = match headers.is_undefined() {
+		true => vec![],
+		false => entries_from_js(headers.dyn_into()?)?,
+	};
+	Ok(Message { entries, headers })
+}
+
+fn entries_to_js(entries: &[MessageEntry]) -> Array {
+	let es = Array::new();
+	for entry in entries {
 		let e = Map::new();
 		e.set(&"key".into(), &Uint8Array::from(entry.key.as_ref()));
 		e.set(&"value".into(), &Uint8Array::from(entry.value.as_ref()));
-		entries.push(&e);
+		e.set(&"kind".into(), &kind_to_js(&entry.kind));
+		es.push(&e);
 	}
+	es
+}
 
+fn message_to_js(message: &Message) -> Result<Map, JsValue> {
 	let map = Map::new();
-	map.set(&"entries".into(), &entries);
+	map.set(&"entries".into(), &entries_to_js(&message.entries));
+	map.set(&"headers".into(), &entries_to_js(&message.headers));
 	Ok(map)
 }
 
@@ -121,6 +268,8 @@ pub fn new_encrypted_metadata(
 	sender_pk: Uint8Array,
 	recipients: Array, // array of pubkeys
 	message: Map,
+	in_reply_to: Option<Uint8Array>,
+	prev_hash: Option<Uint8Array>,
 ) -> Result<Map, JsValue> {
 	utils::set_panic_hook();
 
@@ -135,6 +284,9 @@ pub fn new_encrypted_metadata(
 	let public_nonce = public_nonce.to_vec();
 	let public_nonce = Nonce::<SalsaBox>::from_slice(public_nonce.as_slice());
 	let sender_pk = PublicKey::from(js_value_to_array::<KEY_SIZE>(sender_pk.into())?);
+	let in_reply_to =
+		in_reply_to.map(|hash| js_value_to_array::<KEY_SIZE>(hash.into())).transpose()?;
+	let prev_hash = prev_hash.map(|hash| js_value_to_array::<KEY_SIZE>(hash.into())).transpose()?;
 
 	// SBP-M1 review: public nonce can be passed directly as the value is being dereferenced by the compiler immediately
 	let (meta, secret_nonce) = MessageMetadata::new_encrypted(
@@ -143,6 +295,8 @@ pub fn new_encrypted_metadata(
 		&sender_pk,
 		&reps.iter().collect::<Vec<_>>(),
 		&message_from_js(message)?,
+		in_reply_to,
+		prev_hash,
 	)
 	.map_err(|e| JsError::new(&format!("{}", e)))?;
 
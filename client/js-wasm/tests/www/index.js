@@ -10,6 +10,7 @@ let message = new Map();
 let entry = new Map();
 entry.set('key', enc.encode('data info'));
 entry.set('value', enc.encode('my data'));
+entry.set('kind', new Map([['type', 'file'], ['filename', 'data.txt'], ['mime', 'text/plain']]));
 message.set('entries', [entry]);
 
 let encrypted_metadata = wasm.new_encrypted_metadata(signer, nonce, sender.public, [receiver.public], message);
@@ -30,5 +31,7 @@ var dec = new TextDecoder("utf-8");
 
 console.assert(dec.decode(entry.get('key')), dec.decode(reciever_entry.get('key')));
 console.assert(dec.decode(entry.get('value')), dec.decode(reciever_entry.get('value')));
+console.assert(reciever_entry.get('kind').get('type') === 'file');
+console.assert(reciever_entry.get('kind').get('filename') === 'data.txt');
 
 console.log("tests passed");
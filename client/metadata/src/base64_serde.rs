@@ -0,0 +1,103 @@
+//! Serde helpers to represent bytes as base64 strings, use with `#[serde(with = "...")]`.
+
+use base64::{engine::general_purpose, Engine as _};
+use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+pub fn serialize<S>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error>
+where
+	S: Serializer,
+{
+	serializer.serialize_str(&general_purpose::STANDARD.encode(bytes))
+}
+
+pub fn deserialize<'a, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+where
+	D: Deserializer<'a>,
+{
+	String::deserialize(deserializer).and_then(|string| {
+		general_purpose::STANDARD
+			.decode(string)
+			.map_err(|err| Error::custom(err.to_string()))
+	})
+}
+
+/// Fixed size byte arrays
+pub mod array {
+	use super::*;
+
+	pub fn serialize<S, const N: usize>(bytes: &[u8; N], serializer: S) -> Result<S::Ok, S::Error>
+	where
+		S: Serializer,
+	{
+		super::serialize(bytes, serializer)
+	}
+
+	pub fn deserialize<'a, D, const N: usize>(deserializer: D) -> Result<[u8; N], D::Error>
+	where
+		D: Deserializer<'a>,
+	{
+		super::deserialize(deserializer)?.try_into().map_err(|bytes: Vec<u8>| {
+			Error::custom(format!("expected {} bytes, got {}", N, bytes.len()))
+		})
+	}
+}
+
+/// Optional fixed size byte arrays
+pub mod option {
+	use super::*;
+
+	pub fn serialize<S, const N: usize>(
+		bytes: &Option<[u8; N]>,
+		serializer: S,
+	) -> Result<S::Ok, S::Error>
+	where
+		S: Serializer,
+	{
+		match bytes {
+			Some(bytes) => serializer.serialize_some(&general_purpose::STANDARD.encode(bytes)),
+			None => serializer.serialize_none(),
+		}
+	}
+
+	pub fn deserialize<'a, D, const N: usize>(deserializer: D) -> Result<Option<[u8; N]>, D::Error>
+	where
+		D: Deserializer<'a>,
+	{
+		Option::<String>::deserialize(deserializer)?
+			.map(|string| {
+				let bytes = general_purpose::STANDARD
+					.decode(string)
+					.map_err(|err| Error::custom(err.to_string()))?;
+				bytes.try_into().map_err(|bytes: Vec<u8>| {
+					Error::custom(format!("expected {} bytes, got {}", N, bytes.len()))
+				})
+			})
+			.transpose()
+	}
+}
+
+/// Lists of byte vectors
+pub mod vec {
+	use super::*;
+
+	pub fn serialize<S>(items: &[Vec<u8>], serializer: S) -> Result<S::Ok, S::Error>
+	where
+		S: Serializer,
+	{
+		serializer.collect_seq(items.iter().map(|item| general_purpose::STANDARD.encode(item)))
+	}
+
+	pub fn deserialize<'a, D>(deserializer: D) -> Result<Vec<Vec<u8>>, D::Error>
+	where
+		D: Deserializer<'a>,
+	{
+		Vec::<String>::deserialize(deserializer)?
+			.into_iter()
+			.map(|string| {
+				general_purpose::STANDARD
+					.decode(string)
+					.map_err(|err| Error::custom(err.to_string()))
+			})
+			.collect()
+	}
+}
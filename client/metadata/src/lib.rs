@@ -1,15 +1,84 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 
+#[cfg(feature = "std")]
+mod base64_serde;
 mod messages;
 mod meta;
+mod parts;
+mod validation;
 
-pub use messages::{Message, MessageEntry, MessageType};
-pub use meta::{Channel, MessageMetadata};
+#[cfg(feature = "std")]
+pub use messages::{Ciphertext, MessageBuilder, Plaintext};
+pub use messages::{Message, MessageEntry, MessageType, ORIGIN_HEADER, SUBJECT_HEADER};
+pub use meta::{Channel, HashAlgo, MessageMetadata};
+#[cfg(feature = "std")]
+pub use meta::{EncryptionSession, Group, KeyRotation, MessageKeys, MetadataOptions};
 #[cfg(feature = "std")]
 pub use nolik_cypher::{BytesCypher, Cypher, CypherError, SalsaNonce};
+pub use parts::{join_parts, split_parts};
+pub use validation::{MetadataError, MetadataLimits};
 
 pub const KEY_SIZE: usize = 32;
 pub const NONCE_SIZE: usize = 24;
+/// Size of the SalsaBox authentication tag appended to every ciphertext
+pub const MAC_SIZE: usize = 16;
+/// Maximum number of recipients of a single message. Every party including the sender gets a
+/// channel, so it matches the `MaxChannels` of the runtime minus the sender's channel
+pub const MAX_RECIPIENTS: usize = 127;
+/// Maximum number of entries of a message built with `MessageBuilder`
+pub const MAX_ENTRIES: usize = 256;
+/// Maximum size of a compressed entry value once decompressed, larger values are rejected on
+/// decryption so a small message can't expand into gigabytes
+pub const MAX_DECOMPRESSED_SIZE: usize = 16 * 1024 * 1024;
+/// Size of the padded subject blinded in metadata, one byte of it holds the subject length
+pub const SUBJECT_SIZE: usize = 32;
+
+/// Curve25519 points of a small order, a Diffie-Hellman with any of them results in a predictable
+/// shared secret. Listed with the most significant bit cleared, since X25519 ignores it
+const LOW_ORDER_KEYS: [[u8; KEY_SIZE]; 7] = [
+	[
+		0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+		0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+		0x00, 0x00,
+	],
+	[
+		0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+		0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+		0x00, 0x00,
+	],
+	[
+		0xe0, 0xeb, 0x7a, 0x7c, 0x3b, 0x41, 0xb8, 0xae, 0x16, 0x56, 0xe3, 0xfa, 0xf1, 0x9f, 0xc4,
+		0x6a, 0xda, 0x09, 0x8d, 0xeb, 0x9c, 0x32, 0xb1, 0xfd, 0x86, 0x62, 0x05, 0x16, 0x5f, 0x49,
+		0xb8, 0x00,
+	],
+	[
+		0x5f, 0x9c, 0x95, 0xbc, 0xa3, 0x50, 0x8c, 0x24, 0xb1, 0xd0, 0xb1, 0x55, 0x9c, 0x83, 0xef,
+		0x5b, 0x04, 0x44, 0x5c, 0xc4, 0x58, 0x1c, 0x8e, 0x86, 0xd8, 0x22, 0x4e, 0xdd, 0xd0, 0x9f,
+		0x11, 0x57,
+	],
+	[
+		0xec, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+		0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+		0xff, 0x7f,
+	],
+	[
+		0xed, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+		0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+		0xff, 0x7f,
+	],
+	[
+		0xee, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+		0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+		0xff, 0x7f,
+	],
+];
+
+/// Check if the public `key` is a low order point, like the all-zero key
+pub fn is_low_order_key(key: &[u8; KEY_SIZE]) -> bool {
+	let mut key = *key;
+	key[KEY_SIZE - 1] &= 0x7f;
+	LOW_ORDER_KEYS.contains(&key)
+}
 
 #[cfg(feature = "std")]
 pub enum MessageAction {
@@ -20,7 +89,7 @@ pub enum MessageAction {
 #[cfg(feature = "ffi")]
 mod ffi {
 	use super::*;
-	use crate::{Message, MessageMetadata};
+	use crate::{messages::Redacted, Message, MessageMetadata};
 	use crypto_box::{
 		aead::{AeadCore, OsRng},
 		PublicKey, SalsaBox, SecretKey,
@@ -41,15 +110,32 @@ mod ffi {
 		pub sender_pk: [u8; KEY_SIZE],
 		pub recipients: Vec<[u8; KEY_SIZE]>,
 		pub message: Message,
+		#[serde(default)]
+		pub in_reply_to: Option<[u8; KEY_SIZE]>,
+		#[serde(default)]
+		pub prev_hash: Option<[u8; KEY_SIZE]>,
+		#[serde(default)]
+		pub hash_algo: HashAlgo,
 	}
 
-	#[derive(Serialize, Deserialize, Debug, Default)]
+	#[derive(Serialize, Deserialize, Default)]
 	struct MetadataEncryptReturn {
 		pub metadata: MessageMetadata,
 		pub secret_nonce: [u8; NONCE_SIZE],
 		pub error: String,
 	}
 
+	/// The secret nonce decrypts the message, so it's redacted like message entries
+	impl std::fmt::Debug for MetadataEncryptReturn {
+		fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+			f.debug_struct("MetadataEncryptReturn")
+				.field("metadata", &self.metadata)
+				.field("secret_nonce", &Redacted(&self.secret_nonce))
+				.field("error", &self.error)
+				.finish()
+		}
+	}
+
 	#[derive(Serialize, Deserialize, Debug)]
 	struct MetadataDecryptParams {
 		pub metadata: MessageMetadata,
@@ -118,16 +204,25 @@ mod ffi {
 	#[no_mangle]
 	pub extern "C" fn new_encrypted_metadata(input: *mut c_char) -> *mut c_char {
 		let input = ptr_to_bytes(input);
-		let MetadataEncryptParams { origin, public_nonce, sender_pk, recipients, message } =
-			unwrap_or_return! {serde_json::from_slice(input), MetadataEncryptReturn};
+		let MetadataEncryptParams {
+			origin,
+			public_nonce,
+			sender_pk,
+			recipients,
+			message,
+			in_reply_to,
+			prev_hash,
+			hash_algo,
+		} = unwrap_or_return! {serde_json::from_slice(input), MetadataEncryptReturn};
 		let recipients: Vec<_> = recipients.iter().map(|pk| PublicKey::from(*pk)).collect();
 
-		let (metadata, secret_nonce) = unwrap_or_return! {MessageMetadata::new_encrypted(
+		let (metadata, secret_nonce) = unwrap_or_return! {MessageMetadata::new_encrypted_with(
 			&PublicKey::from(origin),
 			&SalsaNonce::from_slice(&public_nonce),
 			&PublicKey::from(sender_pk),
 			recipients.iter().collect::<Vec<_>>().as_slice(),
 			&message,
+			MetadataOptions { hash_algo, in_reply_to, prev_hash },
 		) , MetadataEncryptReturn};
 
 		let secret_nonce = secret_nonce.to_vec().try_into().map_err(|_| "nonce size is not valid");
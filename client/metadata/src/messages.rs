@@ -4,77 +4,565 @@
 #[cfg(feature = "std")]
 use base64::{engine::general_purpose, Engine as _};
 #[cfg(feature = "std")]
+use blake2::{Blake2s256, Digest};
+#[cfg(feature = "std")]
 use crypto_box::{PublicKey, SecretKey};
 #[cfg(feature = "std")]
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+#[cfg(feature = "std")]
 use nolik_cypher::{BytesCypher, Cypher, CypherError, SalsaNonce};
 #[cfg(feature = "std")]
-use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "std")]
+use std::{
+	borrow::Cow,
+	io::{Read, Write},
+};
+#[cfg(feature = "std")]
+use zeroize::Zeroize;
 
-use codec::{Decode, Encode};
-use scale_info::prelude::vec::Vec;
+#[cfg(feature = "std")]
+use crate::{KEY_SIZE, MAX_DECOMPRESSED_SIZE, MAX_ENTRIES};
+use codec::{Decode, Encode, Input};
+use core::fmt;
+use scale_info::prelude::{boxed::Box, string::String, vec::Vec};
+
+/// Shows the length and a short hash of secret bytes instead of the content, so the output can
+/// be compared in logs without leaking a plaintext
+pub(crate) struct Redacted<'a>(pub &'a [u8]);
+
+impl fmt::Debug for Redacted<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let mut debug = f.debug_struct("Redacted");
+		debug.field("len", &self.0.len());
+		#[cfg(feature = "std")]
+		{
+			let digest = Blake2s256::digest(self.0);
+			let hash = u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]);
+			debug.field("hash", &format_args!("{:08x}", hash));
+		}
+		debug.finish()
+	}
+}
 
 #[allow(dead_code)]
-#[derive(Debug, Encode, Decode, Clone, PartialEq, Default)]
+#[derive(Encode, Clone, PartialEq, Default)]
 #[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
 pub enum MessageType {
 	#[default]
 	RawData,
-	File,
+	/// File attachment, the entry value holds the file contents
+	File { filename: String, mime: String },
+	/// Arbitrary binary data
+	Binary,
+	/// The entry value is deflate-compressed, the original kind is kept inside. The original
+	/// kind is never compressed itself, nested kinds are rejected
+	Compressed(Box<MessageType>),
+	/// JSON data, canonicalized with sorted object keys and no whitespace before it is hashed
+	/// and encrypted, so semantically equal values get the same root hash
+	Json,
+}
+
+/// The same layout as a derived `Decode`, except that a compressed kind may only hold a kind that
+/// is not compressed. Kinds travel in clear, so unbounded nesting would let a message overflow
+/// the stack of a recipient decoding it
+impl Decode for MessageType {
+	fn decode<I: Input>(input: &mut I) -> Result<Self, codec::Error> {
+		Self::decode_kind(input, true)
+	}
+}
+
+impl MessageType {
+	fn decode_kind<I: Input>(input: &mut I, top_level: bool) -> Result<Self, codec::Error> {
+		match input.read_byte()? {
+			0 => Ok(MessageType::RawData),
+			1 => Ok(MessageType::File {
+				filename: String::decode(input)?,
+				mime: String::decode(input)?,
+			}),
+			2 => Ok(MessageType::Binary),
+			3 if top_level =>
+				Ok(MessageType::Compressed(Box::new(Self::decode_kind(input, false)?))),
+			3 => Err("Compressed message kind is nested".into()),
+			4 => Ok(MessageType::Json),
+			_ => Err("Invalid message kind index".into()),
+		}
+	}
+}
+
+/// File attributes are redacted, like entry values
+impl fmt::Debug for MessageType {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			MessageType::RawData => f.write_str("RawData"),
+			MessageType::File { filename, mime } => f
+				.debug_struct("File")
+				.field("filename", &Redacted(filename.as_bytes()))
+				.field("mime", &Redacted(mime.as_bytes()))
+				.finish(),
+			MessageType::Binary => f.write_str("Binary"),
+			MessageType::Compressed(kind) => f.debug_tuple("Compressed").field(kind).finish(),
+			MessageType::Json => f.write_str("Json"),
+		}
+	}
 }
 
+/// Domain separation of nonces derived from the message nonce from other uses of it
+#[cfg(feature = "std")]
+const NONCE_DOMAIN: &[u8] = b"nolik/message-nonce/v1";
+
+/// Derive a nonce from the `nonce` for a single field named by the `tag`, bound to the `data`,
+/// e.g. an entry index. XSalsa20-Poly1305 leaks the XOR of plaintexts encrypted with the same
+/// nonce under a key pair, so every field of every entry is encrypted with its own nonce
+#[cfg(feature = "std")]
+fn derive_nonce(nonce: &SalsaNonce, tag: &[u8], data: &[u8]) -> SalsaNonce {
+	let digest = Blake2s256::new()
+		.chain_update(NONCE_DOMAIN)
+		.chain_update(nonce)
+		.chain_update([tag.len() as u8])
+		.chain_update(tag)
+		.chain_update(data)
+		.finalize();
+	*SalsaNonce::from_slice(&digest[..nonce.len()])
+}
+
+/// The kind is kept along with an encrypted entry, so the file attributes are encrypted and
+/// stored as base64 strings
 #[cfg(feature = "std")]
 impl Cypher for MessageType {
-	fn encrypt(&self, _: &SalsaNonce, _: &PublicKey, _: &SecretKey) -> Result<Self, CypherError> {
-		Ok(self.clone())
+	fn encrypt(
+		&self,
+		nonce: &SalsaNonce,
+		pk: &PublicKey,
+		sk: &SecretKey,
+	) -> Result<Self, CypherError> {
+		let encrypt = |attr: &String, tag: &[u8]| -> Result<String, CypherError> {
+			let encrypted = attr.as_bytes().encrypt(&derive_nonce(nonce, tag, &[]), pk, sk)?;
+			Ok(general_purpose::STANDARD.encode(encrypted))
+		};
+
+		match self {
+			MessageType::File { filename, mime } => Ok(MessageType::File {
+				filename: encrypt(filename, b"filename")?,
+				mime: encrypt(mime, b"mime")?,
+			}),
+			MessageType::Compressed(kind) if matches!(**kind, MessageType::Compressed(_)) =>
+				Err(CypherError::EncryptionFailed(pk.clone())),
+			MessageType::Compressed(kind) =>
+				Ok(MessageType::Compressed(Box::new(kind.encrypt(nonce, pk, sk)?))),
+			_ => Ok(self.clone()),
+		}
 	}
 
-	fn decrypt(&self, _: &SalsaNonce, _: &PublicKey, _: &SecretKey) -> Result<Self, CypherError> {
-		Ok(self.clone())
+	fn decrypt(
+		&self,
+		nonce: &SalsaNonce,
+		pk: &PublicKey,
+		sk: &SecretKey,
+	) -> Result<Self, CypherError> {
+		let decrypt = |attr: &String, tag: &[u8]| -> Result<String, CypherError> {
+			let encrypted = general_purpose::STANDARD
+				.decode(attr)
+				.map_err(|_| CypherError::DecryptionFailed(pk.clone()))?;
+			let decrypted = encrypted.decrypt(&derive_nonce(nonce, tag, &[]), pk, sk)?;
+			String::from_utf8(decrypted).map_err(|_| CypherError::DecryptionFailed(pk.clone()))
+		};
+
+		match self {
+			MessageType::File { filename, mime } => Ok(MessageType::File {
+				filename: decrypt(filename, b"filename")?,
+				mime: decrypt(mime, b"mime")?,
+			}),
+			MessageType::Compressed(kind) if matches!(**kind, MessageType::Compressed(_)) =>
+				Err(CypherError::DecryptionFailed(pk.clone())),
+			MessageType::Compressed(kind) =>
+				Ok(MessageType::Compressed(Box::new(kind.decrypt(nonce, pk, sk)?))),
+			_ => Ok(self.clone()),
+		}
 	}
 }
 
-#[cfg_attr(feature = "std", derive(Cypher, Serialize, Deserialize))]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
 #[derive(Debug, Encode, Decode, Clone, Default, PartialEq)]
 pub struct Message {
 	pub entries: Vec<MessageEntry>,
+	/// Typed headers like subject, timestamp or content type, encrypted the same way as entries
+	/// but kept apart from the message body
+	#[cfg_attr(feature = "std", serde(default))]
+	pub headers: Vec<MessageEntry>,
 }
 
-#[cfg_attr(feature = "std", derive(Cypher, Serialize, Deserialize))]
-#[derive(Debug, Encode, Decode, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[derive(Encode, Decode, Clone, Default, PartialEq)]
 pub struct MessageEntry {
-	#[cfg_attr(
-		feature = "std",
-		serde(serialize_with = "as_base64", deserialize_with = "from_base64")
-	)]
+	#[cfg_attr(feature = "std", serde(with = "crate::base64_serde"))]
 	pub key: Vec<u8>,
-	#[cfg_attr(
-		feature = "std",
-		serde(serialize_with = "as_base64", deserialize_with = "from_base64")
-	)]
+	#[cfg_attr(feature = "std", serde(with = "crate::base64_serde"))]
 	pub value: Vec<u8>,
 	pub kind: MessageType,
 }
 
+/// Entries may hold a decrypted plaintext, so only lengths and hashes of the bytes are shown
+impl fmt::Debug for MessageEntry {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_struct("MessageEntry")
+			.field("key", &Redacted(&self.key))
+			.field("value", &Redacted(&self.value))
+			.field("kind", &self.kind)
+			.finish()
+	}
+}
+
+/// Key of the header holding the origin of a sealed message, since the chain doesn't know the
+/// sender of a sealed message
+pub const ORIGIN_HEADER: &[u8] = b"origin";
+
+/// Key of the header holding the subject of a message, blinded in metadata for a preview
+pub const SUBJECT_HEADER: &[u8] = b"subject";
+
+#[cfg(feature = "std")]
+impl Message {
+	/// Put the `origin` to the headers, so recipients of a sealed message are able to attribute it.
+	/// Should be called before the metadata root hash is computed, so the origin is covered by it
+	pub fn seal_origin(&mut self, origin: &PublicKey) {
+		self.headers.retain(|header| header.key != ORIGIN_HEADER);
+		self.headers.push(MessageEntry {
+			key: ORIGIN_HEADER.to_vec(),
+			value: origin.as_bytes().to_vec(),
+			kind: MessageType::Binary,
+		});
+	}
+
+	/// Origin of a sealed message put by `seal_origin`, `None` if the message is not sealed
+	pub fn sealed_origin(&self) -> Option<PublicKey> {
+		let header = self.headers.iter().find(|header| header.key == ORIGIN_HEADER)?;
+		<[u8; KEY_SIZE]>::try_from(header.value.as_slice()).ok().map(PublicKey::from)
+	}
+
+	/// Value of the `subject` header, `None` if the message has no subject
+	pub fn subject(&self) -> Option<&[u8]> {
+		let header = self.headers.iter().find(|header| header.key == SUBJECT_HEADER)?;
+		Some(&header.value)
+	}
+
+	/// Compress values of all entries, should be called after the metadata root hash is computed
+	/// since the hash covers the original values. Headers are left as is
+	pub fn compress(&self) -> std::io::Result<Self> {
+		let entries = self.entries.iter().map(MessageEntry::compress).collect::<Result<_, _>>()?;
+		Ok(Message { entries, headers: self.headers.clone() })
+	}
+}
+
+/// Entries and headers are counted together, a message with more than `MAX_ENTRIES` of them is
+/// neither encrypted nor decrypted, so its size and the root hash computation time are bounded
+#[cfg(feature = "std")]
+fn check_entry_count(count: usize) -> Result<(), CypherError> {
+	if count > MAX_ENTRIES {
+		return Err(CypherError::TooManyEntries { got: count, max: MAX_ENTRIES })
+	}
+	Ok(())
+}
+
+/// Apply `f` to each entry with a nonce of its own, derived from the message `nonce`, the `tag`
+/// of the section and the index of the entry
+#[cfg(feature = "std")]
+fn map_entries(
+	entries: &[MessageEntry],
+	nonce: &SalsaNonce,
+	tag: &[u8],
+	f: impl Fn(&MessageEntry, &SalsaNonce) -> Result<MessageEntry, CypherError>,
+) -> Result<Vec<MessageEntry>, CypherError> {
+	entries
+		.iter()
+		.enumerate()
+		.map(|(index, entry)| f(entry, &derive_nonce(nonce, tag, &(index as u32).to_le_bytes())))
+		.collect()
+}
+
+/// Entries and headers are encrypted with nonces derived for each of them, so no nonce is used
+/// twice within a message
+#[cfg(feature = "std")]
+impl Cypher for Message {
+	fn encrypt(
+		&self,
+		nonce: &SalsaNonce,
+		pk: &PublicKey,
+		sk: &SecretKey,
+	) -> Result<Self, CypherError> {
+		check_entry_count(self.entries.len() + self.headers.len())?;
+		let encrypt = |entry: &MessageEntry, nonce: &SalsaNonce| entry.encrypt(nonce, pk, sk);
+		Ok(Message {
+			entries: map_entries(&self.entries, nonce, b"entry", encrypt)?,
+			headers: map_entries(&self.headers, nonce, b"header", encrypt)?,
+		})
+	}
+
+	fn decrypt(
+		&self,
+		nonce: &SalsaNonce,
+		pk: &PublicKey,
+		sk: &SecretKey,
+	) -> Result<Self, CypherError> {
+		check_entry_count(self.entries.len() + self.headers.len())?;
+		let decrypt = |entry: &MessageEntry, nonce: &SalsaNonce| entry.decrypt(nonce, pk, sk);
+		Ok(Message {
+			entries: map_entries(&self.entries, nonce, b"entry", decrypt)?,
+			headers: map_entries(&self.headers, nonce, b"header", decrypt)?,
+		})
+	}
+}
+
+/// Builds a `Message` checked the same way the pallet checks it, so a malformed message is
+/// caught before it is encrypted and sent
+#[cfg(feature = "std")]
+#[derive(Default)]
+pub struct MessageBuilder {
+	entries: Vec<MessageEntry>,
+	headers: Vec<MessageEntry>,
+}
+
+#[cfg(feature = "std")]
+impl MessageBuilder {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Add an entry of the message body
+	pub fn entry(mut self, key: impl Into<Vec<u8>>, value: impl Into<Vec<u8>>) -> Self {
+		self.entries.push(MessageEntry {
+			key: key.into(),
+			value: value.into(),
+			kind: MessageType::default(),
+		});
+		self
+	}
+
+	/// Add an entry of the given `kind`, e.g. a file attachment
+	pub fn typed_entry(
+		mut self,
+		key: impl Into<Vec<u8>>,
+		value: impl Into<Vec<u8>>,
+		kind: MessageType,
+	) -> Self {
+		self.entries.push(MessageEntry { key: key.into(), value: value.into(), kind });
+		self
+	}
+
+	/// Add a header, see `Message::headers`
+	pub fn header(mut self, key: impl Into<Vec<u8>>, value: impl Into<Vec<u8>>) -> Self {
+		self.headers.push(MessageEntry {
+			key: key.into(),
+			value: value.into(),
+			kind: MessageType::default(),
+		});
+		self
+	}
+
+	/// Check the entries and build the message. The message must have at least one and at most
+	/// `MAX_ENTRIES` entries, none of them with an empty key or value. Headers are counted as
+	/// entries
+	pub fn build(self) -> Result<Message, CypherError> {
+		if self.entries.is_empty() {
+			return Err(CypherError::EmptyMessage)
+		}
+
+		check_entry_count(self.entries.len() + self.headers.len())?;
+
+		if let Some(index) = self
+			.entries
+			.iter()
+			.chain(&self.headers)
+			.position(|entry| entry.key.is_empty() || entry.value.is_empty())
+		{
+			return Err(CypherError::EmptyEntry(index))
+		}
+
+		Ok(Message { entries: self.entries, headers: self.headers })
+	}
+}
+
+#[cfg(feature = "std")]
+impl MessageEntry {
+	/// The value as it is hashed and encrypted. JSON values are canonicalized, other values are
+	/// kept as is
+	pub fn canonical_value(&self) -> Result<Cow<'_, [u8]>, CypherError> {
+		match self.kind {
+			MessageType::Json => {
+				let value: serde_json::Value =
+					serde_json::from_slice(&self.value).map_err(|_| CypherError::InvalidJson)?;
+				Ok(Cow::Owned(canonical_json(value).to_string().into_bytes()))
+			},
+			_ => Ok(Cow::Borrowed(&self.value)),
+		}
+	}
+
+	/// The canonical value, or the value as is if it's not valid JSON. A message with invalid
+	/// JSON can't be encrypted anyway, so the fallback only keeps the root hash infallible
+	pub(crate) fn hashed_value(&self) -> Cow<'_, [u8]> {
+		self.canonical_value().unwrap_or(Cow::Borrowed(&self.value))
+	}
+
+	/// Deflate the entry value, the entry is decompressed back on decryption. Values larger than
+	/// `MAX_DECOMPRESSED_SIZE` are rejected, a recipient wouldn't decompress them
+	pub fn compress(&self) -> std::io::Result<Self> {
+		if let MessageType::Compressed(_) = self.kind {
+			return Ok(self.clone())
+		}
+		if self.value.len() > MAX_DECOMPRESSED_SIZE {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::InvalidInput,
+				"entry value exceeds MAX_DECOMPRESSED_SIZE",
+			))
+		}
+
+		let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+		encoder.write_all(&self.value)?;
+		Ok(MessageEntry {
+			key: self.key.clone(),
+			value: encoder.finish()?,
+			kind: MessageType::Compressed(Box::new(self.kind.clone())),
+		})
+	}
+}
+
+/// Sort object keys recursively, `serde_json` keeps the insertion order if its `preserve_order`
+/// feature is enabled by any crate in a build
+#[cfg(feature = "std")]
+fn canonical_json(value: serde_json::Value) -> serde_json::Value {
+	use serde_json::Value;
+
+	match value {
+		Value::Object(map) => {
+			let mut fields: Vec<_> = map.into_iter().collect();
+			fields.sort_by(|(a, _), (b, _)| a.cmp(b));
+			Value::Object(
+				fields.into_iter().map(|(key, value)| (key, canonical_json(value))).collect(),
+			)
+		},
+		Value::Array(values) => Value::Array(values.into_iter().map(canonical_json).collect()),
+		value => value,
+	}
+}
+
+/// Nonce to encrypt an entry value, derived from the nonce of the entry and the encrypted key.
+/// SalsaBox doesn't support associated data, so the derived nonce binds the value to its key.
+/// The entry nonce is bound to the index of the entry, so entries with equal keys get distinct
+/// value nonces
+#[cfg(feature = "std")]
+fn value_nonce(nonce: &SalsaNonce, encrypted_key: &[u8]) -> SalsaNonce {
+	derive_nonce(nonce, b"value", encrypted_key)
+}
+
+/// Compressed entries are decompressed after decryption, so a recipient gets the original
+/// entry back, unless it exceeds `MAX_DECOMPRESSED_SIZE`. The key, the value and the kind are
+/// encrypted with nonces derived from the `nonce` of the entry
+#[cfg(feature = "std")]
+impl Cypher for MessageEntry {
+	fn encrypt(
+		&self,
+		nonce: &SalsaNonce,
+		pk: &PublicKey,
+		sk: &SecretKey,
+	) -> Result<Self, CypherError> {
+		let key = self.key.encrypt(&derive_nonce(nonce, b"key", &[]), pk, sk)?;
+		Ok(MessageEntry {
+			value: self.canonical_value()?.encrypt(&value_nonce(nonce, &key), pk, sk)?,
+			key,
+			kind: self.kind.encrypt(nonce, pk, sk)?,
+		})
+	}
+
+	fn decrypt(
+		&self,
+		nonce: &SalsaNonce,
+		pk: &PublicKey,
+		sk: &SecretKey,
+	) -> Result<Self, CypherError> {
+		let key = self.key.decrypt(&derive_nonce(nonce, b"key", &[]), pk, sk)?;
+		let value = self.value.decrypt(&value_nonce(nonce, &self.key), pk, sk)?;
+		match self.kind.decrypt(nonce, pk, sk)? {
+			MessageType::Compressed(kind) => {
+				let mut decompressed = Vec::new();
+				// one byte over the limit tells a value of exactly the limit from a larger one
+				DeflateDecoder::new(value.as_slice())
+					.take(MAX_DECOMPRESSED_SIZE as u64 + 1)
+					.read_to_end(&mut decompressed)
+					.map_err(|_| CypherError::DecryptionFailed(pk.clone()))?;
+				if decompressed.len() > MAX_DECOMPRESSED_SIZE {
+					return Err(CypherError::DecompressedTooLarge { max: MAX_DECOMPRESSED_SIZE })
+				}
+				Ok(MessageEntry { key, value: decompressed, kind: *kind })
+			},
+			kind => Ok(MessageEntry { key, value, kind }),
+		}
+	}
+}
+
+/// Allows to clear a decrypted message with `zeroize::Zeroizing`
 #[cfg(feature = "std")]
-fn as_base64<S>(key: &[u8], serializer: S) -> Result<S::Ok, S::Error>
-where
-	S: Serializer,
-{
-	serializer.serialize_str(&general_purpose::STANDARD.encode(key))
+impl Zeroize for Message {
+	fn zeroize(&mut self) {
+		self.entries.iter_mut().for_each(Zeroize::zeroize);
+		self.headers.iter_mut().for_each(Zeroize::zeroize);
+	}
 }
 
 #[cfg(feature = "std")]
-fn from_base64<'a, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
-where
-	D: Deserializer<'a>,
-{
-	use serde::de::Error;
-	// SBP-M1 review: no need to borrow, direct string can be passed in decode function
-	String::deserialize(deserializer).and_then(|string| {
-		general_purpose::STANDARD
-			.decode(&string)
-			.map_err(|err| Error::custom(err.to_string()))
-	})
+impl Zeroize for MessageEntry {
+	fn zeroize(&mut self) {
+		self.key.zeroize();
+		self.value.zeroize();
+	}
+}
+
+/// A message with plaintext entries, only encrypting it yields a `Ciphertext`. It is not
+/// encodable, so it can't be stored off-chain by mistake
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Plaintext(pub Message);
+
+/// A message with encrypted entries, as stored off-chain. Only a ciphertext is accepted where
+/// an encrypted message is expected:
+///
+/// ```compile_fail
+/// use nolik_metadata::{Ciphertext, Message, Plaintext};
+///
+/// fn store(_message: &Ciphertext) {}
+/// store(&Plaintext(Message::default()));
+/// ```
+#[cfg(feature = "std")]
+#[derive(Debug, Encode, Decode, Clone, Default, PartialEq)]
+pub struct Ciphertext(pub Message);
+
+#[cfg(feature = "std")]
+impl Plaintext {
+	pub fn encrypt(
+		&self,
+		nonce: &SalsaNonce,
+		pk: &PublicKey,
+		sk: &SecretKey,
+	) -> Result<Ciphertext, CypherError> {
+		self.0.encrypt(nonce, pk, sk).map(Ciphertext)
+	}
+}
+
+#[cfg(feature = "std")]
+impl Ciphertext {
+	pub fn decrypt(
+		&self,
+		nonce: &SalsaNonce,
+		pk: &PublicKey,
+		sk: &SecretKey,
+	) -> Result<Plaintext, CypherError> {
+		self.0.decrypt(nonce, pk, sk).map(Plaintext)
+	}
+}
+
+#[cfg(feature = "std")]
+impl Zeroize for Plaintext {
+	fn zeroize(&mut self) {
+		self.0.zeroize();
+	}
 }
 
 #[cfg(feature = "std")]
@@ -103,6 +591,7 @@ mod tests {
 				value: "value".into(),
 				kind: MessageType::default(),
 			}],
+			headers: vec![],
 		};
 
 		let encrypted_message = message.encrypt(&nonce, &receiver_pk, &sender_sk).unwrap();
@@ -112,4 +601,427 @@ mod tests {
 
 		assert_eq!(message, decrypted_message);
 	}
+
+	#[test]
+	fn encrypt_decrypt_typed_message() {
+		let sender_sk = SecretKey::generate(&mut OsRng);
+		let receiver_sk = SecretKey::generate(&mut OsRng);
+		let nonce = SalsaBox::generate_nonce(&mut OsRng);
+		let plaintext = Plaintext(Message {
+			entries: vec![MessageEntry {
+				key: "key".into(),
+				value: "value".into(),
+				kind: MessageType::default(),
+			}],
+			headers: vec![],
+		});
+
+		let ciphertext = plaintext.encrypt(&nonce, &receiver_sk.public_key(), &sender_sk).unwrap();
+		assert_ne!(ciphertext.0, plaintext.0);
+		// stored the same way as an untyped encrypted message
+		let untyped = plaintext.0.encrypt(&nonce, &receiver_sk.public_key(), &sender_sk).unwrap();
+		assert_eq!(ciphertext.encode(), untyped.encode());
+
+		let decoded = Ciphertext::decode(&mut &ciphertext.encode()[..]).unwrap();
+		let decrypted = decoded.decrypt(&nonce, &sender_sk.public_key(), &receiver_sk).unwrap();
+		assert_eq!(decrypted, plaintext);
+	}
+
+	#[test]
+	fn encrypt_decrypt_zeroizing_message() {
+		use zeroize::Zeroizing;
+
+		let sender_sk = SecretKey::generate(&mut OsRng);
+		let receiver_sk = SecretKey::generate(&mut OsRng);
+		let nonce = SalsaBox::generate_nonce(&mut OsRng);
+
+		let message = Zeroizing::new(Message {
+			entries: vec![MessageEntry {
+				key: "key".into(),
+				value: "value".into(),
+				kind: MessageType::default(),
+			}],
+			headers: vec![],
+		});
+
+		let encrypted_message =
+			message.encrypt(&nonce, &receiver_sk.public_key(), &sender_sk).unwrap();
+		let decrypted_message = Zeroizing::new(
+			encrypted_message
+				.decrypt(&nonce, &sender_sk.public_key(), &receiver_sk)
+				.unwrap(),
+		);
+		assert_eq!(*message, *decrypted_message);
+
+		let mut cleared = (*decrypted_message).clone();
+		cleared.zeroize();
+		assert!(cleared.entries[0].key.is_empty() && cleared.entries[0].value.is_empty());
+	}
+	#[test]
+	fn encrypt_decrypt_file_entry() {
+		let sender_sk = SecretKey::generate(&mut OsRng);
+		let receiver_sk = SecretKey::generate(&mut OsRng);
+		let nonce = SalsaBox::generate_nonce(&mut OsRng);
+
+		let kind = MessageType::File { filename: "notes.txt".into(), mime: "text/plain".into() };
+		let message = Message {
+			entries: vec![MessageEntry {
+				key: "notes.txt".into(),
+				value: "file contents".into(),
+				kind: kind.clone(),
+			}],
+			headers: vec![],
+		};
+
+		let encrypted_message =
+			message.encrypt(&nonce, &receiver_sk.public_key(), &sender_sk).unwrap();
+		let MessageType::File { filename, mime } = &encrypted_message.entries[0].kind else {
+			panic!("entry kind is not preserved");
+		};
+		assert_ne!(filename, "notes.txt");
+		assert_ne!(mime, "text/plain");
+
+		let decrypted_message = encrypted_message
+			.decrypt(&nonce, &sender_sk.public_key(), &receiver_sk)
+			.expect("could not decrypt a test message");
+		assert_eq!(decrypted_message.entries[0].kind, kind);
+		assert_eq!(message, decrypted_message);
+	}
+	#[test]
+	fn fields_are_encrypted_with_distinct_nonces() {
+		let sender_sk = SecretKey::generate(&mut OsRng);
+		let receiver_sk = SecretKey::generate(&mut OsRng);
+		let nonce = SalsaBox::generate_nonce(&mut OsRng);
+
+		// the CLI names a file entry after the file
+		let kind = MessageType::File { filename: "notes.txt".into(), mime: "notes.txt".into() };
+		let entry = MessageEntry { key: "notes.txt".into(), value: "notes.txt".into(), kind };
+		let message = Message { entries: vec![entry.clone(), entry.clone()], headers: vec![entry] };
+
+		let encrypted = message.encrypt(&nonce, &receiver_sk.public_key(), &sender_sk).unwrap();
+		let mut ciphertexts = vec![];
+		for entry in encrypted.entries.iter().chain(&encrypted.headers) {
+			let MessageType::File { filename, mime } = &entry.kind else {
+				panic!("entry kind is not preserved");
+			};
+			let decode = |attr| general_purpose::STANDARD.decode(attr).unwrap();
+			ciphertexts.extend([entry.key.clone(), entry.value.clone()]);
+			ciphertexts.extend([decode(filename), decode(mime)]);
+		}
+		// equal plaintexts give distinct ciphertexts, so no nonce is reused
+		for (i, a) in ciphertexts.iter().enumerate() {
+			assert!(ciphertexts[i + 1..].iter().all(|b| a != b));
+		}
+
+		let decrypted = encrypted.decrypt(&nonce, &sender_sk.public_key(), &receiver_sk).unwrap();
+		assert_eq!(decrypted, message);
+	}
+	#[test]
+	fn encrypt_decrypt_compressed_message() {
+		let sender_sk = SecretKey::generate(&mut OsRng);
+		let receiver_sk = SecretKey::generate(&mut OsRng);
+		let nonce = SalsaBox::generate_nonce(&mut OsRng);
+
+		let value = r#"{"level":"info","msg":"block imported"}"#.repeat(300);
+		let message = Message {
+			entries: vec![MessageEntry {
+				key: "log".into(),
+				value: value.as_bytes()[..10 * 1024].into(),
+				kind: MessageType::default(),
+			}],
+			headers: vec![],
+		};
+
+		let compressed_message = message.compress().unwrap();
+		assert_eq!(compressed_message.entries[0].kind, MessageType::Compressed(Box::default()));
+
+		let encrypted_message =
+			message.encrypt(&nonce, &receiver_sk.public_key(), &sender_sk).unwrap();
+		let encrypted_compressed_message = compressed_message
+			.encrypt(&nonce, &receiver_sk.public_key(), &sender_sk)
+			.unwrap();
+		assert!(
+			encrypted_compressed_message.entries[0].value.len() * 10 <
+				encrypted_message.entries[0].value.len()
+		);
+
+		let decrypted_message = encrypted_compressed_message
+			.decrypt(&nonce, &sender_sk.public_key(), &receiver_sk)
+			.expect("could not decrypt a test message");
+		assert_eq!(message, decrypted_message);
+	}
+	#[test]
+	fn compressed_and_uncompressed_entries() {
+		let sender_sk = SecretKey::generate(&mut OsRng);
+		let receiver_sk = SecretKey::generate(&mut OsRng);
+		let nonce = SalsaBox::generate_nonce(&mut OsRng);
+
+		let entry = |key: &str, value: Vec<u8>| MessageEntry {
+			key: key.into(),
+			value,
+			kind: MessageType::Binary,
+		};
+		let compressed = entry("compressed", b"repeated ".repeat(100)).compress().unwrap();
+		// a value that happens to be deflated is only decompressed when the kind says so
+		let deflated = entry("deflated", compressed.value.clone());
+		let message = Message { entries: vec![compressed, deflated.clone()], headers: vec![] };
+
+		let decrypted = message
+			.encrypt(&nonce, &receiver_sk.public_key(), &sender_sk)
+			.unwrap()
+			.decrypt(&nonce, &sender_sk.public_key(), &receiver_sk)
+			.unwrap();
+		assert_eq!(decrypted.entries[0], entry("compressed", b"repeated ".repeat(100)));
+		assert_eq!(decrypted.entries[1], deflated);
+	}
+	#[test]
+	fn nested_compressed_kinds_are_rejected() {
+		let kind = MessageType::Compressed(Box::new(MessageType::File {
+			filename: "notes.txt".into(),
+			mime: "text/plain".into(),
+		}));
+		assert_eq!(MessageType::decode(&mut &kind.encode()[..]).unwrap(), kind);
+
+		let nested = MessageType::Compressed(Box::new(kind));
+		assert!(MessageType::decode(&mut &nested.encode()[..]).is_err());
+		// a long run of compressed tags fails at the second one, without recursing through it
+		let bytes = vec![3; 1024 * 1024];
+		assert!(MessageType::decode(&mut &bytes[..]).is_err());
+
+		let sender_sk = SecretKey::generate(&mut OsRng);
+		let receiver_pk = SecretKey::generate(&mut OsRng).public_key();
+		let nonce = SalsaBox::generate_nonce(&mut OsRng);
+		assert!(nested.encrypt(&nonce, &receiver_pk, &sender_sk).is_err());
+	}
+	#[test]
+	fn decompressed_size_is_bounded() {
+		let sender_sk = SecretKey::generate(&mut OsRng);
+		let receiver_sk = SecretKey::generate(&mut OsRng);
+		let nonce = SalsaBox::generate_nonce(&mut OsRng);
+
+		let too_large = vec![0; MAX_DECOMPRESSED_SIZE + 1];
+		let entry =
+			MessageEntry { key: "bomb".into(), value: too_large, kind: MessageType::Binary };
+		assert!(entry.compress().is_err());
+
+		// a sender may deflate the value by hand, bypassing the check of `compress`
+		let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+		encoder.write_all(&entry.value).unwrap();
+		let bomb = MessageEntry {
+			value: encoder.finish().unwrap(),
+			kind: MessageType::Compressed(Box::new(MessageType::Binary)),
+			..entry
+		};
+		let encrypted = bomb.encrypt(&nonce, &receiver_sk.public_key(), &sender_sk).unwrap();
+		assert!(matches!(
+			encrypted.decrypt(&nonce, &sender_sk.public_key(), &receiver_sk),
+			Err(CypherError::DecompressedTooLarge { max: MAX_DECOMPRESSED_SIZE })
+		));
+	}
+	#[test]
+	fn encrypt_decrypt_message_headers() {
+		let sender_sk = SecretKey::generate(&mut OsRng);
+		let receiver_sk = SecretKey::generate(&mut OsRng);
+		let nonce = SalsaBox::generate_nonce(&mut OsRng);
+
+		let entry = |key: &str, value: &str| MessageEntry {
+			key: key.into(),
+			value: value.into(),
+			kind: MessageType::default(),
+		};
+		let message = Message {
+			entries: vec![entry("body", "hello")],
+			headers: vec![entry("subject", "greetings"), entry("content-type", "text/plain")],
+		};
+
+		let encrypted_message =
+			message.encrypt(&nonce, &receiver_sk.public_key(), &sender_sk).unwrap();
+		assert_eq!(encrypted_message.headers.len(), 2);
+		assert_ne!(encrypted_message.headers[0].value, message.headers[0].value);
+
+		let decrypted_message = encrypted_message
+			.decrypt(&nonce, &sender_sk.public_key(), &receiver_sk)
+			.expect("could not decrypt a test message");
+		assert_eq!(message, decrypted_message);
+	}
+	#[test]
+	fn debug_output_is_redacted() {
+		let plaintext = "top secret plaintext";
+		let message = Message {
+			entries: vec![MessageEntry {
+				key: "key".into(),
+				value: plaintext.into(),
+				kind: MessageType::File { filename: plaintext.into(), mime: "text/plain".into() },
+			}],
+			headers: vec![MessageEntry {
+				key: "subject".into(),
+				value: plaintext.into(),
+				kind: MessageType::default(),
+			}],
+		};
+
+		let debug = format!("{:?}", message);
+		let plaintext_bytes = format!("{:?}", plaintext.as_bytes());
+		assert!(!debug.contains(plaintext));
+		assert!(!debug.contains(plaintext_bytes.trim_matches(|c| c == '[' || c == ']')));
+		assert!(debug.contains(&format!("len: {}", plaintext.len())));
+	}
+
+	#[test]
+	fn swapped_entry_values_are_rejected() {
+		let sender_sk = SecretKey::generate(&mut OsRng);
+		let receiver_sk = SecretKey::generate(&mut OsRng);
+		let nonce = SalsaBox::generate_nonce(&mut OsRng);
+
+		let entry = |key: &str, value: &str| MessageEntry {
+			key: key.into(),
+			value: value.into(),
+			kind: MessageType::default(),
+		};
+		let message =
+			Message { entries: vec![entry("from", "alice"), entry("to", "bob")], headers: vec![] };
+
+		let mut encrypted_message =
+			message.encrypt(&nonce, &receiver_sk.public_key(), &sender_sk).unwrap();
+		let (first, second) = encrypted_message.entries.split_at_mut(1);
+		std::mem::swap(&mut first[0].value, &mut second[0].value);
+
+		assert!(matches!(
+			encrypted_message.decrypt(&nonce, &sender_sk.public_key(), &receiver_sk),
+			Err(CypherError::DecryptionFailed(_))
+		));
+	}
+
+	#[test]
+	fn duplicate_keys_get_distinct_nonces() {
+		let sender_sk = SecretKey::generate(&mut OsRng);
+		let receiver_sk = SecretKey::generate(&mut OsRng);
+		let nonce = SalsaBox::generate_nonce(&mut OsRng);
+
+		let entry = |value: &str| MessageEntry {
+			key: "to".into(),
+			value: value.into(),
+			kind: MessageType::default(),
+		};
+		let message = Message {
+			entries: vec![entry("alice"), entry("alice"), entry("bob")],
+			headers: vec![],
+		};
+
+		let encrypted = message.encrypt(&nonce, &receiver_sk.public_key(), &sender_sk).unwrap();
+		let entries = &encrypted.entries;
+		// equal keys and values are encrypted with distinct nonces
+		assert_ne!(entries[0].key, entries[1].key);
+		assert_ne!(entries[0].value, entries[1].value);
+
+		let decrypted = encrypted.decrypt(&nonce, &sender_sk.public_key(), &receiver_sk).unwrap();
+		assert_eq!(decrypted, message);
+	}
+
+	#[test]
+	fn build_message() {
+		let message = MessageBuilder::new()
+			.entry("subject", "hello")
+			.typed_entry(
+				"file",
+				vec![1, 2, 3],
+				MessageType::File {
+					filename: "a.bin".into(),
+					mime: "application/octet-stream".into(),
+				},
+			)
+			.header("timestamp", "1680000000")
+			.build()
+			.unwrap();
+
+		assert_eq!(message.entries.len(), 2);
+		assert_eq!(message.entries[0].key, b"subject");
+		assert_eq!(message.entries[1].value, vec![1, 2, 3]);
+		assert_eq!(message.headers[0].value, b"1680000000");
+	}
+
+	#[test]
+	fn build_invalid_message() {
+		assert!(matches!(MessageBuilder::new().build(), Err(CypherError::EmptyMessage)));
+		assert!(matches!(
+			MessageBuilder::new().entry("subject", "hello").entry("body", "").build(),
+			Err(CypherError::EmptyEntry(1))
+		));
+		assert!(matches!(
+			MessageBuilder::new().entry("", "hello").build(),
+			Err(CypherError::EmptyEntry(0))
+		));
+
+		let builder = (0..=MAX_ENTRIES)
+			.fold(MessageBuilder::new(), |builder, i| builder.entry(i.to_string(), "value"));
+		assert!(matches!(
+			builder.build(),
+			Err(CypherError::TooManyEntries { got, max: MAX_ENTRIES }) if got == MAX_ENTRIES + 1
+		));
+	}
+
+	#[test]
+	fn encrypt_max_entries() {
+		let sender_sk = SecretKey::generate(&mut OsRng);
+		let receiver_sk = SecretKey::generate(&mut OsRng);
+		let nonce = SalsaBox::generate_nonce(&mut OsRng);
+		let entry = |i: usize| MessageEntry {
+			key: i.to_string().into(),
+			value: "value".into(),
+			kind: MessageType::default(),
+		};
+
+		// at the boundary, headers are counted as entries
+		let message =
+			Message { entries: (1..MAX_ENTRIES).map(entry).collect(), headers: vec![entry(0)] };
+		let encrypted = message.encrypt(&nonce, &receiver_sk.public_key(), &sender_sk).unwrap();
+		assert_eq!(
+			encrypted.decrypt(&nonce, &sender_sk.public_key(), &receiver_sk).unwrap(),
+			message
+		);
+
+		let too_many = Message { headers: vec![entry(0), entry(MAX_ENTRIES)], ..message };
+		assert!(matches!(
+			too_many.encrypt(&nonce, &receiver_sk.public_key(), &sender_sk),
+			Err(CypherError::TooManyEntries { got, max: MAX_ENTRIES }) if got == MAX_ENTRIES + 1
+		));
+
+		// a received message over the bound is not decrypted either
+		let mut received = encrypted;
+		received.entries.push(received.entries[0].clone());
+		assert!(matches!(
+			received.decrypt(&nonce, &sender_sk.public_key(), &receiver_sk),
+			Err(CypherError::TooManyEntries { .. })
+		));
+	}
+
+	#[test]
+	fn encrypt_json_entry() {
+		let sender_sk = SecretKey::generate(&mut OsRng);
+		let receiver_sk = SecretKey::generate(&mut OsRng);
+		let nonce = SalsaBox::generate_nonce(&mut OsRng);
+		let entry = |value: &str, kind: MessageType| MessageEntry {
+			key: "data".into(),
+			value: value.into(),
+			kind,
+		};
+		let json = r#"{ "b": 1, "a": 2 }"#;
+
+		let message = Message {
+			entries: vec![entry(json, MessageType::Json), entry(json, MessageType::Binary)],
+			headers: vec![],
+		};
+		let decrypted = message
+			.encrypt(&nonce, &receiver_sk.public_key(), &sender_sk)
+			.and_then(|m| m.decrypt(&nonce, &sender_sk.public_key(), &receiver_sk))
+			.unwrap();
+		assert_eq!(decrypted.entries[0].value, br#"{"a":2,"b":1}"#);
+		assert_eq!(decrypted.entries[1].value, json.as_bytes());
+
+		let invalid = Message { entries: vec![entry("{", MessageType::Json)], headers: vec![] };
+		assert!(matches!(
+			invalid.encrypt(&nonce, &receiver_sk.public_key(), &sender_sk),
+			Err(CypherError::InvalidJson)
+		));
+	}
 }
@@ -1,4 +1,4 @@
-use crate::{KEY_SIZE, NONCE_SIZE};
+use crate::{KEY_SIZE, MAC_SIZE, NONCE_SIZE, SUBJECT_SIZE};
 use codec::{Decode, Encode};
 #[cfg(feature = "std")]
 pub use inner_std::*;
@@ -11,23 +11,75 @@ use serde::{Deserialize, Serialize};
 #[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
 pub struct Channel {
 	/// Encrypted nonce, should be used to decrypt a message
+	#[cfg_attr(feature = "std", serde(with = "crate::base64_serde"))]
 	pub nonce: Vec<u8>,
 	/// Encrypted parties (pubkeys) of communication
+	#[cfg_attr(feature = "std", serde(with = "crate::base64_serde::vec"))]
 	pub parties: Vec<Vec<u8>>,
 }
 
+/// Digest of the root hash, recorded in metadata so a verifier uses the same one
+#[derive(Debug, Encode, Decode, TypeInfo, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[repr(u8)]
+pub enum HashAlgo {
+	#[default]
+	Blake2s256,
+	/// BLAKE2b with 32 bytes output, the same as `blake2_256` of Substrate
+	Blake2b256,
+}
+
+impl TryFrom<u8> for HashAlgo {
+	type Error = u8;
+
+	fn try_from(value: u8) -> Result<Self, Self::Error> {
+		match value {
+			0 => Ok(HashAlgo::Blake2s256),
+			1 => Ok(HashAlgo::Blake2b256),
+			_ => Err(value),
+		}
+	}
+}
+
 #[derive(Debug, Encode, Decode, TypeInfo, Clone, Default, PartialEq)]
 #[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
 pub struct MessageMetadata {
 	/// Nonce generated by Alice, should be used with broker
+	#[cfg_attr(feature = "std", serde(with = "crate::base64_serde::array"))]
 	pub nonce: [u8; NONCE_SIZE],
 	/// The pubkey that Alice has generated to encrypt a channels using a Diffie–Hellman
 	/// algorithm. Channels are then used to decrypt a message. Alice uses broker's
 	/// private key and Bob's pubkey to encrypt, then Bob uses the broker's pubkey and his
 	/// private key to decrypt the channels
+	#[cfg_attr(feature = "std", serde(with = "crate::base64_serde::array"))]
 	pub broker: [u8; KEY_SIZE],
 	/// The root hash of all metadata and message entries
+	#[cfg_attr(feature = "std", serde(with = "crate::base64_serde::array"))]
 	pub hash: [u8; KEY_SIZE],
+	/// Digest the root hash is computed with
+	#[cfg_attr(feature = "std", serde(default))]
+	pub hash_algo: HashAlgo,
+	/// The root hash of a message this one replies to
+	#[cfg_attr(feature = "std", serde(default, with = "crate::base64_serde::option"))]
+	pub in_reply_to: Option<[u8; KEY_SIZE]>,
+	/// Optional cleartext hint of the number of message entries, checked by the pallet to be
+	/// within its limits. It discloses the message structure and is not covered by the root hash
+	#[cfg_attr(feature = "std", serde(default))]
+	pub entry_count: Option<u32>,
+	/// Number of parts the message is split into with `split_parts`, stored under consecutive
+	/// counters of the sender. Every part is sent with this metadata, the first of them holds
+	/// the first part. Not covered by the root hash, which covers the joined message
+	#[cfg_attr(feature = "std", serde(default))]
+	pub part_count: Option<u32>,
+	/// Optional blinded subject, the `subject` header of the message padded to `SUBJECT_SIZE`
+	/// and encrypted with a key of the secret nonce. Recipients preview it with
+	/// `decrypt_subject` before fetching the message, it is covered by the root hash
+	#[cfg_attr(feature = "std", serde(default, with = "crate::base64_serde::option"))]
+	pub subject: Option<[u8; SUBJECT_SIZE + MAC_SIZE]>,
+	/// Optional sr25519 signature of the sender account over `hash`, gives recipients a proof
+	/// of authorship. It signs the root hash, so it is not covered by it
+	#[cfg_attr(feature = "std", serde(default, with = "crate::base64_serde::option"))]
+	pub signature: Option<[u8; 64]>,
 	/// Keeps info to decrypt a message using Diffie–Hellman.
 	pub channels: Vec<Channel>,
 }
@@ -35,29 +87,135 @@ pub struct MessageMetadata {
 #[cfg(feature = "std")]
 mod inner_std {
 	use super::*;
-	use crate::messages::{Message, MessageEntry};
-	use blake2::{digest::Update, Digest};
+	use crate::{messages::Message, MetadataLimits, MAX_RECIPIENTS};
+	use blake2::{digest::consts::U32, Blake2b, Blake2s256, Digest};
+	use codec::DecodeAll;
 	use crypto_box::{
 		aead::{AeadCore, OsRng},
 		PublicKey, SalsaBox, SecretKey,
 	};
 	use nolik_cypher::{BytesCypher, CypherError, SalsaNonce};
+	use std::{borrow::Borrow, collections::HashSet};
+	use zeroize::{Zeroize, Zeroizing};
+
+	/// Domain separation of the previous message hash in the root hash
+	const PREV_HASH_PREFIX: &[u8] = b"nolik/prev-hash";
+
+	/// Domain separation of group ids from other hashes of public keys
+	const GROUP_ID_PREFIX: &[u8] = b"nolik/group";
+
+	/// Domain separation of the subject in the root hash
+	const SUBJECT_PREFIX: &[u8] = b"nolik/subject";
+
+	/// Domain separation of the subject key derived from the secret nonce
+	const SUBJECT_KEY_PREFIX: &[u8] = b"nolik/subject-key";
+
+	/// The subject prefixed with its length and padded with zeros, longer subjects are truncated
+	fn padded_subject(subject: &[u8]) -> Zeroizing<[u8; SUBJECT_SIZE]> {
+		let len = subject.len().min(SUBJECT_SIZE - 1);
+		let mut padded = Zeroizing::new([0; SUBJECT_SIZE]);
+		padded[0] = len as u8;
+		padded[1..=len].copy_from_slice(&subject[..len]);
+		padded
+	}
+
+	/// Checked conversion of a channel nonce, so malformed metadata fails with `InvalidNonce`
+	/// instead of a panic of `SalsaNonce::from_slice`
+	fn parse_nonce(bytes: &[u8]) -> Result<SalsaNonce, CypherError> {
+		<[u8; NONCE_SIZE]>::try_from(bytes)
+			.map(SalsaNonce::from)
+			.map_err(|_| CypherError::InvalidNonce(bytes.to_vec()))
+	}
+
+	/// Key the subject is blinded with, only the parties knowing the secret nonce derive it
+	fn subject_key(secret_nonce: &SalsaNonce) -> SecretKey {
+		let mut hash = Blake2s256::new();
+		Digest::update(&mut hash, SUBJECT_KEY_PREFIX);
+		Digest::update(&mut hash, secret_nonce.as_slice());
+		let key = Zeroizing::new(<[u8; KEY_SIZE]>::from(hash.finalize()));
+		SecretKey::from(*key)
+	}
+
+	/// Optional inputs of the root hash of new metadata
+	#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+	pub struct MetadataOptions {
+		/// Digest the root hash is computed with, recorded in metadata
+		pub hash_algo: HashAlgo,
+		/// The root hash of a message this one replies to, recorded in metadata
+		pub in_reply_to: Option<[u8; KEY_SIZE]>,
+		/// The root hash of the previous message of the sender, only committed to
+		pub prev_hash: Option<[u8; KEY_SIZE]>,
+	}
+
+	/// Public keys of a message the root hash commits to
+	#[derive(Debug, Clone, Copy)]
+	pub struct MessageKeys<'a> {
+		/// The account key that signs the extrinsic
+		pub origin: &'a PublicKey,
+		pub sender_pk: &'a PublicKey,
+		pub broker_pk: &'a PublicKey,
+		pub recipients: &'a [&'a PublicKey],
+	}
 
 	impl MessageMetadata {
 		/// Creates encrypted metadata using Diffie-Hellman scheme with extra secret nonce
+		///
+		/// A recipient can't be added to existing metadata: the broker secret key needed to
+		/// encrypt a new channel is dropped here, the message is encrypted to a particular
+		/// recipient key and the root hash commits to the list of recipients. To reach another
+		/// recipient, new metadata has to be created and the message encrypted for them.
+		///
+		/// `prev_hash` is the root hash of the previous message of the sender. It is only
+		/// committed to by the root hash and not stored, so chaining every message to the previous
+		/// one lets a recipient spot a dropped or reordered message with `verify_chained`.
 		pub fn new_encrypted(
 			origin: &PublicKey,
 			public_nonce: &SalsaNonce,
 			sender_pk: &PublicKey,
 			recipients: &[&PublicKey],
 			message: &Message,
+			in_reply_to: Option<[u8; KEY_SIZE]>,
+			prev_hash: Option<[u8; KEY_SIZE]>,
+		) -> Result<(MessageMetadata, SalsaNonce), CypherError> {
+			Self::new_encrypted_with(
+				origin,
+				public_nonce,
+				sender_pk,
+				recipients,
+				message,
+				MetadataOptions { in_reply_to, prev_hash, ..Default::default() },
+			)
+		}
+
+		/// Same as `new_encrypted`, but the root hash is computed with the `options`
+		pub fn new_encrypted_with(
+			origin: &PublicKey,
+			public_nonce: &SalsaNonce,
+			sender_pk: &PublicKey,
+			recipients: &[&PublicKey],
+			message: &Message,
+			options: MetadataOptions,
 		) -> Result<(MessageMetadata, SalsaNonce), CypherError> {
+			if recipients.len() > MAX_RECIPIENTS {
+				return Err(CypherError::TooManyRecipients {
+					got: recipients.len(),
+					max: MAX_RECIPIENTS,
+				})
+			}
+
 			let secret_nonce = SalsaBox::generate_nonce(&mut OsRng);
+			// `SecretKey` is zeroized on drop
 			let broker_sk = SecretKey::generate(&mut OsRng);
 			let broker_pk = broker_sk.public_key();
 
 			let mut parties = vec![sender_pk];
-			parties.extend(recipients);
+			for recipient in recipients {
+				// each party gets exactly one channel
+				if parties.iter().any(|party| party.as_bytes() == recipient.as_bytes()) {
+					return Err(CypherError::DuplicateRecipient((*recipient).clone()))
+				}
+				parties.push(*recipient);
+			}
 
 			let mut encrypted_channels = vec![];
 			for party_pk in &parties {
@@ -75,113 +233,692 @@ mod inner_std {
 				.as_slice()
 				.try_into()
 				.map_err(|_| CypherError::UnexpectedNonceType(*public_nonce))?;
-			Ok((
-				MessageMetadata {
-					nonce: public_nonce_arr,
-					broker: *broker_pk.as_bytes(),
-					hash: Self::compute_root_hash(
-						origin,
-						public_nonce,
-						sender_pk,
-						&broker_pk,
-						&secret_nonce,
-						recipients,
-						message,
-					)
-					.finalize()
-					.into(),
-					channels: encrypted_channels,
-				},
-				secret_nonce,
-			))
+			let metadata = MessageMetadata {
+				nonce: public_nonce_arr,
+				broker: *broker_pk.as_bytes(),
+				hash: Self::compute_root_hash(
+					MessageKeys { origin, sender_pk, broker_pk: &broker_pk, recipients },
+					public_nonce,
+					&secret_nonce,
+					message,
+					&options,
+				),
+				hash_algo: options.hash_algo,
+				in_reply_to: options.in_reply_to,
+				entry_count: None,
+				part_count: None,
+				subject: message
+					.subject()
+					.map(|subject| Self::blind_subject(subject, public_nonce, &secret_nonce))
+					.transpose()?,
+				signature: None,
+				channels: encrypted_channels,
+			};
+
+			// the same checks as the pallet does, so the metadata is never rejected by the chain
+			metadata.check_parties()?;
+			metadata.validate(&MetadataLimits::DEFAULT)?;
+			Ok((metadata, secret_nonce))
 		}
 
-		/// Create a root hash of all metadata and message entries
-		pub fn compute_root_hash(
+		/// Same as `new_encrypted`, but with a sender key generated for this message only, so
+		/// messages can't be linked by the sender key. The account of the `origin` only signs the
+		/// extrinsic. Recipients find the ephemeral public key among the encrypted parties with
+		/// `sender_pk`.
+		///
+		/// The returned ephemeral secret key encrypts the message. The sender's own channel is
+		/// encrypted to it as well, so the sender has to keep the key to read the message later.
+		pub fn new_ephemeral(
+			origin: &PublicKey,
+			public_nonce: &SalsaNonce,
+			recipients: &[&PublicKey],
+			message: &Message,
+			in_reply_to: Option<[u8; KEY_SIZE]>,
+		) -> Result<(MessageMetadata, SalsaNonce, SecretKey), CypherError> {
+			let sender_sk = SecretKey::generate(&mut OsRng);
+			let (metadata, secret_nonce) = Self::new_encrypted(
+				origin,
+				public_nonce,
+				&sender_sk.public_key(),
+				recipients,
+				message,
+				in_reply_to,
+				None,
+			)?;
+			Ok((metadata, secret_nonce, sender_sk))
+		}
+
+		/// Same as `new_encrypted`, with the members of the `group` as recipients
+		pub fn new_encrypted_to_group(
 			origin: &PublicKey,
 			public_nonce: &SalsaNonce,
 			sender_pk: &PublicKey,
-			broker_pk: &PublicKey,
+			group: &Group,
+			message: &Message,
+			in_reply_to: Option<[u8; KEY_SIZE]>,
+			prev_hash: Option<[u8; KEY_SIZE]>,
+		) -> Result<(MessageMetadata, SalsaNonce), CypherError> {
+			Self::new_encrypted(
+				origin,
+				public_nonce,
+				sender_pk,
+				&group.members.iter().collect::<Vec<_>>(),
+				message,
+				in_reply_to,
+				prev_hash,
+			)
+		}
+
+		/// Metadata of a note the sender sends to themselves. It has the only channel of the
+		/// sender with the sender as the only party, which the pallet accepts like any other
+		/// metadata. The message is encrypted to the `sender_pk` with the sender secret key
+		pub fn new_self_message(
+			origin: &PublicKey,
+			public_nonce: &SalsaNonce,
+			sender_pk: &PublicKey,
+			message: &Message,
+			in_reply_to: Option<[u8; KEY_SIZE]>,
+		) -> Result<(MessageMetadata, SalsaNonce), CypherError> {
+			Self::new_encrypted(origin, public_nonce, sender_pk, &[], message, in_reply_to, None)
+		}
+
+		/// Create a root hash of all metadata and message entries with the digest of the
+		/// `options`
+		pub fn compute_root_hash(
+			keys: MessageKeys,
+			public_nonce: &SalsaNonce,
+			secret_nonce: &SalsaNonce,
+			message: &Message,
+			options: &MetadataOptions,
+		) -> [u8; KEY_SIZE] {
+			let root_hash = match options.hash_algo {
+				HashAlgo::Blake2s256 => Self::root_hash_with::<Blake2s256>,
+				HashAlgo::Blake2b256 => Self::root_hash_with::<Blake2b<U32>>,
+			};
+			root_hash(keys, public_nonce, secret_nonce, message, options)
+		}
+
+		fn root_hash_with<D: Digest>(
+			keys: MessageKeys,
+			public_nonce: &SalsaNonce,
 			secret_nonce: &SalsaNonce,
-			recipients: &[&PublicKey],
 			message: &Message,
-		) -> blake2::Blake2s256 {
-			let mut hash = blake2::Blake2s256::new();
+			options: &MetadataOptions,
+		) -> [u8; KEY_SIZE] {
+			let MessageKeys { origin, sender_pk, broker_pk, recipients } = keys;
+			let mut hash = D::new();
 
-			let origin_hash = Self::hash_with_nonce(origin.as_ref(), secret_nonce);
-			let public_nonce_hash = Self::hash_with_nonce(public_nonce.as_ref(), secret_nonce);
-			let secret_nonce_hash = Self::hash_with_nonce(secret_nonce.as_ref(), secret_nonce);
-			let broker_pk_hash = Self::hash_with_nonce(broker_pk.as_ref(), secret_nonce);
-			let sender_pk_hash = Self::hash_with_nonce(sender_pk.as_ref(), secret_nonce);
+			let origin_hash = Self::digest_with_nonce::<D>(origin.as_ref(), secret_nonce);
+			let public_nonce_hash =
+				Self::digest_with_nonce::<D>(public_nonce.as_ref(), secret_nonce);
+			let secret_nonce_hash =
+				Self::digest_with_nonce::<D>(secret_nonce.as_ref(), secret_nonce);
+			let broker_pk_hash = Self::digest_with_nonce::<D>(broker_pk.as_ref(), secret_nonce);
+			let sender_pk_hash = Self::digest_with_nonce::<D>(sender_pk.as_ref(), secret_nonce);
 
-			let mut recipients_hash = blake2::Blake2s256::new();
+			let mut recipients_hash = D::new();
 			for recipient in recipients {
-				let recipient_pk_hash = Self::hash_with_nonce(recipient.as_ref(), secret_nonce);
-				Update::update(&mut recipients_hash, recipient_pk_hash.as_ref());
+				let recipient_pk_hash =
+					Self::digest_with_nonce::<D>(recipient.as_ref(), secret_nonce);
+				Digest::update(&mut recipients_hash, &recipient_pk_hash);
+			}
+			Digest::update(&mut recipients_hash, secret_nonce.as_slice());
+
+			let mut entries_hash = D::new();
+			for entry in &message.entries {
+				let key_hash = Self::digest_with_nonce::<D>(entry.key.as_ref(), secret_nonce);
+				let value_hash = Self::digest_with_nonce::<D>(&entry.hashed_value(), secret_nonce);
+				Digest::update(&mut entries_hash, &key_hash);
+				Digest::update(&mut entries_hash, &value_hash);
 			}
-			Update::update(&mut recipients_hash, secret_nonce.as_ref());
+			Digest::update(&mut entries_hash, secret_nonce.as_slice());
+
+			// messages without headers keep the same hash as before headers were introduced
+			let headers_hash = (!message.headers.is_empty()).then(|| {
+				let mut headers_hash = D::new();
+				for entry in &message.headers {
+					let key_hash = Self::digest_with_nonce::<D>(entry.key.as_ref(), secret_nonce);
+					let value_hash =
+						Self::digest_with_nonce::<D>(&entry.hashed_value(), secret_nonce);
+					Digest::update(&mut headers_hash, &key_hash);
+					Digest::update(&mut headers_hash, &value_hash);
+				}
+				Digest::update(&mut headers_hash, secret_nonce.as_slice());
+				headers_hash.finalize()
+			});
 
-			let mut entries_hash = blake2::Blake2s256::new();
-			for MessageEntry { key, value, kind: _ } in &message.entries {
-				let key_hash = Self::hash_with_nonce(key.as_ref(), secret_nonce);
-				let value_hash = Self::hash_with_nonce(value.as_ref(), secret_nonce);
-				Update::update(&mut entries_hash, &key_hash);
-				Update::update(&mut entries_hash, &value_hash);
+			Digest::update(&mut hash, &origin_hash);
+			Digest::update(&mut hash, &public_nonce_hash);
+			Digest::update(&mut hash, &secret_nonce_hash);
+			Digest::update(&mut hash, &broker_pk_hash);
+			Digest::update(&mut hash, &sender_pk_hash);
+			Digest::update(&mut hash, recipients_hash.finalize());
+			Digest::update(&mut hash, entries_hash.finalize());
+			if let Some(headers_hash) = headers_hash {
+				Digest::update(&mut hash, &headers_hash);
+			}
+			if let Some(in_reply_to) = &options.in_reply_to {
+				let in_reply_to_hash = Self::digest_with_nonce::<D>(in_reply_to, secret_nonce);
+				Digest::update(&mut hash, &in_reply_to_hash);
+			}
+			// prefixed, so the previous message hash can't be confused with `in_reply_to`
+			if let Some(prev_hash) = &options.prev_hash {
+				let prev_hash = [PREV_HASH_PREFIX, prev_hash.as_slice()].concat();
+				let prev_hash = Self::digest_with_nonce::<D>(&prev_hash, secret_nonce);
+				Digest::update(&mut hash, &prev_hash);
+			}
+			// the subject as it is blinded, which may be truncated
+			if let Some(subject) = message.subject() {
+				let subject =
+					Zeroizing::new([SUBJECT_PREFIX, padded_subject(subject).as_slice()].concat());
+				let subject_hash = Self::digest_with_nonce::<D>(&subject, secret_nonce);
+				Digest::update(&mut hash, &subject_hash);
 			}
-			Update::update(&mut entries_hash, secret_nonce.as_ref());
 
-			Update::update(&mut hash, &origin_hash);
-			Update::update(&mut hash, &public_nonce_hash);
-			Update::update(&mut hash, &secret_nonce_hash);
-			Update::update(&mut hash, &broker_pk_hash);
-			Update::update(&mut hash, &sender_pk_hash);
-			Update::update(&mut hash, &recipients_hash.finalize());
-			Update::update(&mut hash, &entries_hash.finalize());
-			hash
+			let mut root_hash = [0; KEY_SIZE];
+			root_hash.copy_from_slice(&hash.finalize());
+			root_hash
 		}
 
 		pub fn hash_with_nonce(data: &[u8], nonce: &SalsaNonce) -> Vec<u8> {
-			let mut hash = blake2::Blake2s256::new();
-			Update::update(&mut hash, data);
-			Update::update(&mut hash, nonce.as_ref());
+			Self::digest_with_nonce::<Blake2s256>(data, nonce)
+		}
+
+		fn digest_with_nonce<D: Digest>(data: &[u8], nonce: &SalsaNonce) -> Vec<u8> {
+			let mut hash = D::new();
+			Digest::update(&mut hash, data);
+			Digest::update(&mut hash, nonce.as_slice());
 			hash.finalize().to_vec()
 		}
 
+		/// Blind the `subject` with a key of the `secret_nonce`, the result has a fixed size
+		pub fn blind_subject(
+			subject: &[u8],
+			public_nonce: &SalsaNonce,
+			secret_nonce: &SalsaNonce,
+		) -> Result<[u8; SUBJECT_SIZE + MAC_SIZE], CypherError> {
+			let subject_sk = subject_key(secret_nonce);
+			let subject_pk = subject_sk.public_key();
+			padded_subject(subject)
+				.as_slice()
+				.encrypt(public_nonce, &subject_pk, &subject_sk)?
+				.try_into()
+				.map_err(|_| CypherError::EncryptionFailed(subject_pk))
+		}
+
+		/// Preview the blinded subject without fetching and decrypting the message. Only the
+		/// channel nonce of the `receiver_sk` is decrypted, `None` if the message has no subject
+		pub fn decrypt_subject(
+			&self,
+			receiver_sk: &SecretKey,
+		) -> Result<Option<Vec<u8>>, CypherError> {
+			let Some(subject) = &self.subject else { return Ok(None) };
+			self.check_broker()?;
+			let public_nonce = SalsaNonce::from_slice(&self.nonce);
+			let broker_pk = PublicKey::from(self.broker);
+
+			let secret_nonce = self
+				.channels
+				.iter()
+				.find_map(|channel| {
+					channel.nonce.decrypt(public_nonce, &broker_pk, receiver_sk).ok()
+				})
+				.map(Zeroizing::new)
+				.ok_or(CypherError::NotDecrypted)?;
+			let mut secret_nonce = parse_nonce(&secret_nonce)?;
+
+			let subject_sk = subject_key(&secret_nonce);
+			secret_nonce.as_mut_slice().zeroize();
+			let subject_pk = subject_sk.public_key();
+			let padded = Zeroizing::new(subject.as_slice().decrypt(
+				public_nonce,
+				&subject_pk,
+				&subject_sk,
+			)?);
+			match padded.first() {
+				Some(len) if (*len as usize) < SUBJECT_SIZE && padded.len() == SUBJECT_SIZE =>
+					Ok(Some(padded[1..=*len as usize].to_vec())),
+				_ => Err(CypherError::DecryptionFailed(subject_pk)),
+			}
+		}
+
+		/// Serialize metadata to JSON, bytes are encoded as base64 strings
+		pub fn to_json(&self) -> Result<String, serde_json::Error> {
+			serde_json::to_string(self)
+		}
+
+		/// Deserialize metadata from JSON produced by `to_json`
+		pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+			serde_json::from_str(json)
+		}
+
+		/// Verify the root hash of decrypted metadata against the decrypted `message` and the
+		/// parties of communication
+		pub fn verify(
+			&self,
+			origin: &PublicKey,
+			message: &Message,
+			sender_pk: &PublicKey,
+			recipients: &[&PublicKey],
+		) -> Result<(), CypherError> {
+			self.verify_chained(origin, message, sender_pk, recipients, None)
+		}
+
+		/// Same as `verify`, but also checks the message follows the message of the sender with
+		/// the root hash `prev_hash`, as passed to `new_encrypted`
+		pub fn verify_chained(
+			&self,
+			origin: &PublicKey,
+			message: &Message,
+			sender_pk: &PublicKey,
+			recipients: &[&PublicKey],
+			prev_hash: Option<&[u8; KEY_SIZE]>,
+		) -> Result<(), CypherError> {
+			let channel = self.channels.first().ok_or(CypherError::NotDecrypted)?;
+			let secret_nonce = parse_nonce(&channel.nonce)?;
+			let hash = Self::compute_root_hash(
+				MessageKeys {
+					origin,
+					sender_pk,
+					broker_pk: &PublicKey::from(self.broker),
+					recipients,
+				},
+				SalsaNonce::from_slice(&self.nonce),
+				&secret_nonce,
+				message,
+				&MetadataOptions {
+					hash_algo: self.hash_algo,
+					in_reply_to: self.in_reply_to,
+					prev_hash: prev_hash.copied(),
+				},
+			);
+
+			if hash != self.hash {
+				return Err(CypherError::HashMismatch)
+			}
+
+			// the blinded subject has to preview the subject of the message
+			let subject = message
+				.subject()
+				.map(|subject| {
+					Self::blind_subject(subject, SalsaNonce::from_slice(&self.nonce), &secret_nonce)
+				})
+				.transpose()?;
+			if subject != self.subject {
+				return Err(CypherError::HashMismatch)
+			}
+			Ok(())
+		}
+
 		/// Decrypt metadata channels that are possible to decrypt and return
 		pub fn decrypt(&self, receiver_sk: &SecretKey) -> Result<Self, CypherError> {
+			let channels = self
+				.decrypt_detailed(receiver_sk)?
+				.into_iter()
+				.map(|(_, channel)| channel)
+				.collect();
+			Ok(MessageMetadata { channels, ..*self })
+		}
+
+		/// Decrypt metadata with the first of the `sks` able to decrypt it, e.g. keys of several
+		/// identities of a user, and return the index of the key along with the decrypted
+		/// metadata
+		pub fn decrypt_any(&self, sks: &[SecretKey]) -> Result<(usize, Self), CypherError> {
+			let index = sks
+				.iter()
+				.position(|sk| self.can_decrypt(sk))
+				.ok_or_else(|| CypherError::DecryptionFailed(PublicKey::from(self.broker)))?;
+			Ok((index, self.decrypt(&sks[index])?))
+		}
+
+		/// Indices of the `sks` able to open a channel, e.g. keys of users of a shared inbox.
+		/// Only channel nonces are decrypted, as in `can_decrypt`
+		pub fn recipients_among(&self, sks: &[SecretKey]) -> Vec<usize> {
+			sks.iter()
+				.enumerate()
+				.filter(|(_, sk)| self.can_decrypt(sk))
+				.map(|(index, _)| index)
+				.collect()
+		}
+
+		/// Decrypt metadata channels that are possible to decrypt and return them along with
+		/// their original index. Index `0` is the sender's channel, the rest belong to recipients
+		/// in the order they were passed to `new_encrypted`.
+		///
+		/// Every channel is fully processed whether it belongs to the receiver or not, so the
+		/// time taken doesn't depend on a matched index. AEAD failures themselves are not constant
+		/// time, so a small residual leakage remains.
+		pub fn decrypt_detailed(
+			&self,
+			receiver_sk: &SecretKey,
+		) -> Result<Vec<(usize, Channel)>, CypherError> {
+			self.check_broker()?;
+			let decrypted: Vec<_> = self
+				.channels
+				.iter()
+				.map(|channel| self.decrypt_channel(channel, receiver_sk))
+				.collect();
+
+			let mut channels = vec![];
+			for (index, channel) in decrypted.into_iter().enumerate() {
+				if let Some(channel) = channel? {
+					channels.push((index, channel));
+				}
+			}
+
+			Ok(channels)
+		}
+
+		/// Lazily decrypt a stream of metadata, e.g. an inbox being synced, so only one metadata
+		/// is decrypted at a time. Metadata without a channel for the `receiver_sk` is skipped,
+		/// while metadata failing to decrypt is yielded as an error
+		pub fn decrypt_all<'a, I>(
+			metadata: I,
+			receiver_sk: &'a SecretKey,
+		) -> impl Iterator<Item = Result<Self, CypherError>> + 'a
+		where
+			I: IntoIterator,
+			I::Item: Borrow<Self>,
+			I::IntoIter: 'a,
+		{
+			metadata.into_iter().filter_map(move |metadata| {
+				match metadata.borrow().decrypt(receiver_sk) {
+					Ok(decrypted) if decrypted.channels.is_empty() => None,
+					result => Some(result),
+				}
+			})
+		}
+
+		/// Check if any channel is possible to decrypt with the `receiver_sk`
+		pub fn can_decrypt(&self, receiver_sk: &SecretKey) -> bool {
+			if self.check_broker().is_err() {
+				return false
+			}
 			let public_nonce = SalsaNonce::from_slice(&self.nonce);
 			let broker_pk = PublicKey::from(self.broker);
 
-			let mut channels = vec![];
+			// every channel is tried, as in `decrypt_detailed`
+			self.channels
+				.iter()
+				.filter(|channel| {
+					channel
+						.nonce
+						.decrypt(public_nonce, &broker_pk, receiver_sk)
+						.map(Zeroizing::new)
+						.is_ok()
+				})
+				.count() > 0
+		}
 
-			for channel in &self.channels {
-				let secret_nonce =
-					match channel.nonce.decrypt(public_nonce, &broker_pk, receiver_sk) {
-						Ok(nonce) => *SalsaNonce::from_slice(&nonce),
-						_ => {
-							// can't decrypt - not receiver's entry, try next one
-							continue
-						},
-					};
-
-				channels.push(Channel {
-					nonce: secret_nonce.as_slice().into(),
-					parties: channel
-						.parties
-						.iter()
-						.map(|p| p.decrypt(&secret_nonce, &broker_pk, receiver_sk))
-						.collect::<Result<_, _>>()?,
-				});
+		/// Cheap check if any channel belongs to the `sk`, only channel nonces are decrypted and
+		/// the search stops on the first match. Unlike `can_decrypt` the time taken depends on
+		/// the matched channel, so it's meant for filtering on a trusted device only
+		pub fn has_channel_for(&self, sk: &SecretKey) -> bool {
+			if self.check_broker().is_err() {
+				return false
 			}
+			let public_nonce = SalsaNonce::from_slice(&self.nonce);
+			let broker_pk = PublicKey::from(self.broker);
 
-			Ok(MessageMetadata { channels, ..*self })
+			self.channels.iter().any(|channel| {
+				channel.nonce.decrypt(public_nonce, &broker_pk, sk).map(Zeroizing::new).is_ok()
+			})
+		}
+
+		/// Decrypt the sender's own channel, so the sender is able to read messages they sent
+		pub fn decrypt_as_sender(&self, sender_sk: &SecretKey) -> Result<Self, CypherError> {
+			self.check_broker()?;
+			let channel = self.channels.first().ok_or(CypherError::NotDecrypted)?;
+			let channel =
+				self.decrypt_channel(channel, sender_sk)?.ok_or(CypherError::NotDecrypted)?;
+			Ok(MessageMetadata { channels: vec![channel], ..*self })
+		}
+
+		/// Check every channel lists all parties, one per channel, as the pallet expects. Holds
+		/// for metadata created with `new_encrypted`, so it only fails for metadata built
+		/// otherwise
+		pub fn check_parties(&self) -> Result<(), CypherError> {
+			let channels = self.channels.len();
+			match self.channels.iter().position(|channel| channel.parties.len() != channels) {
+				Some(index) => Err(CypherError::ChannelPartyMismatch {
+					index,
+					parties: self.channels[index].parties.len(),
+					channels,
+				}),
+				None => Ok(()),
+			}
+		}
+
+		/// Public key of the sender, should be called on decrypted metadata. The sender is the
+		/// first party of every channel. Fails with `NotDecrypted` if no channel is decrypted,
+		/// its nonce is still encrypted then
+		pub fn sender_pk(&self) -> Result<PublicKey, CypherError> {
+			let channel = self
+				.channels
+				.first()
+				.filter(|channel| channel.nonce.len() == NONCE_SIZE)
+				.ok_or(CypherError::NotDecrypted)?;
+			let party = channel.parties.first().ok_or(CypherError::NotDecrypted)?;
+			<[u8; KEY_SIZE]>::try_from(party.as_slice())
+				.map(PublicKey::from)
+				.map_err(|_| CypherError::InvalidPubkey(party.clone()))
+		}
+
+		/// Recipients of a reply to everyone, should be called on decrypted metadata. The parties
+		/// of the decrypted channel are parsed, the replier `my_pk` is removed and duplicates
+		/// are skipped, so the original sender comes first followed by the other recipients
+		pub fn reply_recipients(&self, my_pk: &PublicKey) -> Result<Vec<PublicKey>, CypherError> {
+			let channel = self.channels.first().ok_or(CypherError::NotDecrypted)?;
+
+			let mut recipients: Vec<PublicKey> = vec![];
+			for party in &channel.parties {
+				let pk = <[u8; KEY_SIZE]>::try_from(party.as_slice())
+					.map(PublicKey::from)
+					.map_err(|_| CypherError::InvalidPubkey(party.clone()))?;
+				if pk.as_bytes() != my_pk.as_bytes() &&
+					!recipients.iter().any(|r| r.as_bytes() == pk.as_bytes())
+				{
+					recipients.push(pk);
+				}
+			}
+			Ok(recipients)
+		}
+
+		/// Reject a crafted broker key which would make Diffie-Hellman degenerate
+		fn check_broker(&self) -> Result<(), CypherError> {
+			if crate::is_low_order_key(&self.broker) {
+				return Err(CypherError::InvalidPubkey(self.broker.to_vec()))
+			}
+			Ok(())
+		}
+
+		/// Decrypt a single `channel`, returns `None` if it doesn't belong to the `receiver_sk`.
+		/// Parties are decrypted in any case to keep the work the same for all channels
+		fn decrypt_channel(
+			&self,
+			channel: &Channel,
+			receiver_sk: &SecretKey,
+		) -> Result<Option<Channel>, CypherError> {
+			let public_nonce = SalsaNonce::from_slice(&self.nonce);
+			let broker_pk = PublicKey::from(self.broker);
+
+			let decrypted_nonce =
+				channel.nonce.decrypt(public_nonce, &broker_pk, receiver_sk).map(Zeroizing::new);
+			let matched = decrypted_nonce.is_ok();
+			// a dummy nonce is used for the channels of other parties
+			let mut secret_nonce = match decrypted_nonce {
+				Ok(nonce) => parse_nonce(&nonce)?,
+				Err(_) => SalsaNonce::default(),
+			};
+
+			// no short-circuit on the first failure
+			let parties: Vec<_> = channel
+				.parties
+				.iter()
+				.map(|p| p.decrypt(&secret_nonce, &broker_pk, receiver_sk))
+				.collect();
+			let nonce = secret_nonce.as_slice().into();
+			secret_nonce.as_mut_slice().zeroize();
+
+			if !matched {
+				// can't decrypt - not receiver's entry
+				return Ok(None)
+			}
+			// report which party is corrupt
+			let parties = parties
+				.into_iter()
+				.enumerate()
+				.map(|(index, party)| party.map_err(|_| CypherError::PartyDecryptFailed { index }))
+				.collect::<Result<_, _>>()?;
+			Ok(Some(Channel { nonce, parties }))
+		}
+	}
+
+	/// Decode SCALE encoded metadata received over the wire and check its structure, so no
+	/// unchecked metadata exists in a client
+	impl TryFrom<&[u8]> for MessageMetadata {
+		type Error = CypherError;
+
+		fn try_from(mut bytes: &[u8]) -> Result<Self, Self::Error> {
+			let metadata = Self::decode_all(&mut bytes)
+				.map_err(|e| CypherError::MetadataMalformed(e.to_string()))?;
+			metadata.validate(&MetadataLimits::DEFAULT)?;
+			Ok(metadata)
+		}
+	}
+
+	/// Ordered list of recipients messaged together, so they don't have to be listed for every
+	/// message. Metadata lists the members in the same order, so recipients recognize a message
+	/// to the group by its `id`
+	#[derive(Debug, Clone, PartialEq)]
+	pub struct Group {
+		members: Vec<PublicKey>,
+	}
+
+	impl Group {
+		/// Fails if a member is listed twice or there are more than `MAX_RECIPIENTS` members
+		pub fn new(members: Vec<PublicKey>) -> Result<Self, CypherError> {
+			if members.len() > MAX_RECIPIENTS {
+				return Err(CypherError::TooManyRecipients {
+					got: members.len(),
+					max: MAX_RECIPIENTS,
+				})
+			}
+			for (index, member) in members.iter().enumerate() {
+				if members[..index].iter().any(|other| other.as_bytes() == member.as_bytes()) {
+					return Err(CypherError::DuplicateRecipient(member.clone()))
+				}
+			}
+			Ok(Self { members })
+		}
+
+		/// The group a decrypted `metadata` was sent to, all parties except the sender
+		pub fn of(metadata: &MessageMetadata) -> Result<Self, CypherError> {
+			let channel = metadata.channels.first().ok_or(CypherError::NotDecrypted)?;
+			let members = channel
+				.parties
+				.iter()
+				.skip(1)
+				.map(|party| {
+					<[u8; KEY_SIZE]>::try_from(party.as_slice())
+						.map(PublicKey::from)
+						.map_err(|_| CypherError::InvalidPubkey(party.clone()))
+				})
+				.collect::<Result<_, _>>()?;
+			Self::new(members)
+		}
+
+		pub fn members(&self) -> &[PublicKey] {
+			&self.members
+		}
+
+		/// Hash of the ordered member keys, the same group always gets the same id
+		pub fn id(&self) -> [u8; KEY_SIZE] {
+			let mut hash = Blake2s256::new();
+			Digest::update(&mut hash, GROUP_ID_PREFIX);
+			for member in &self.members {
+				Digest::update(&mut hash, member.as_bytes());
+			}
+			hash.finalize().into()
+		}
+	}
+
+	/// Secret keys of a user who rotated the encryption key, allows to read messages received
+	/// both before and after the rotation
+	pub struct KeyRotation {
+		pub old: SecretKey,
+		pub new: SecretKey,
+	}
+
+	impl KeyRotation {
+		/// The key able to decrypt the `metadata`, the new key is tried first
+		pub fn key_for(&self, metadata: &MessageMetadata) -> Option<&SecretKey> {
+			[&self.new, &self.old].into_iter().find(|sk| metadata.can_decrypt(sk))
+		}
+
+		/// Decrypt a batch of metadata, each one along with the key it was encrypted to
+		pub fn decrypt_batch(
+			&self,
+			batch: &[MessageMetadata],
+		) -> Vec<Result<(MessageMetadata, &SecretKey), CypherError>> {
+			batch
+				.iter()
+				.map(|metadata| {
+					let sk = self.key_for(metadata).ok_or(CypherError::NotDecrypted)?;
+					Ok((metadata.decrypt(sk)?, sk))
+				})
+				.collect()
+		}
+	}
+
+	/// Tracks public nonces used to create metadata, so a sender sending many messages doesn't
+	/// reuse a caller supplied nonce by accident
+	#[derive(Default)]
+	pub struct EncryptionSession {
+		used_nonces: HashSet<[u8; NONCE_SIZE]>,
+	}
+
+	impl EncryptionSession {
+		/// Same as `MessageMetadata::new_encrypted_with`, fails with `CypherError::NonceReused` if
+		/// the `public_nonce` was already used in this session
+		pub fn new_encrypted(
+			&mut self,
+			origin: &PublicKey,
+			public_nonce: &SalsaNonce,
+			sender_pk: &PublicKey,
+			recipients: &[&PublicKey],
+			message: &Message,
+			options: MetadataOptions,
+		) -> Result<(MessageMetadata, SalsaNonce), CypherError> {
+			let nonce: [u8; NONCE_SIZE] = public_nonce
+				.as_slice()
+				.try_into()
+				.map_err(|_| CypherError::UnexpectedNonceType(*public_nonce))?;
+			if self.used_nonces.contains(&nonce) {
+				return Err(CypherError::NonceReused(*public_nonce))
+			}
+
+			let encrypted = MessageMetadata::new_encrypted_with(
+				origin,
+				public_nonce,
+				sender_pk,
+				recipients,
+				message,
+				options,
+			)?;
+			// the nonce is only used once metadata is created
+			self.used_nonces.insert(nonce);
+			Ok(encrypted)
 		}
 	}
 
 	#[cfg(test)]
 	mod tests {
 		use super::*;
-		use crate::messages::{Message, MessageEntry, MessageType};
+		use crate::{
+			messages::{Message, MessageEntry, MessageType},
+			MetadataError,
+		};
 		use nolik_cypher::Cypher;
 
 		#[test]
@@ -199,6 +936,7 @@ mod inner_std {
 					value: "value".into(),
 					kind: MessageType::default(),
 				}],
+				headers: vec![],
 			};
 
 			let signer = SecretKey::generate(&mut OsRng);
@@ -208,6 +946,8 @@ mod inner_std {
 				&sender_pk,
 				&[&receiver_pk],
 				&message,
+				None,
+				None,
 			)
 			.unwrap();
 
@@ -225,5 +965,1198 @@ mod inner_std {
 				encrypted_message.decrypt(&secret_nonce, &sender_pk, &receiver_sk).unwrap();
 			assert_eq!(message, receiver_message);
 		}
+
+		#[test]
+		fn metadata_json_round_trip() {
+			let sender_pk = SecretKey::generate(&mut OsRng).public_key();
+			let receiver_pk = SecretKey::generate(&mut OsRng).public_key();
+			let nonce = SalsaBox::generate_nonce(&mut OsRng);
+
+			let (encrypted_metadata, _) = MessageMetadata::new_encrypted(
+				&SecretKey::generate(&mut OsRng).public_key(),
+				&nonce,
+				&sender_pk,
+				&[&receiver_pk],
+				&Message::default(),
+				None,
+				None,
+			)
+			.unwrap();
+
+			let json = encrypted_metadata.to_json().unwrap();
+			let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+			assert!(value["nonce"].is_string() && value["hash"].is_string());
+			assert!(value["channels"][0]["parties"][0].is_string());
+
+			assert_eq!(MessageMetadata::from_json(&json).unwrap(), encrypted_metadata);
+			// nonce of a wrong length is rejected
+			assert!(MessageMetadata::from_json(
+				&json.replace(&value["nonce"].to_string(), "\"AA==\"")
+			)
+			.is_err());
+		}
+
+		#[test]
+		fn verify_root_hash() {
+			let origin = SecretKey::generate(&mut OsRng).public_key();
+			let sender_sk = SecretKey::generate(&mut OsRng);
+			let sender_pk = sender_sk.public_key();
+			let receiver_sk = SecretKey::generate(&mut OsRng);
+			let receiver_pk = receiver_sk.public_key();
+
+			let message = Message {
+				entries: vec![MessageEntry {
+					key: "key".into(),
+					value: "value".into(),
+					kind: MessageType::default(),
+				}],
+				headers: vec![],
+			};
+
+			let nonce = SalsaBox::generate_nonce(&mut OsRng);
+			let (encrypted_metadata, _) = MessageMetadata::new_encrypted(
+				&origin,
+				&nonce,
+				&sender_pk,
+				&[&receiver_pk],
+				&message,
+				None,
+				None,
+			)
+			.unwrap();
+
+			let decrypted_metadata = encrypted_metadata.decrypt(&receiver_sk).unwrap();
+			decrypted_metadata
+				.verify(&origin, &message, &sender_pk, &[&receiver_pk])
+				.unwrap();
+
+			let mut tampered = message.clone();
+			tampered.entries[0].value[0] ^= 1;
+			assert!(matches!(
+				decrypted_metadata.verify(&origin, &tampered, &sender_pk, &[&receiver_pk]),
+				Err(CypherError::HashMismatch)
+			));
+
+			// a stranger decrypts no channels, so there is no secret nonce to verify with
+			let stranger_sk = SecretKey::generate(&mut OsRng);
+			assert!(matches!(
+				encrypted_metadata.decrypt(&stranger_sk).unwrap().verify(
+					&origin,
+					&message,
+					&sender_pk,
+					&[&receiver_pk]
+				),
+				Err(CypherError::NotDecrypted)
+			));
+		}
+
+		#[test]
+		fn decrypt_detailed_returns_channel_index() {
+			let sender_sk = SecretKey::generate(&mut OsRng);
+			let sender_pk = sender_sk.public_key();
+			let receivers_sk: Vec<_> = (0..2).map(|_| SecretKey::generate(&mut OsRng)).collect();
+			let receivers_pk: Vec<_> = receivers_sk.iter().map(|sk| sk.public_key()).collect();
+
+			let nonce = SalsaBox::generate_nonce(&mut OsRng);
+			let (encrypted_metadata, secret_nonce) = MessageMetadata::new_encrypted(
+				&SecretKey::generate(&mut OsRng).public_key(),
+				&nonce,
+				&sender_pk,
+				&receivers_pk.iter().collect::<Vec<_>>(),
+				&Message::default(),
+				None,
+				None,
+			)
+			.unwrap();
+
+			let parties: Vec<_> = [&sender_pk]
+				.into_iter()
+				.chain(&receivers_pk)
+				.map(|pk| pk.as_bytes().to_vec())
+				.collect();
+
+			for (sk, expected) in [(&sender_sk, 0), (&receivers_sk[0], 1), (&receivers_sk[1], 2)] {
+				let channels = encrypted_metadata.decrypt_detailed(sk).unwrap();
+				assert_eq!(channels.len(), 1);
+
+				let (index, channel) = &channels[0];
+				assert_eq!(*index, expected);
+				assert_eq!(channel.nonce, secret_nonce.to_vec());
+				assert_eq!(channel.parties, parties);
+			}
+
+			let stranger_sk = SecretKey::generate(&mut OsRng);
+			assert!(encrypted_metadata.decrypt_detailed(&stranger_sk).unwrap().is_empty());
+		}
+
+		#[test]
+		fn decrypt_as_sender_recovers_secret_nonce() {
+			let sender_sk = SecretKey::generate(&mut OsRng);
+			let sender_pk = sender_sk.public_key();
+			let receiver_sk = SecretKey::generate(&mut OsRng);
+			let receiver_pk = receiver_sk.public_key();
+
+			let nonce = SalsaBox::generate_nonce(&mut OsRng);
+			let (encrypted_metadata, secret_nonce) = MessageMetadata::new_encrypted(
+				&SecretKey::generate(&mut OsRng).public_key(),
+				&nonce,
+				&sender_pk,
+				&[&receiver_pk],
+				&Message::default(),
+				None,
+				None,
+			)
+			.unwrap();
+
+			let sender_metadata = encrypted_metadata.decrypt_as_sender(&sender_sk).unwrap();
+			let receiver_metadata = encrypted_metadata.decrypt(&receiver_sk).unwrap();
+			assert_eq!(sender_metadata.channels.len(), 1);
+			assert_eq!(sender_metadata.channels[0].nonce, secret_nonce.to_vec());
+			assert_eq!(sender_metadata.channels, receiver_metadata.channels);
+
+			// the receiver's key doesn't open the sender's channel
+			assert!(matches!(
+				encrypted_metadata.decrypt_as_sender(&receiver_sk),
+				Err(CypherError::NotDecrypted)
+			));
+		}
+
+		#[test]
+		fn reply_preserves_parent_hash() {
+			let origin = SecretKey::generate(&mut OsRng).public_key();
+			let sender_pk = SecretKey::generate(&mut OsRng).public_key();
+			let receiver_sk = SecretKey::generate(&mut OsRng);
+			let receiver_pk = receiver_sk.public_key();
+			let message = Message::default();
+
+			let (parent, _) = MessageMetadata::new_encrypted(
+				&origin,
+				&SalsaBox::generate_nonce(&mut OsRng),
+				&sender_pk,
+				&[&receiver_pk],
+				&message,
+				None,
+				None,
+			)
+			.unwrap();
+			let (reply, _) = MessageMetadata::new_encrypted(
+				&origin,
+				&SalsaBox::generate_nonce(&mut OsRng),
+				&sender_pk,
+				&[&receiver_pk],
+				&message,
+				Some(parent.hash),
+				None,
+			)
+			.unwrap();
+
+			let decrypted_reply = MessageMetadata::from_json(&reply.to_json().unwrap())
+				.unwrap()
+				.decrypt(&receiver_sk)
+				.unwrap();
+			assert_eq!(decrypted_reply.in_reply_to, Some(parent.hash));
+			decrypted_reply.verify(&origin, &message, &sender_pk, &[&receiver_pk]).unwrap();
+
+			// the parent hash is covered by the root hash
+			let tampered = MessageMetadata { in_reply_to: Some([1; KEY_SIZE]), ..decrypted_reply };
+			assert!(matches!(
+				tampered.verify(&origin, &message, &sender_pk, &[&receiver_pk]),
+				Err(CypherError::HashMismatch)
+			));
+		}
+
+		#[test]
+		fn sender_hash_chain() {
+			let origin = SecretKey::generate(&mut OsRng).public_key();
+			let sender_pk = SecretKey::generate(&mut OsRng).public_key();
+			let receiver_sk = SecretKey::generate(&mut OsRng);
+			let receiver_pk = receiver_sk.public_key();
+			let message = |value: &str| Message {
+				entries: vec![MessageEntry {
+					key: "key".into(),
+					value: value.into(),
+					kind: MessageType::default(),
+				}],
+				headers: vec![],
+			};
+
+			// each message is chained to the previous one
+			let mut chain = vec![];
+			let mut prev_hash = None;
+			for value in ["first", "second", "third"] {
+				let (metadata, _) = MessageMetadata::new_encrypted(
+					&origin,
+					&SalsaBox::generate_nonce(&mut OsRng),
+					&sender_pk,
+					&[&receiver_pk],
+					&message(value),
+					None,
+					prev_hash,
+				)
+				.unwrap();
+				prev_hash = Some(metadata.hash);
+				chain.push((metadata.decrypt(&receiver_sk).unwrap(), message(value)));
+			}
+
+			// the recipient verifies each message against the hash it recomputed for the previous
+			let verify_chain = |chain: &[(MessageMetadata, Message)]| {
+				let mut prev_hash = None;
+				for (metadata, message) in chain {
+					metadata.verify_chained(
+						&origin,
+						message,
+						&sender_pk,
+						&[&receiver_pk],
+						prev_hash.as_ref(),
+					)?;
+					prev_hash = Some(metadata.hash);
+				}
+				Ok::<_, CypherError>(())
+			};
+			verify_chain(&chain).unwrap();
+
+			// a chained message doesn't verify without the previous hash
+			let (third, third_message) = &chain[2];
+			assert!(matches!(
+				third.verify(&origin, third_message, &sender_pk, &[&receiver_pk]),
+				Err(CypherError::HashMismatch)
+			));
+
+			// a different second message, itself correctly chained to the first one, no longer
+			// has the hash the third message follows
+			let (forged, _) = MessageMetadata::new_encrypted(
+				&origin,
+				&SalsaBox::generate_nonce(&mut OsRng),
+				&sender_pk,
+				&[&receiver_pk],
+				&message("altered"),
+				None,
+				Some(chain[0].0.hash),
+			)
+			.unwrap();
+			let mut altered = chain.clone();
+			altered[1] = (forged.decrypt(&receiver_sk).unwrap(), message("altered"));
+			verify_chain(&altered[..2]).unwrap();
+			assert!(matches!(verify_chain(&altered), Err(CypherError::HashMismatch)));
+		}
+
+		#[test]
+		fn self_message() {
+			let origin = SecretKey::generate(&mut OsRng).public_key();
+			let sender_sk = SecretKey::generate(&mut OsRng);
+			let sender_pk = sender_sk.public_key();
+			let message = Message {
+				entries: vec![MessageEntry {
+					key: "note".into(),
+					value: "to myself".into(),
+					kind: MessageType::default(),
+				}],
+				headers: vec![],
+			};
+
+			let (metadata, secret_nonce) = MessageMetadata::new_self_message(
+				&origin,
+				&SalsaBox::generate_nonce(&mut OsRng),
+				&sender_pk,
+				&message,
+				None,
+			)
+			.unwrap();
+			assert_eq!(metadata.channels.len(), 1);
+			assert_eq!(metadata.channels[0].parties.len(), 1);
+			assert_eq!(metadata.validate(&MetadataLimits::DEFAULT), Ok(()));
+			let encrypted = message.encrypt(&secret_nonce, &sender_pk, &sender_sk).unwrap();
+
+			for decrypted in [
+				metadata.decrypt(&sender_sk).unwrap(),
+				metadata.decrypt_as_sender(&sender_sk).unwrap(),
+			] {
+				assert_eq!(decrypted.channels.len(), 1);
+				assert_eq!(decrypted.sender_pk().unwrap().as_bytes(), sender_pk.as_bytes());
+				let channel_nonce = SalsaNonce::from_slice(&decrypted.channels[0].nonce);
+				assert_eq!(
+					encrypted.decrypt(channel_nonce, &sender_pk, &sender_sk).unwrap(),
+					message
+				);
+				decrypted.verify(&origin, &message, &sender_pk, &[]).unwrap();
+			}
+
+			let stranger_sk = SecretKey::generate(&mut OsRng);
+			assert!(metadata.decrypt(&stranger_sk).unwrap().channels.is_empty());
+		}
+
+		#[test]
+		fn ephemeral_sender() {
+			let origin = SecretKey::generate(&mut OsRng).public_key();
+			let receiver_sk = SecretKey::generate(&mut OsRng);
+			let receiver_pk = receiver_sk.public_key();
+			let message = Message {
+				entries: vec![MessageEntry {
+					key: "key".into(),
+					value: "value".into(),
+					kind: MessageType::default(),
+				}],
+				headers: vec![],
+			};
+
+			let new_ephemeral = || {
+				MessageMetadata::new_ephemeral(
+					&origin,
+					&SalsaBox::generate_nonce(&mut OsRng),
+					&[&receiver_pk],
+					&message,
+					None,
+				)
+				.unwrap()
+			};
+			let (metadata, secret_nonce, sender_sk) = new_ephemeral();
+			let encrypted = message.encrypt(&secret_nonce, &receiver_pk, &sender_sk).unwrap();
+
+			// the recipient only needs the metadata to find the sender key
+			let decrypted = metadata.decrypt(&receiver_sk).unwrap();
+			let sender_pk = decrypted.sender_pk().unwrap();
+			assert_eq!(sender_pk.as_bytes(), sender_sk.public_key().as_bytes());
+			let channel_nonce = SalsaNonce::from_slice(&decrypted.channels[0].nonce);
+			assert_eq!(
+				encrypted.decrypt(channel_nonce, &sender_pk, &receiver_sk).unwrap(),
+				message
+			);
+			decrypted.verify(&origin, &message, &sender_pk, &[&receiver_pk]).unwrap();
+
+			// every message gets a new sender key
+			let (_, _, other_sk) = new_ephemeral();
+			assert_ne!(other_sk.public_key().as_bytes(), sender_pk.as_bytes());
+		}
+
+		#[test]
+		fn channel_party_mismatch() {
+			let sender_pk = SecretKey::generate(&mut OsRng).public_key();
+			let receiver_pk = SecretKey::generate(&mut OsRng).public_key();
+			let (metadata, _) = MessageMetadata::new_encrypted(
+				&sender_pk,
+				&SalsaBox::generate_nonce(&mut OsRng),
+				&sender_pk,
+				&[&receiver_pk],
+				&Message::default(),
+				None,
+				None,
+			)
+			.unwrap();
+			assert!(metadata.check_parties().is_ok());
+
+			let mut mismatched = metadata.clone();
+			mismatched.channels[1].parties.pop();
+			assert!(matches!(
+				mismatched.check_parties(),
+				Err(CypherError::ChannelPartyMismatch { index: 1, parties: 1, channels: 2 })
+			));
+
+			let mut extra_channel = metadata;
+			extra_channel.channels.push(extra_channel.channels[0].clone());
+			assert!(matches!(
+				extra_channel.check_parties(),
+				Err(CypherError::ChannelPartyMismatch { index: 0, parties: 2, channels: 3 })
+			));
+		}
+
+		proptest::proptest! {
+			#![proptest_config(proptest::test_runner::Config::with_cases(32))]
+
+			/// Every recipient, including none at all, recovers the secret nonce and the message
+			/// whatever the entries are, empty ones included
+			#[test]
+			fn encrypt_decrypt_round_trip(
+				entries in proptest::collection::vec(
+					(
+						proptest::collection::vec(proptest::num::u8::ANY, 0..16),
+						proptest::collection::vec(proptest::num::u8::ANY, 0..64),
+					),
+					0..4,
+				),
+				recipient_count in 0usize..4,
+				public_nonce in proptest::array::uniform24(proptest::num::u8::ANY),
+			) {
+				let origin = SecretKey::generate(&mut OsRng).public_key();
+				let sender_sk = SecretKey::generate(&mut OsRng);
+				let sender_pk = sender_sk.public_key();
+				let receiver_sks: Vec<_> =
+					(0..recipient_count).map(|_| SecretKey::generate(&mut OsRng)).collect();
+				let receiver_pks: Vec<_> = receiver_sks.iter().map(|sk| sk.public_key()).collect();
+				let message = Message {
+					entries: entries
+						.into_iter()
+						.map(|(key, value)| MessageEntry {
+							key,
+							value,
+							kind: MessageType::default(),
+						})
+						.collect(),
+					headers: vec![],
+				};
+
+				let (metadata, secret_nonce) = MessageMetadata::new_encrypted(
+					&origin,
+					SalsaNonce::from_slice(&public_nonce),
+					&sender_pk,
+					&receiver_pks.iter().collect::<Vec<_>>(),
+					&message,
+					None,
+					None,
+				)
+				.unwrap();
+				proptest::prop_assert_eq!(metadata.channels.len(), recipient_count + 1);
+
+				let sent = metadata.decrypt_as_sender(&sender_sk).unwrap();
+				proptest::prop_assert_eq!(&sent.channels[0].nonce[..], secret_nonce.as_slice());
+
+				for (receiver_sk, receiver_pk) in receiver_sks.iter().zip(&receiver_pks) {
+					let decrypted = metadata.decrypt(receiver_sk).unwrap();
+					proptest::prop_assert_eq!(decrypted.channels.len(), 1);
+					let channel_nonce = SalsaNonce::from_slice(&decrypted.channels[0].nonce);
+					proptest::prop_assert_eq!(channel_nonce.as_slice(), secret_nonce.as_slice());
+					let decrypted_pk = decrypted.sender_pk().unwrap();
+					proptest::prop_assert_eq!(decrypted_pk.as_bytes(), sender_pk.as_bytes());
+
+					let encrypted =
+						message.encrypt(&secret_nonce, receiver_pk, &sender_sk).unwrap();
+					let received =
+						encrypted.decrypt(channel_nonce, &sender_pk, receiver_sk).unwrap();
+					proptest::prop_assert_eq!(&received, &message);
+					let recipients: Vec<_> = receiver_pks.iter().collect();
+					decrypted.verify(&origin, &received, &sender_pk, &recipients).unwrap();
+				}
+			}
+		}
+
+		#[test]
+		fn root_hash_algorithms() {
+			let keys: Vec<_> =
+				(0..4).map(|_| SecretKey::generate(&mut OsRng).public_key()).collect();
+			let public_nonce = SalsaBox::generate_nonce(&mut OsRng);
+			let secret_nonce = SalsaBox::generate_nonce(&mut OsRng);
+			let message = Message {
+				entries: vec![MessageEntry {
+					key: "key".into(),
+					value: "value".into(),
+					kind: MessageType::default(),
+				}],
+				headers: vec![],
+			};
+			let root_hash = |hash_algo| {
+				MessageMetadata::compute_root_hash(
+					MessageKeys {
+						origin: &keys[0],
+						sender_pk: &keys[1],
+						broker_pk: &keys[2],
+						recipients: &[&keys[3]],
+					},
+					&public_nonce,
+					&secret_nonce,
+					&message,
+					&MetadataOptions { hash_algo, ..Default::default() },
+				)
+			};
+
+			let blake2s = root_hash(HashAlgo::Blake2s256);
+			let blake2b = root_hash(HashAlgo::Blake2b256);
+			assert_ne!(blake2s, blake2b);
+			assert_eq!(blake2s, root_hash(HashAlgo::Blake2s256));
+			assert_eq!(blake2b, root_hash(HashAlgo::Blake2b256));
+
+			// the recorded digest is used to verify
+			let receiver_sk = SecretKey::generate(&mut OsRng);
+			let receiver_pk = receiver_sk.public_key();
+			let (metadata, _) = MessageMetadata::new_encrypted_with(
+				&keys[0],
+				&public_nonce,
+				&keys[1],
+				&[&receiver_pk],
+				&message,
+				MetadataOptions { hash_algo: HashAlgo::Blake2b256, ..Default::default() },
+			)
+			.unwrap();
+			assert_eq!(metadata.hash_algo, HashAlgo::Blake2b256);
+			let metadata = metadata.decrypt(&receiver_sk).unwrap();
+			metadata.verify(&keys[0], &message, &keys[1], &[&receiver_pk]).unwrap();
+
+			let wrong_algo = MessageMetadata { hash_algo: HashAlgo::Blake2s256, ..metadata };
+			assert!(matches!(
+				wrong_algo.verify(&keys[0], &message, &keys[1], &[&receiver_pk]),
+				Err(CypherError::HashMismatch)
+			));
+		}
+
+		#[test]
+		fn message_to_group() {
+			let origin = SecretKey::generate(&mut OsRng).public_key();
+			let sender_pk = SecretKey::generate(&mut OsRng).public_key();
+			let members_sk: Vec<_> = (0..3).map(|_| SecretKey::generate(&mut OsRng)).collect();
+			let members: Vec<_> = members_sk.iter().map(|sk| sk.public_key()).collect();
+			let group = Group::new(members.clone()).unwrap();
+
+			let (to_group, _) = MessageMetadata::new_encrypted_to_group(
+				&origin,
+				&SalsaBox::generate_nonce(&mut OsRng),
+				&sender_pk,
+				&group,
+				&Message::default(),
+				None,
+				None,
+			)
+			.unwrap();
+			let (listed, _) = MessageMetadata::new_encrypted(
+				&origin,
+				&SalsaBox::generate_nonce(&mut OsRng),
+				&sender_pk,
+				&members.iter().collect::<Vec<_>>(),
+				&Message::default(),
+				None,
+				None,
+			)
+			.unwrap();
+
+			// channels are encrypted with different brokers, but decrypt to the same parties
+			assert_eq!(to_group.channels.len(), listed.channels.len());
+			for sk in &members_sk {
+				let to_group = to_group.decrypt(sk).unwrap();
+				let listed = listed.decrypt(sk).unwrap();
+				assert_eq!(to_group.channels[0].parties, listed.channels[0].parties);
+				assert_eq!(Group::of(&to_group).unwrap().id(), group.id());
+			}
+
+			// the order of members matters
+			let reordered = Group::new(members.iter().rev().cloned().collect()).unwrap();
+			assert_ne!(reordered.id(), group.id());
+			assert!(matches!(
+				Group::new(vec![members[0].clone(), members[0].clone()]),
+				Err(CypherError::DuplicateRecipient(_))
+			));
+		}
+
+		#[test]
+		fn blinded_subject() {
+			let origin = SecretKey::generate(&mut OsRng).public_key();
+			let sender_sk = SecretKey::generate(&mut OsRng);
+			let sender_pk = sender_sk.public_key();
+			let receiver_sk = SecretKey::generate(&mut OsRng);
+			let receiver_pk = receiver_sk.public_key();
+			let entry = |key: &str, value: &str| MessageEntry {
+				key: key.into(),
+				value: value.into(),
+				kind: MessageType::default(),
+			};
+			let message = Message {
+				entries: vec![entry("body", "a long message body")],
+				headers: vec![entry("subject", "greetings")],
+			};
+			let new_metadata = |message: &Message| {
+				MessageMetadata::new_encrypted(
+					&origin,
+					&SalsaBox::generate_nonce(&mut OsRng),
+					&sender_pk,
+					&[&receiver_pk],
+					message,
+					None,
+					None,
+				)
+				.unwrap()
+				.0
+			};
+
+			// the subject is previewed without the message
+			let metadata = new_metadata(&message);
+			assert_eq!(
+				metadata.decrypt_subject(&receiver_sk).unwrap(),
+				Some(b"greetings".to_vec())
+			);
+			assert!(matches!(
+				metadata.decrypt_subject(&SecretKey::generate(&mut OsRng)),
+				Err(CypherError::NotDecrypted)
+			));
+			let decrypted = metadata.decrypt(&receiver_sk).unwrap();
+			decrypted.verify(&origin, &message, &sender_pk, &[&receiver_pk]).unwrap();
+
+			let mut tampered = decrypted.clone();
+			tampered.subject.as_mut().unwrap()[0] ^= 1;
+			assert!(matches!(
+				tampered.verify(&origin, &message, &sender_pk, &[&receiver_pk]),
+				Err(CypherError::HashMismatch)
+			));
+
+			// a long subject is truncated to fit the fixed size tag
+			let long = "s".repeat(SUBJECT_SIZE * 2);
+			let long_message =
+				Message { headers: vec![entry("subject", &long)], ..message.clone() };
+			let metadata = new_metadata(&long_message);
+			assert_eq!(
+				metadata.decrypt_subject(&receiver_sk).unwrap(),
+				Some(long.as_bytes()[..SUBJECT_SIZE - 1].to_vec())
+			);
+			metadata
+				.decrypt(&receiver_sk)
+				.unwrap()
+				.verify(&origin, &long_message, &sender_pk, &[&receiver_pk])
+				.unwrap();
+
+			let no_subject = new_metadata(&Message { headers: vec![], ..message });
+			assert_eq!(no_subject.subject, None);
+			assert_eq!(no_subject.decrypt_subject(&receiver_sk).unwrap(), None);
+		}
+
+		#[test]
+		fn metadata_from_bytes() {
+			let sender_pk = SecretKey::generate(&mut OsRng).public_key();
+			let receiver_pk = SecretKey::generate(&mut OsRng).public_key();
+			let (metadata, _) = MessageMetadata::new_encrypted(
+				&sender_pk,
+				&SalsaBox::generate_nonce(&mut OsRng),
+				&sender_pk,
+				&[&receiver_pk],
+				&Message::default(),
+				None,
+				None,
+			)
+			.unwrap();
+
+			let bytes = metadata.encode();
+			assert_eq!(MessageMetadata::try_from(bytes.as_slice()).unwrap(), metadata);
+
+			// truncated or with trailing bytes
+			let trailing = [bytes.as_slice(), &[0]].concat();
+			for bytes in [&bytes[..bytes.len() - 1], &trailing[..]] {
+				assert!(matches!(
+					MessageMetadata::try_from(bytes),
+					Err(CypherError::MetadataMalformed(_))
+				));
+			}
+
+			// decodable, but rejected by the chain
+			let no_channels = MessageMetadata { channels: vec![], ..metadata.clone() }.encode();
+			let unset_hash = MessageMetadata { hash: [0; KEY_SIZE], ..metadata }.encode();
+			for (bytes, expected) in
+				[(no_channels, MetadataError::NoChannels), (unset_hash, MetadataError::InvalidHash)]
+			{
+				match MessageMetadata::try_from(bytes.as_slice()) {
+					Err(CypherError::InvalidMetadata(e)) =>
+						assert_eq!(e.downcast_ref::<MetadataError>(), Some(&expected)),
+					other => panic!("unexpected result {:?}", other),
+				}
+			}
+		}
+
+		#[test]
+		fn sender_pk_of_decrypted_metadata() {
+			let sender_pk = SecretKey::generate(&mut OsRng).public_key();
+			let receiver_sk = SecretKey::generate(&mut OsRng);
+			let (metadata, _) = MessageMetadata::new_encrypted(
+				&sender_pk,
+				&SalsaBox::generate_nonce(&mut OsRng),
+				&sender_pk,
+				&[&receiver_sk.public_key()],
+				&Message::default(),
+				None,
+				None,
+			)
+			.unwrap();
+
+			let decrypted = metadata.decrypt(&receiver_sk).unwrap();
+			assert_eq!(decrypted.sender_pk().unwrap().as_bytes(), sender_pk.as_bytes());
+
+			assert!(matches!(metadata.sender_pk(), Err(CypherError::NotDecrypted)));
+			let no_channels = MessageMetadata { channels: vec![], ..decrypted };
+			assert!(matches!(no_channels.sender_pk(), Err(CypherError::NotDecrypted)));
+		}
+
+		#[test]
+		fn recipients_among_keys() {
+			let sender_pk = SecretKey::generate(&mut OsRng).public_key();
+			let sks: Vec<_> = (0..3).map(|_| SecretKey::generate(&mut OsRng)).collect();
+			let (metadata, _) = MessageMetadata::new_encrypted(
+				&sender_pk,
+				&SalsaBox::generate_nonce(&mut OsRng),
+				&sender_pk,
+				&[&sks[2].public_key(), &sks[0].public_key()],
+				&Message::default(),
+				None,
+				None,
+			)
+			.unwrap();
+
+			assert_eq!(metadata.recipients_among(&sks), vec![0, 2]);
+			assert!(metadata.recipients_among(&sks[1..2]).is_empty());
+			assert!(metadata.recipients_among(&[]).is_empty());
+		}
+
+		#[test]
+		fn root_hash_covers_headers() {
+			let origin = SecretKey::generate(&mut OsRng).public_key();
+			let sender_pk = SecretKey::generate(&mut OsRng).public_key();
+			let receiver_sk = SecretKey::generate(&mut OsRng);
+			let receiver_pk = receiver_sk.public_key();
+			let header = |value: &str| MessageEntry {
+				key: "subject".into(),
+				value: value.into(),
+				kind: MessageType::default(),
+			};
+			let message = Message { entries: vec![], headers: vec![header("greetings")] };
+
+			let (metadata, _) = MessageMetadata::new_encrypted(
+				&origin,
+				&SalsaBox::generate_nonce(&mut OsRng),
+				&sender_pk,
+				&[&receiver_pk],
+				&message,
+				None,
+				None,
+			)
+			.unwrap();
+			let metadata = metadata.decrypt(&receiver_sk).unwrap();
+			metadata.verify(&origin, &message, &sender_pk, &[&receiver_pk]).unwrap();
+
+			let tampered = Message { headers: vec![header("tampered")], ..message.clone() };
+			assert!(matches!(
+				metadata.verify(&origin, &tampered, &sender_pk, &[&receiver_pk]),
+				Err(CypherError::HashMismatch)
+			));
+			// headers are not interchangeable with entries
+			let moved = Message { entries: message.headers.clone(), headers: vec![] };
+			assert!(matches!(
+				metadata.verify(&origin, &moved, &sender_pk, &[&receiver_pk]),
+				Err(CypherError::HashMismatch)
+			));
+		}
+
+		#[test]
+		fn corrupt_party_is_reported() {
+			let sender_pk = SecretKey::generate(&mut OsRng).public_key();
+			let receiver_sk = SecretKey::generate(&mut OsRng);
+			let other_pk = SecretKey::generate(&mut OsRng).public_key();
+
+			let (mut metadata, _) = MessageMetadata::new_encrypted(
+				&sender_pk,
+				&SalsaBox::generate_nonce(&mut OsRng),
+				&sender_pk,
+				&[&receiver_sk.public_key(), &other_pk],
+				&Message::default(),
+				None,
+				None,
+			)
+			.unwrap();
+			// the receiver's channel is the second one
+			metadata.channels[1].parties[2][0] ^= 1;
+
+			assert!(matches!(
+				metadata.decrypt(&receiver_sk),
+				Err(CypherError::PartyDecryptFailed { index: 2 })
+			));
+		}
+
+		#[test]
+		fn truncated_nonce_is_reported() {
+			let sender_pk = SecretKey::generate(&mut OsRng).public_key();
+			let receiver_sk = SecretKey::generate(&mut OsRng);
+			let broker_sk = SecretKey::generate(&mut OsRng);
+			let message = Message::default();
+
+			let (mut metadata, _) = MessageMetadata::new_encrypted(
+				&sender_pk,
+				&SalsaBox::generate_nonce(&mut OsRng),
+				&sender_pk,
+				&[&receiver_sk.public_key()],
+				&message,
+				None,
+				None,
+			)
+			.unwrap();
+			let decrypted = metadata.decrypt(&receiver_sk).unwrap();
+
+			// the receiver's channel holds a nonce one byte short
+			metadata.broker = *broker_sk.public_key().as_bytes();
+			metadata.channels[1].nonce = [1; NONCE_SIZE - 1]
+				.as_slice()
+				.encrypt(
+					SalsaNonce::from_slice(&metadata.nonce),
+					&receiver_sk.public_key(),
+					&broker_sk,
+				)
+				.unwrap();
+			assert!(matches!(
+				metadata.decrypt(&receiver_sk),
+				Err(CypherError::InvalidNonce(nonce)) if nonce.len() == NONCE_SIZE - 1
+			));
+
+			let mut truncated = decrypted;
+			truncated.channels[0].nonce.pop();
+			assert!(matches!(
+				truncated.verify(&sender_pk, &message, &sender_pk, &[&receiver_sk.public_key()]),
+				Err(CypherError::InvalidNonce(_))
+			));
+		}
+
+		#[test]
+		fn reply_to_all() {
+			let sender_pk = SecretKey::generate(&mut OsRng).public_key();
+			let replier_sk = SecretKey::generate(&mut OsRng);
+			let other_pk = SecretKey::generate(&mut OsRng).public_key();
+
+			let (metadata, _) = MessageMetadata::new_encrypted(
+				&sender_pk,
+				&SalsaBox::generate_nonce(&mut OsRng),
+				&sender_pk,
+				&[&replier_sk.public_key(), &other_pk],
+				&Message::default(),
+				None,
+				None,
+			)
+			.unwrap();
+
+			// encrypted parties can't be parsed
+			assert!(metadata.reply_recipients(&replier_sk.public_key()).is_err());
+
+			let decrypted = metadata.decrypt(&replier_sk).unwrap();
+			let recipients = decrypted.reply_recipients(&replier_sk.public_key()).unwrap();
+			assert_eq!(
+				recipients.iter().map(|pk| *pk.as_bytes()).collect::<Vec<_>>(),
+				vec![*sender_pk.as_bytes(), *other_pk.as_bytes()]
+			);
+			assert!(recipients.iter().all(|pk| pk.as_bytes() != &metadata.broker));
+		}
+
+		#[test]
+		fn decrypt_all_skips_other_recipients() {
+			let sender_pk = SecretKey::generate(&mut OsRng).public_key();
+			let receiver_sk = SecretKey::generate(&mut OsRng);
+			let stranger_pk = SecretKey::generate(&mut OsRng).public_key();
+
+			let mut secret_nonces = vec![];
+			let inbox: Vec<_> = [true, false, false, true, false, true]
+				.into_iter()
+				.map(|matching| {
+					let recipient =
+						if matching { receiver_sk.public_key() } else { stranger_pk.clone() };
+					let (metadata, secret_nonce) = MessageMetadata::new_encrypted(
+						&sender_pk,
+						&SalsaBox::generate_nonce(&mut OsRng),
+						&sender_pk,
+						&[&recipient],
+						&Message::default(),
+						None,
+						None,
+					)
+					.unwrap();
+					if matching {
+						secret_nonces.push(secret_nonce.to_vec());
+					}
+					metadata
+				})
+				.collect();
+
+			let decrypted: Vec<_> = MessageMetadata::decrypt_all(&inbox, &receiver_sk)
+				.map(|metadata| metadata.unwrap().channels[0].nonce.clone())
+				.collect();
+			assert_eq!(decrypted, secret_nonces);
+
+			// metadata is only pulled from the source as far as the next match
+			let pulled = std::cell::Cell::new(0);
+			let mut stream = MessageMetadata::decrypt_all(
+				inbox.iter().inspect(|_| pulled.set(pulled.get() + 1)),
+				&receiver_sk,
+			);
+			assert!(stream.next().unwrap().is_ok());
+			assert_eq!(pulled.get(), 1);
+			assert!(stream.next().unwrap().is_ok());
+			assert_eq!(pulled.get(), 4);
+			drop(stream);
+
+			let stranger_sk = SecretKey::generate(&mut OsRng);
+			assert_eq!(MessageMetadata::decrypt_all(inbox, &stranger_sk).count(), 0);
+		}
+
+		#[test]
+		fn decrypt_any_key() {
+			let sender_pk = SecretKey::generate(&mut OsRng).public_key();
+			let sks: Vec<_> = (0..3).map(|_| SecretKey::generate(&mut OsRng)).collect();
+
+			let (metadata, secret_nonce) = MessageMetadata::new_encrypted(
+				&sender_pk,
+				&SalsaBox::generate_nonce(&mut OsRng),
+				&sender_pk,
+				&[&sks[1].public_key()],
+				&Message::default(),
+				None,
+				None,
+			)
+			.unwrap();
+
+			let (index, decrypted) = metadata.decrypt_any(&sks).unwrap();
+			assert_eq!(index, 1);
+			assert_eq!(decrypted.channels.len(), 1);
+			assert_eq!(decrypted.channels[0].nonce, secret_nonce.as_slice());
+
+			assert!(matches!(
+				metadata.decrypt_any(&[sks[0].clone(), sks[2].clone()]),
+				Err(CypherError::DecryptionFailed(_))
+			));
+			assert!(matches!(metadata.decrypt_any(&[]), Err(CypherError::DecryptionFailed(_))));
+		}
+
+		#[test]
+		fn json_entries_are_canonicalized() {
+			let keys: Vec<_> =
+				(0..4).map(|_| SecretKey::generate(&mut OsRng).public_key()).collect();
+			let public_nonce = SalsaBox::generate_nonce(&mut OsRng);
+			let secret_nonce = SalsaBox::generate_nonce(&mut OsRng);
+			let root_hash = |value: &str, kind: MessageType| {
+				let entry = MessageEntry { key: "data".into(), value: value.into(), kind };
+				let message = Message { entries: vec![entry], headers: vec![] };
+				MessageMetadata::compute_root_hash(
+					MessageKeys {
+						origin: &keys[0],
+						sender_pk: &keys[1],
+						broker_pk: &keys[2],
+						recipients: &[&keys[3]],
+					},
+					&public_nonce,
+					&secret_nonce,
+					&message,
+					&MetadataOptions::default(),
+				)
+			};
+
+			let json = r#"{"b": 1, "a": {"d": [1, 2], "c": null}}"#;
+			let reordered = r#"{"a":{"c":null,"d":[1,2]},"b":1}"#;
+			assert_eq!(root_hash(json, MessageType::Json), root_hash(reordered, MessageType::Json));
+			// binary entries are hashed as is
+			assert_ne!(
+				root_hash(json, MessageType::Binary),
+				root_hash(reordered, MessageType::Binary)
+			);
+		}
+
+		#[test]
+		fn decrypt_multi_channel_metadata() {
+			let sender_sk = SecretKey::generate(&mut OsRng);
+			let receivers_sk: Vec<_> = (0..4).map(|_| SecretKey::generate(&mut OsRng)).collect();
+			let receivers_pk: Vec<_> = receivers_sk.iter().map(|sk| sk.public_key()).collect();
+
+			let (encrypted_metadata, secret_nonce) = MessageMetadata::new_encrypted(
+				&SecretKey::generate(&mut OsRng).public_key(),
+				&SalsaBox::generate_nonce(&mut OsRng),
+				&sender_sk.public_key(),
+				&receivers_pk.iter().collect::<Vec<_>>(),
+				&Message::default(),
+				None,
+				None,
+			)
+			.unwrap();
+			assert_eq!(encrypted_metadata.channels.len(), 5);
+
+			for sk in [&sender_sk].into_iter().chain(&receivers_sk) {
+				let decrypted_metadata = encrypted_metadata.decrypt(sk).unwrap();
+				assert_eq!(decrypted_metadata.channels.len(), 1);
+				assert_eq!(decrypted_metadata.channels[0].nonce, secret_nonce.to_vec());
+				assert_eq!(decrypted_metadata.channels[0].parties.len(), 5);
+			}
+
+			let stranger_sk = SecretKey::generate(&mut OsRng);
+			assert!(encrypted_metadata.decrypt(&stranger_sk).unwrap().channels.is_empty());
+		}
+
+		#[test]
+		fn duplicate_recipients_are_rejected() {
+			let sender_pk = SecretKey::generate(&mut OsRng).public_key();
+			let receiver_pk = SecretKey::generate(&mut OsRng).public_key();
+			let new_encrypted = |recipients: &[&PublicKey]| {
+				MessageMetadata::new_encrypted(
+					&SecretKey::generate(&mut OsRng).public_key(),
+					&SalsaBox::generate_nonce(&mut OsRng),
+					&sender_pk,
+					recipients,
+					&Message::default(),
+					None,
+					None,
+				)
+			};
+
+			assert!(matches!(
+				new_encrypted(&[&receiver_pk, &receiver_pk]),
+				Err(CypherError::DuplicateRecipient(pk)) if pk.as_bytes() == receiver_pk.as_bytes()
+			));
+			assert!(matches!(
+				new_encrypted(&[&receiver_pk, &sender_pk]),
+				Err(CypherError::DuplicateRecipient(pk)) if pk.as_bytes() == sender_pk.as_bytes()
+			));
+			assert_eq!(new_encrypted(&[&receiver_pk]).unwrap().0.channels.len(), 2);
+		}
+
+		#[test]
+		fn low_order_broker_is_rejected() {
+			let receiver_sk = SecretKey::generate(&mut OsRng);
+			let (metadata, _) = MessageMetadata::new_encrypted(
+				&SecretKey::generate(&mut OsRng).public_key(),
+				&SalsaBox::generate_nonce(&mut OsRng),
+				&SecretKey::generate(&mut OsRng).public_key(),
+				&[&receiver_sk.public_key()],
+				&Message::default(),
+				None,
+				None,
+			)
+			.unwrap();
+			assert!(metadata.can_decrypt(&receiver_sk));
+
+			// the all-zero key and the same with the ignored most significant bit set
+			let mut high_bit = [0; KEY_SIZE];
+			high_bit[KEY_SIZE - 1] = 0x80;
+			for broker in [[0; KEY_SIZE], high_bit] {
+				let metadata = MessageMetadata { broker, ..metadata.clone() };
+				assert!(matches!(
+					metadata.decrypt(&receiver_sk),
+					Err(CypherError::InvalidPubkey(key)) if key == broker
+				));
+				assert!(matches!(
+					metadata.decrypt_as_sender(&receiver_sk),
+					Err(CypherError::InvalidPubkey(_))
+				));
+				assert!(!metadata.can_decrypt(&receiver_sk));
+			}
+		}
+
+		#[test]
+		fn recipients_limit() {
+			let sender_pk = SecretKey::generate(&mut OsRng).public_key();
+			let recipients: Vec<_> = (0..=MAX_RECIPIENTS)
+				.map(|_| SecretKey::generate(&mut OsRng).public_key())
+				.collect();
+			let new_encrypted = |recipients: &[PublicKey]| {
+				MessageMetadata::new_encrypted(
+					&sender_pk,
+					&SalsaBox::generate_nonce(&mut OsRng),
+					&sender_pk,
+					&recipients.iter().collect::<Vec<_>>(),
+					&Message::default(),
+					None,
+					None,
+				)
+			};
+
+			let (metadata, _) = new_encrypted(&recipients[..MAX_RECIPIENTS]).unwrap();
+			assert_eq!(metadata.channels.len(), MAX_RECIPIENTS + 1);
+			assert!(matches!(
+				new_encrypted(&recipients),
+				Err(CypherError::TooManyRecipients { got, max: MAX_RECIPIENTS })
+					if got == MAX_RECIPIENTS + 1
+			));
+		}
+
+		#[test]
+		fn has_channel_for() {
+			let sender_sk = SecretKey::generate(&mut OsRng);
+			let receivers_sk: Vec<_> = (0..3).map(|_| SecretKey::generate(&mut OsRng)).collect();
+			let receivers_pk: Vec<_> = receivers_sk.iter().map(|sk| sk.public_key()).collect();
+			let (metadata, _) = MessageMetadata::new_encrypted(
+				&sender_sk.public_key(),
+				&SalsaBox::generate_nonce(&mut OsRng),
+				&sender_sk.public_key(),
+				&receivers_pk.iter().collect::<Vec<_>>(),
+				&Message::default(),
+				None,
+				None,
+			)
+			.unwrap();
+
+			assert!(metadata.has_channel_for(&sender_sk));
+			for sk in &receivers_sk {
+				assert!(metadata.has_channel_for(sk));
+			}
+			assert!(!metadata.has_channel_for(&SecretKey::generate(&mut OsRng)));
+		}
+
+		#[test]
+		fn reused_nonce_is_rejected() {
+			let sender_pk = SecretKey::generate(&mut OsRng).public_key();
+			let receiver_pk = SecretKey::generate(&mut OsRng).public_key();
+			let nonce = SalsaBox::generate_nonce(&mut OsRng);
+
+			let mut session = EncryptionSession::default();
+			let mut new_encrypted = |nonce: &SalsaNonce| {
+				session.new_encrypted(
+					&sender_pk,
+					nonce,
+					&sender_pk,
+					&[&receiver_pk],
+					&Message::default(),
+					MetadataOptions::default(),
+				)
+			};
+			assert!(new_encrypted(&nonce).is_ok());
+			assert!(matches!(
+				new_encrypted(&nonce),
+				Err(CypherError::NonceReused(reused)) if reused == nonce
+			));
+			assert!(new_encrypted(&SalsaBox::generate_nonce(&mut OsRng)).is_ok());
+		}
+
+		#[test]
+		fn decrypt_across_key_rotation() {
+			let sender_sk = SecretKey::generate(&mut OsRng);
+			let sender_pk = sender_sk.public_key();
+			let rotation = KeyRotation {
+				old: SecretKey::generate(&mut OsRng),
+				new: SecretKey::generate(&mut OsRng),
+			};
+
+			let message = Message {
+				entries: vec![MessageEntry {
+					key: "key".into(),
+					value: "value".into(),
+					kind: MessageType::default(),
+				}],
+				headers: vec![],
+			};
+			let send = |receiver_pk: &PublicKey| {
+				let (metadata, secret_nonce) = MessageMetadata::new_encrypted(
+					&SecretKey::generate(&mut OsRng).public_key(),
+					&SalsaBox::generate_nonce(&mut OsRng),
+					&sender_pk,
+					&[receiver_pk],
+					&message,
+					None,
+					None,
+				)
+				.unwrap();
+				let encrypted_message =
+					message.encrypt(&secret_nonce, receiver_pk, &sender_sk).unwrap();
+				(metadata, encrypted_message)
+			};
+
+			let (old_metadata, old_message) = send(&rotation.old.public_key());
+			let (new_metadata, new_message) = send(&rotation.new.public_key());
+			assert!(
+				old_metadata.can_decrypt(&rotation.old) && !old_metadata.can_decrypt(&rotation.new)
+			);
+			assert!(
+				new_metadata.can_decrypt(&rotation.new) && !new_metadata.can_decrypt(&rotation.old)
+			);
+
+			let stranger_sk = SecretKey::generate(&mut OsRng);
+			let (stranger_metadata, _) = send(&stranger_sk.public_key());
+
+			let decrypted =
+				rotation.decrypt_batch(&[old_metadata, new_metadata, stranger_metadata]);
+			assert!(matches!(decrypted[2], Err(CypherError::NotDecrypted)));
+
+			for (result, encrypted_message, expected_sk) in [
+				(&decrypted[0], &old_message, &rotation.old),
+				(&decrypted[1], &new_message, &rotation.new),
+			] {
+				let (metadata, sk) = result.as_ref().unwrap();
+				assert_eq!(sk.public_key().as_bytes(), expected_sk.public_key().as_bytes());
+
+				let secret_nonce = SalsaNonce::from_slice(&metadata.channels[0].nonce);
+				let decrypted_message =
+					encrypted_message.decrypt(secret_nonce, &sender_pk, sk).unwrap();
+				assert_eq!(decrypted_message, message);
+			}
+		}
 	}
 }
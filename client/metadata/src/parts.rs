@@ -0,0 +1,36 @@
+//! Splitting of messages too large for a single off-chain blob into ordered parts. The parts are
+//! sent as consecutive messages of the sender, e.g. in one `send_messages` batch, so the part with
+//! the index `i` is stored under the counter of the first part plus `i`. The metadata of the
+//! parts records their count in `part_count`
+
+use scale_info::prelude::vec::Vec;
+
+/// Split the `message` into ordered parts of at most `max_part_size` bytes, e.g. the
+/// `MaxMessageSize` of the runtime. Panics if `max_part_size` is zero
+pub fn split_parts(message: &[u8], max_part_size: usize) -> Vec<&[u8]> {
+	message.chunks(max_part_size).collect()
+}
+
+/// Join the `parts` fetched from off-chain storage in their order back into the message
+pub fn join_parts<P: AsRef<[u8]>>(parts: impl IntoIterator<Item = P>) -> Vec<u8> {
+	parts.into_iter().fold(Vec::new(), |mut message, part| {
+		message.extend_from_slice(part.as_ref());
+		message
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn split_and_join() {
+		let message: Vec<u8> = (0..10).collect();
+		let parts = split_parts(&message, 4);
+		assert_eq!(parts, vec![&[0, 1, 2, 3][..], &[4, 5, 6, 7], &[8, 9]]);
+		assert_eq!(join_parts(parts), message);
+
+		assert_eq!(split_parts(&message, 10).len(), 1);
+		assert!(split_parts(&[], 4).is_empty());
+	}
+}
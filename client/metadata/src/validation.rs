@@ -0,0 +1,257 @@
+//! Structural checks of message metadata, shared by the pallet and clients, so a client never
+//! produces metadata the chain rejects
+
+use crate::{
+	is_low_order_key, Channel, MessageMetadata, MAC_SIZE, MAX_ENTRIES, MAX_RECIPIENTS, NONCE_SIZE,
+};
+
+/// Limits metadata is checked against, set by the runtime `Config` of the pallet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetadataLimits {
+	pub max_channels: u32,
+	pub max_parties_per_channel: u32,
+	pub max_entries: u32,
+}
+
+impl MetadataLimits {
+	/// Limits of the runtime, a client checks the metadata it creates against them
+	pub const DEFAULT: Self = Self {
+		max_channels: MAX_RECIPIENTS as u32 + 1,
+		max_parties_per_channel: MAX_RECIPIENTS as u32 + 1,
+		max_entries: MAX_ENTRIES as u32,
+	};
+}
+
+impl Default for MetadataLimits {
+	fn default() -> Self {
+		Self::DEFAULT
+	}
+}
+
+/// Rule broken by metadata, each matches an error of the pallet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetadataError {
+	/// The root hash is not set
+	InvalidHash,
+	/// Metadata has no channels
+	NoChannels,
+	/// The broker key is a low order point
+	LowOrderBroker,
+	/// Metadata claims to be split into no parts
+	NoParts,
+	/// The encrypted nonce of a channel has a wrong size
+	InvalidNonce { channel: u32 },
+	/// A channel doesn't list one party per channel
+	PartyCountMismatch { channel: u32 },
+	/// A party of a channel is empty
+	EmptyParty { channel: u32, party: u32 },
+	/// Metadata has more channels than allowed
+	TooManyChannels,
+	/// A channel has more parties than allowed
+	TooManyParties,
+	/// Metadata claims no entries or more than allowed
+	InvalidEntryCount,
+}
+
+impl core::fmt::Display for MetadataError {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		let reason = match self {
+			Self::InvalidHash => "root hash is not set",
+			Self::NoChannels => "metadata has no channels",
+			Self::LowOrderBroker => "broker key is a low order point",
+			Self::NoParts => "metadata claims no parts",
+			Self::InvalidNonce { channel } =>
+				return write!(f, "nonce of channel {} has a wrong size", channel),
+			Self::PartyCountMismatch { channel } =>
+				return write!(f, "channel {} doesn't list one party per channel", channel),
+			Self::EmptyParty { channel, party } =>
+				return write!(f, "party {} of channel {} is empty", party, channel),
+			Self::TooManyChannels => "too many channels",
+			Self::TooManyParties => "too many parties in a channel",
+			Self::InvalidEntryCount => "entry count is out of range",
+		};
+		f.write_str(reason)
+	}
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for MetadataError {}
+
+/// Clients handle `CypherError`, the rule broken is kept as the source of `InvalidMetadata`
+#[cfg(feature = "std")]
+impl From<MetadataError> for nolik_cypher::CypherError {
+	fn from(error: MetadataError) -> Self {
+		Self::InvalidMetadata(Box::new(error))
+	}
+}
+
+impl MessageMetadata {
+	/// Check the structure of metadata against the `limits`. The channels are encrypted, so
+	/// only their sizes can be checked. Returns the first rule broken, so a client is able to
+	/// tell what is wrong, while the pallet reports most of them as `MetadataMalformed`
+	pub fn validate(&self, limits: &MetadataLimits) -> Result<(), MetadataError> {
+		// the root hash is salted with a secret nonce, so it can only be checked to be set
+		if self.hash.iter().all(|b| *b == 0) {
+			return Err(MetadataError::InvalidHash)
+		}
+
+		if self.channels.is_empty() {
+			return Err(MetadataError::NoChannels)
+		}
+
+		// a low order broker key makes the channels Diffie-Hellman degenerate
+		if is_low_order_key(&self.broker) {
+			return Err(MetadataError::LowOrderBroker)
+		}
+
+		if self.channels.len() > limits.max_channels as usize {
+			return Err(MetadataError::TooManyChannels)
+		}
+
+		// the entries are encrypted, only an optional cleartext hint can be checked
+		if let Some(entry_count) = self.entry_count {
+			if entry_count == 0 || entry_count > limits.max_entries {
+				return Err(MetadataError::InvalidEntryCount)
+			}
+		}
+
+		// a message split into parts has at least one
+		if self.part_count == Some(0) {
+			return Err(MetadataError::NoParts)
+		}
+
+		for (index, Channel { nonce, parties }) in self.channels.iter().enumerate() {
+			let channel = index as u32;
+			if parties.len() > limits.max_parties_per_channel as usize {
+				return Err(MetadataError::TooManyParties)
+			}
+
+			// the public nonce is a fixed size array, while the secret one is encrypted
+			if nonce.len() != NONCE_SIZE + MAC_SIZE {
+				return Err(MetadataError::InvalidNonce { channel })
+			}
+
+			if parties.len() != self.channels.len() {
+				return Err(MetadataError::PartyCountMismatch { channel })
+			}
+
+			if let Some(party) = parties.iter().position(|party| party.is_empty()) {
+				return Err(MetadataError::EmptyParty { channel, party: party as u32 })
+			}
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::KEY_SIZE;
+
+	fn channel(parties: usize) -> Channel {
+		Channel {
+			nonce: vec![1; NONCE_SIZE + MAC_SIZE],
+			parties: vec![vec![1; KEY_SIZE + MAC_SIZE]; parties],
+		}
+	}
+
+	fn metadata(channels: usize) -> MessageMetadata {
+		MessageMetadata {
+			nonce: [1; NONCE_SIZE],
+			broker: [9; KEY_SIZE],
+			hash: [1; KEY_SIZE],
+			hash_algo: Default::default(),
+			in_reply_to: None,
+			entry_count: None,
+			part_count: None,
+			subject: None,
+			signature: None,
+			channels: vec![channel(channels); channels],
+		}
+	}
+
+	#[test]
+	fn valid_metadata() {
+		let limits = MetadataLimits::default();
+		assert_eq!(metadata(2).validate(&limits), Ok(()));
+		assert_eq!(
+			MessageMetadata { entry_count: Some(MAX_ENTRIES as u32), ..metadata(2) }
+				.validate(&limits),
+			Ok(())
+		);
+		assert_eq!(metadata(MAX_RECIPIENTS + 1).validate(&limits), Ok(()));
+	}
+
+	#[test]
+	fn hash_is_set() {
+		let metadata = MessageMetadata { hash: [0; KEY_SIZE], ..metadata(2) };
+		assert_eq!(metadata.validate(&MetadataLimits::DEFAULT), Err(MetadataError::InvalidHash));
+	}
+
+	#[test]
+	fn malformed_metadata() {
+		let check = |metadata: MessageMetadata| metadata.validate(&MetadataLimits::DEFAULT);
+
+		assert_eq!(check(metadata(0)), Err(MetadataError::NoChannels));
+		assert_eq!(
+			check(MessageMetadata { broker: [0; KEY_SIZE], ..metadata(2) }),
+			Err(MetadataError::LowOrderBroker)
+		);
+
+		let mut short_nonce = metadata(2);
+		short_nonce.channels[1].nonce = vec![1; NONCE_SIZE];
+		assert_eq!(check(short_nonce), Err(MetadataError::InvalidNonce { channel: 1 }));
+
+		let mut parties_mismatch = metadata(2);
+		parties_mismatch.channels[0] = channel(1);
+		assert_eq!(check(parties_mismatch), Err(MetadataError::PartyCountMismatch { channel: 0 }));
+
+		let mut no_parties = metadata(2);
+		no_parties.channels[1].parties.clear();
+		assert_eq!(check(no_parties), Err(MetadataError::PartyCountMismatch { channel: 1 }));
+
+		assert_eq!(
+			check(MessageMetadata { part_count: Some(0), ..metadata(2) }),
+			Err(MetadataError::NoParts)
+		);
+
+		let mut empty_party = metadata(2);
+		empty_party.channels[0].parties[1] = vec![];
+		assert_eq!(check(empty_party), Err(MetadataError::EmptyParty { channel: 0, party: 1 }));
+
+		// the first broken rule is reported
+		let mut many_violations = metadata(3);
+		many_violations.channels[1].parties[0] = vec![];
+		many_violations.channels[2].nonce = vec![];
+		assert_eq!(check(many_violations), Err(MetadataError::EmptyParty { channel: 1, party: 0 }));
+	}
+
+	#[test]
+	fn limits_are_checked() {
+		let limits = MetadataLimits { max_channels: 2, max_parties_per_channel: 2, max_entries: 4 };
+
+		assert_eq!(metadata(3).validate(&limits), Err(MetadataError::TooManyChannels));
+
+		let mut many_parties = metadata(2);
+		many_parties.channels[0] = channel(3);
+		assert_eq!(many_parties.validate(&limits), Err(MetadataError::TooManyParties));
+
+		for entry_count in [0, 5] {
+			let metadata = MessageMetadata { entry_count: Some(entry_count), ..metadata(2) };
+			assert_eq!(metadata.validate(&limits), Err(MetadataError::InvalidEntryCount));
+		}
+	}
+
+	#[test]
+	fn converts_to_cypher_error() {
+		let error =
+			nolik_cypher::CypherError::from(MetadataError::EmptyParty { channel: 0, party: 1 });
+		match error {
+			nolik_cypher::CypherError::InvalidMetadata(e) => assert_eq!(
+				e.downcast_ref::<MetadataError>(),
+				Some(&MetadataError::EmptyParty { channel: 0, party: 1 })
+			),
+			other => panic!("unexpected error {:?}", other),
+		}
+	}
+}
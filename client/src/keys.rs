@@ -0,0 +1,167 @@
+//! Encryption keys supplied to the CLI
+
+use crypto_box::{PublicKey, SecretKey};
+use hkdf::Hkdf;
+use nolik_cypher::parse_pubkey_hex;
+use nolik_metadata::KEY_SIZE;
+use sha2::Sha256;
+use sp_core::{crypto::Pair, sr25519};
+use std::{fs::OpenOptions, io::Write, path::Path};
+use zeroize::Zeroizing;
+
+/// Domain separation of the encryption key from other keys derived from the same seed
+const ENCRYPTION_KEY_INFO: &[u8] = b"nolik/encryption-key/v1";
+
+/// Derive an encryption secret key from the seed of an sr25519 account, so the key is recoverable
+/// from the same mnemonic.
+///
+/// The derived key is an X25519 key used by `crypto_box`, a different curve from the sr25519
+/// signing key, so the encryption public key can't be obtained from the account id and has to be
+/// shared separately.
+pub fn encryption_key_from_seed(seed: &[u8]) -> SecretKey {
+	let mut key = Zeroizing::new([0; KEY_SIZE]);
+	Hkdf::<Sha256>::new(None, seed)
+		.expand(ENCRYPTION_KEY_INFO, key.as_mut())
+		.expect("KEY_SIZE is a valid HKDF-SHA256 output length");
+	SecretKey::from(*key)
+}
+
+/// Derive an encryption secret key from an sr25519 account mnemonic, see
+/// `encryption_key_from_seed`
+pub fn encryption_key_from_phrase(
+	phrase: &str,
+	password: Option<&str>,
+) -> Result<SecretKey, String> {
+	let (_, seed) = sr25519::Pair::from_phrase(phrase, password)
+		.map_err(|e| format!("Invalid mnemonic: {:?}", e))?;
+	let seed = Zeroizing::new(seed);
+	Ok(encryption_key_from_seed(&*seed))
+}
+
+/// Write the hex encoded secret key to a new file readable by the owner only, as read by
+/// `nolik_cypher::parse_secret_key_hex`. An existing file is never overwritten, so a key in use
+/// can't be lost
+pub fn write_secret_key(path: &Path, sk: &SecretKey) -> std::io::Result<()> {
+	let mut options = OpenOptions::new();
+	options.write(true).create_new(true);
+	#[cfg(unix)]
+	std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+	let hex_key = Zeroizing::new(hex::encode(sk.as_bytes()));
+	options.open(path)?.write_all(hex_key.as_bytes())
+}
+
+/// Parse recipient public keys, one hex key per line. Blank lines and lines starting with `#` are
+/// skipped, any malformed key fails the whole list with its line number.
+pub fn parse_recipients(contents: &str) -> Result<Vec<PublicKey>, String> {
+	contents
+		.lines()
+		.enumerate()
+		.filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+		.map(|(index, line)| {
+			parse_pubkey_hex(line).map_err(|e| format!("Recipient on line {}: {}", index + 1, e))
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crypto_box::{
+		aead::{AeadCore, OsRng},
+		SalsaBox, SecretKey,
+	};
+	use nolik_cypher::Cypher;
+	use nolik_metadata::{Message, MessageMetadata};
+
+	#[test]
+	fn metadata_for_recipients_file() {
+		let keys: Vec<_> = (0..3).map(|_| SecretKey::generate(&mut OsRng).public_key()).collect();
+		let contents = format!(
+			"# recipients\n0x{}\n\n{}\n  0x{}  \n",
+			hex::encode(keys[0].as_bytes()),
+			hex::encode(keys[1].as_bytes()),
+			hex::encode(keys[2].as_bytes()),
+		);
+
+		let recipients = parse_recipients(&contents).unwrap();
+		assert_eq!(
+			recipients.iter().map(|pk| *pk.as_bytes()).collect::<Vec<_>>(),
+			keys.iter().map(|pk| *pk.as_bytes()).collect::<Vec<_>>(),
+		);
+
+		let sender_pk = SecretKey::generate(&mut OsRng).public_key();
+		let (metadata, _) = MessageMetadata::new_encrypted(
+			&sender_pk,
+			&SalsaBox::generate_nonce(&mut OsRng),
+			&sender_pk,
+			&recipients.iter().collect::<Vec<_>>(),
+			&Message::default(),
+			None,
+			None,
+		)
+		.unwrap();
+		// three recipients and the sender
+		assert_eq!(metadata.channels.len(), 4);
+	}
+
+	#[test]
+	fn encryption_key_from_mnemonic() {
+		let phrase = "bottom drive obey lake curtain smoke basket hold race lonely fit walk";
+		let other = "legal winner thank year wave sausage worth useful legal winner thank yellow";
+
+		let pk = |phrase: &str| {
+			*encryption_key_from_phrase(phrase, None).unwrap().public_key().as_bytes()
+		};
+		assert_eq!(pk(phrase), pk(phrase));
+		assert_ne!(pk(phrase), pk(other));
+
+		// a password gives a different seed
+		let with_password = encryption_key_from_phrase(phrase, Some("password")).unwrap();
+		assert_ne!(*with_password.public_key().as_bytes(), pk(phrase));
+
+		assert!(encryption_key_from_phrase("not a mnemonic", None).is_err());
+	}
+
+	#[test]
+	fn generated_key_round_trip() {
+		let path = std::env::temp_dir().join(format!("nolik-key-{}", std::process::id()));
+		let _ = std::fs::remove_file(&path);
+
+		let receiver_sk = SecretKey::generate(&mut OsRng);
+		write_secret_key(&path, &receiver_sk).unwrap();
+		// a key file is never overwritten
+		assert!(write_secret_key(&path, &SecretKey::generate(&mut OsRng)).is_err());
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::PermissionsExt;
+			let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+			assert_eq!(mode & 0o777, 0o600);
+		}
+
+		let read_sk =
+			nolik_cypher::parse_secret_key_hex(&std::fs::read_to_string(&path).unwrap()).unwrap();
+		std::fs::remove_file(&path).unwrap();
+
+		let sender_sk = SecretKey::generate(&mut OsRng);
+		let nonce = SalsaBox::generate_nonce(&mut OsRng);
+		let message = Message {
+			entries: vec![nolik_metadata::MessageEntry {
+				key: "key".into(),
+				value: "value".into(),
+				kind: Default::default(),
+			}],
+			headers: vec![],
+		};
+		let encrypted = message.encrypt(&nonce, &read_sk.public_key(), &sender_sk).unwrap();
+		let decrypted = encrypted.decrypt(&nonce, &sender_sk.public_key(), &read_sk).unwrap();
+		assert_eq!(decrypted, message);
+	}
+
+	#[test]
+	fn malformed_recipients_are_rejected() {
+		let err = parse_recipients(&format!("{}\n0x1234\n", hex::encode([1; KEY_SIZE])));
+		assert_eq!(err.unwrap_err(), "Recipient on line 2: Could not parse pubkey [18, 52]");
+		assert!(parse_recipients("not hex").is_err());
+	}
+}
@@ -5,12 +5,20 @@
 )]
 pub mod polkadot {}
 
+pub mod keys;
+#[cfg(feature = "registry")]
+pub mod registry;
+
 use crypto_box::{PublicKey, SecretKey};
-use nolik_cypher::{CypherError, SalsaNonce};
-use nolik_metadata::{Channel, Message, MessageMetadata};
+use nolik_cypher::{Cypher, CypherError, SalsaNonce};
+use nolik_metadata::{Channel, Message, MessageMetadata, NONCE_SIZE};
+use parity_scale_codec::Encode;
+// `substrate_metadata.scale` predates the runtime using `nolik_metadata::MessageMetadata`, switch
+// to `polkadot::runtime_types::nolik_metadata::meta` once it is regenerated from a node
 pub use polkadot::runtime_types::pallet_nolik::pallet::{
 	Channel as PolkadotChannel, MessageMetadata as PolkadotMessageMetadata,
 };
+use sp_core::{crypto::Pair, sr25519};
 
 impl PolkadotMessageMetadata {
 	pub fn new_encrypted(
@@ -19,38 +27,379 @@ impl PolkadotMessageMetadata {
 		sender_pk: &PublicKey,
 		recipients: &[&PublicKey],
 		message: &Message,
-	) -> Result<(Self, SalsaNonce), CypherError> {
-		let (meta, secret_nonce) =
-			MessageMetadata::new_encrypted(origin, public_nonce, sender_pk, recipients, message)?;
-		Ok((Self::from(meta), secret_nonce))
-	}
-
-	pub fn decrypt(&self, receiver_sk: &SecretKey) -> Result<Self, CypherError> {
-		let meta = MessageMetadata {
-			nonce: self.nonce,
-			broker: self.broker,
-			hash: self.hash,
-			channels: self
-				.channels
-				.iter()
-				.map(|c| Channel { nonce: c.nonce.clone(), parties: c.parties.clone() })
-				.collect(),
-		};
+		in_reply_to: Option<[u8; 32]>,
+		prev_hash: Option<[u8; 32]>,
+	) -> Result<(Self, SalsaNonce), Box<dyn std::error::Error>> {
+		let (meta, secret_nonce) = MessageMetadata::new_encrypted(
+			origin,
+			public_nonce,
+			sender_pk,
+			recipients,
+			message,
+			in_reply_to,
+			prev_hash,
+		)?;
+		Ok((Self::try_from(meta)?, secret_nonce))
+	}
+
+	pub fn decrypt(&self, receiver_sk: &SecretKey) -> Result<Self, Box<dyn std::error::Error>> {
+		let meta = MessageMetadata::from(self).decrypt(receiver_sk)?;
+		Ok(Self::try_from(meta)?)
+	}
+}
 
-		let meta = meta.decrypt(receiver_sk)?;
-		Ok(Self::from(meta))
+/// The runtime type of the static metadata only has the nonces, the root hash and the channels.
+/// Metadata using any later field can't be represented, so it fails instead of losing the field
+impl TryFrom<MessageMetadata> for PolkadotMessageMetadata {
+	type Error = String;
+
+	fn try_from(meta: MessageMetadata) -> Result<Self, Self::Error> {
+		let MessageMetadata {
+			nonce,
+			broker,
+			hash,
+			hash_algo,
+			in_reply_to,
+			entry_count,
+			part_count,
+			subject,
+			signature,
+			channels,
+		} = meta;
+		if hash_algo != Default::default() ||
+			in_reply_to.is_some() ||
+			entry_count.is_some() ||
+			part_count.is_some() ||
+			subject.is_some() ||
+			signature.is_some()
+		{
+			return Err("Metadata has fields unknown to substrate_metadata.scale, regenerate it \
+				from a node running the current runtime"
+				.into())
+		}
+
+		Ok(PolkadotMessageMetadata {
+			nonce,
+			broker,
+			hash,
+			channels: channels
+				.into_iter()
+				.map(|c| PolkadotChannel { nonce: c.nonce, parties: c.parties })
+				.collect(),
+		})
 	}
+}
 
-	pub fn from(meta: MessageMetadata) -> Self {
-		PolkadotMessageMetadata {
+impl From<&PolkadotMessageMetadata> for MessageMetadata {
+	fn from(meta: &PolkadotMessageMetadata) -> Self {
+		MessageMetadata {
 			nonce: meta.nonce,
 			broker: meta.broker,
 			hash: meta.hash,
+			// not a part of the static runtime metadata yet
+			hash_algo: Default::default(),
+			in_reply_to: None,
+			entry_count: None,
+			part_count: None,
+			subject: None,
+			signature: None,
 			channels: meta
 				.channels
-				.into_iter()
-				.map(|c| PolkadotChannel { nonce: c.nonce, parties: c.parties })
+				.iter()
+				.map(|c| Channel { nonce: c.nonce.clone(), parties: c.parties.clone() })
 				.collect(),
 		}
 	}
 }
+
+/// Sign the root hash of metadata with the sr25519 account of the sender, so recipients can tell
+/// who authored the message
+pub trait MetadataSignature {
+	/// Sign the root hash with the `sender_account`, the metadata must not change afterwards
+	fn sign(&mut self, sender_account: &sr25519::Pair);
+
+	/// Check the metadata is signed by the `sender_account` over its root hash, `false` if the
+	/// metadata is not signed
+	fn verify_signature(&self, sender_account: &sr25519::Public) -> bool;
+}
+
+impl MetadataSignature for MessageMetadata {
+	fn sign(&mut self, sender_account: &sr25519::Pair) {
+		self.signature = Some(sender_account.sign(&self.hash).0);
+	}
+
+	fn verify_signature(&self, sender_account: &sr25519::Public) -> bool {
+		self.signature.map_or(false, |signature| {
+			sr25519::Pair::verify(
+				&sr25519::Signature::from_raw(signature),
+				self.hash,
+				sender_account,
+			)
+		})
+	}
+}
+
+/// Estimate the SCALE encoded size of the `metadata` and the encoded `message` passed to
+/// `send_message`, without encoding them. Lets a client check the size before paying fees
+pub fn estimate_encoded_len(metadata: &MessageMetadata, message: &[u8]) -> usize {
+	metadata.encoded_size() + message.encoded_size()
+}
+
+/// Decrypt a message fetched from off-chain storage and check it against the root hash of its
+/// `metadata`. The `origin` is the account of the sender which submitted the message.
+pub fn verify_message(
+	origin: &PublicKey,
+	metadata: &MessageMetadata,
+	encrypted_message: &Message,
+	receiver_sk: &SecretKey,
+) -> Result<(), CypherError> {
+	decrypt_verified(Some(origin), metadata, encrypted_message, receiver_sk).map(|_| ())
+}
+
+/// Decrypt a sealed message fetched from off-chain storage and attribute it. The chain doesn't
+/// know the sender of a sealed message, so the origin is taken from the encrypted headers and
+/// returned once the root hash of `metadata` is verified against it.
+pub fn verify_sealed_message(
+	metadata: &MessageMetadata,
+	encrypted_message: &Message,
+	receiver_sk: &SecretKey,
+) -> Result<PublicKey, CypherError> {
+	decrypt_verified(None, metadata, encrypted_message, receiver_sk)
+}
+
+/// Decrypt and verify a message against the `origin`, or the sealed origin of the message if
+/// `None`, and return the origin
+fn decrypt_verified(
+	origin: Option<&PublicKey>,
+	metadata: &MessageMetadata,
+	encrypted_message: &Message,
+	receiver_sk: &SecretKey,
+) -> Result<PublicKey, CypherError> {
+	let metadata = metadata.decrypt(receiver_sk)?;
+	let channel = metadata.channels.first().ok_or(CypherError::NotDecrypted)?;
+
+	// the sender is the first party, the rest are recipients
+	let parties = channel
+		.parties
+		.iter()
+		.map(|party| {
+			<[u8; 32]>::try_from(party.as_slice())
+				.map(PublicKey::from)
+				.map_err(|_| CypherError::InvalidPubkey(party.clone()))
+		})
+		.collect::<Result<Vec<_>, _>>()?;
+	let (sender_pk, recipients) = parties.split_first().ok_or(CypherError::NotDecrypted)?;
+
+	let secret_nonce = <[u8; NONCE_SIZE]>::try_from(channel.nonce.as_slice())
+		.map(SalsaNonce::from)
+		.map_err(|_| CypherError::InvalidNonce(channel.nonce.clone()))?;
+	let message = zeroize::Zeroizing::new(encrypted_message.decrypt(
+		&secret_nonce,
+		sender_pk,
+		receiver_sk,
+	)?);
+
+	let origin = match origin {
+		Some(origin) => origin.clone(),
+		None => message.sealed_origin().ok_or(CypherError::MissingOrigin)?,
+	};
+	metadata.verify(&origin, &message, sender_pk, &recipients.iter().collect::<Vec<_>>())?;
+	Ok(origin)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crypto_box::{
+		aead::{AeadCore, OsRng},
+		SalsaBox,
+	};
+	use nolik_metadata::{HashAlgo, MessageEntry, MessageType, MAC_SIZE, SUBJECT_SIZE};
+
+	fn message(value: &str) -> Message {
+		Message {
+			entries: vec![MessageEntry {
+				key: "key".into(),
+				value: value.into(),
+				kind: MessageType::default(),
+			}],
+			headers: vec![],
+		}
+	}
+
+	#[test]
+	fn estimate_len() {
+		let receiver_pk = SecretKey::generate(&mut OsRng).public_key();
+		let sender_sk = SecretKey::generate(&mut OsRng);
+		let (metadata, secret_nonce) = MessageMetadata::new_encrypted(
+			&receiver_pk,
+			&SalsaBox::generate_nonce(&mut OsRng),
+			&sender_sk.public_key(),
+			&[&receiver_pk],
+			&message("value"),
+			Some([1; 32]),
+			None,
+		)
+		.unwrap();
+		let encrypted = message("value").encrypt(&secret_nonce, &receiver_pk, &sender_sk).unwrap();
+
+		for message in [encrypted.encode(), vec![], vec![1; 1 << 16]] {
+			assert_eq!(
+				estimate_encoded_len(&metadata, &message),
+				(&metadata, &message).encode().len()
+			);
+		}
+	}
+
+	#[test]
+	fn verify_fetched_message() {
+		let origin = SecretKey::generate(&mut OsRng).public_key();
+		let sender_sk = SecretKey::generate(&mut OsRng);
+		let receiver_sk = SecretKey::generate(&mut OsRng);
+		let receiver_pk = receiver_sk.public_key();
+
+		let (metadata, secret_nonce) = MessageMetadata::new_encrypted(
+			&origin,
+			&SalsaBox::generate_nonce(&mut OsRng),
+			&sender_sk.public_key(),
+			&[&receiver_pk],
+			&message("value"),
+			None,
+			None,
+		)
+		.unwrap();
+
+		let good = message("value").encrypt(&secret_nonce, &receiver_pk, &sender_sk).unwrap();
+		assert!(verify_message(&origin, &metadata, &good, &receiver_sk).is_ok());
+
+		// a different message encrypted with the same keys
+		let corrupted =
+			message("corrupted").encrypt(&secret_nonce, &receiver_pk, &sender_sk).unwrap();
+		assert!(matches!(
+			verify_message(&origin, &metadata, &corrupted, &receiver_sk),
+			Err(CypherError::HashMismatch)
+		));
+
+		// the hash commits to the origin as well
+		let other_origin = SecretKey::generate(&mut OsRng).public_key();
+		assert!(matches!(
+			verify_message(&other_origin, &metadata, &good, &receiver_sk),
+			Err(CypherError::HashMismatch)
+		));
+	}
+
+	#[test]
+	fn attribute_sealed_message() {
+		let origin = SecretKey::generate(&mut OsRng).public_key();
+		let sender_sk = SecretKey::generate(&mut OsRng);
+		let receiver_sk = SecretKey::generate(&mut OsRng);
+		let receiver_pk = receiver_sk.public_key();
+
+		let mut sealed = message("value");
+		sealed.seal_origin(&origin);
+		let (metadata, secret_nonce) = MessageMetadata::new_encrypted(
+			&origin,
+			&SalsaBox::generate_nonce(&mut OsRng),
+			&sender_sk.public_key(),
+			&[&receiver_pk],
+			&sealed,
+			None,
+			None,
+		)
+		.unwrap();
+		let encrypted = sealed.encrypt(&secret_nonce, &receiver_pk, &sender_sk).unwrap();
+
+		// the origin is only readable by a recipient
+		assert!(encrypted.sealed_origin().is_none());
+		let attributed = verify_sealed_message(&metadata, &encrypted, &receiver_sk).unwrap();
+		assert_eq!(attributed.as_bytes(), origin.as_bytes());
+
+		let stranger_sk = SecretKey::generate(&mut OsRng);
+		assert!(verify_sealed_message(&metadata, &encrypted, &stranger_sk).is_err());
+
+		// a message without the origin header can't be attributed
+		let (metadata, secret_nonce) = MessageMetadata::new_encrypted(
+			&origin,
+			&SalsaBox::generate_nonce(&mut OsRng),
+			&sender_sk.public_key(),
+			&[&receiver_pk],
+			&message("value"),
+			None,
+			None,
+		)
+		.unwrap();
+		let encrypted = message("value").encrypt(&secret_nonce, &receiver_pk, &sender_sk).unwrap();
+		assert!(matches!(
+			verify_sealed_message(&metadata, &encrypted, &receiver_sk),
+			Err(CypherError::MissingOrigin)
+		));
+	}
+
+	#[test]
+	fn convert_runtime_metadata() {
+		let receiver_sk = SecretKey::generate(&mut OsRng);
+		let receiver_pk = receiver_sk.public_key();
+		let sender_pk = SecretKey::generate(&mut OsRng).public_key();
+		let (metadata, _) = MessageMetadata::new_encrypted(
+			&sender_pk,
+			&SalsaBox::generate_nonce(&mut OsRng),
+			&sender_pk,
+			&[&receiver_pk],
+			&message("value"),
+			None,
+			None,
+		)
+		.unwrap();
+		assert_eq!(metadata.channels.len(), 2);
+
+		let runtime = PolkadotMessageMetadata::try_from(metadata.clone()).unwrap();
+		assert_eq!(MessageMetadata::from(&runtime), metadata);
+		assert_eq!(
+			PolkadotMessageMetadata::try_from(MessageMetadata::from(&runtime)),
+			Ok(runtime.clone())
+		);
+
+		// decrypting either type gives the same metadata
+		assert_eq!(
+			runtime.decrypt(&receiver_sk).unwrap(),
+			PolkadotMessageMetadata::try_from(metadata.decrypt(&receiver_sk).unwrap()).unwrap()
+		);
+
+		// fields unknown to the static metadata are never dropped silently
+		for metadata in [
+			MessageMetadata { hash_algo: HashAlgo::Blake2b256, ..metadata.clone() },
+			MessageMetadata { in_reply_to: Some([3; 32]), ..metadata.clone() },
+			MessageMetadata { entry_count: Some(1), ..metadata.clone() },
+			MessageMetadata { part_count: Some(2), ..metadata.clone() },
+			MessageMetadata { subject: Some([4; SUBJECT_SIZE + MAC_SIZE]), ..metadata.clone() },
+			MessageMetadata { signature: Some([5; 64]), ..metadata.clone() },
+		] {
+			assert!(PolkadotMessageMetadata::try_from(metadata).is_err());
+		}
+	}
+	#[test]
+	fn metadata_signature() {
+		let sender_pk = SecretKey::generate(&mut OsRng).public_key();
+		let receiver_pk = SecretKey::generate(&mut OsRng).public_key();
+		let (mut metadata, _) = MessageMetadata::new_encrypted(
+			&sender_pk,
+			&SalsaBox::generate_nonce(&mut OsRng),
+			&sender_pk,
+			&[&receiver_pk],
+			&message("value"),
+			None,
+			None,
+		)
+		.unwrap();
+		let account = sr25519::Pair::from_seed(&[1; 32]);
+		assert!(!metadata.verify_signature(&account.public()));
+
+		metadata.sign(&account);
+		assert!(metadata.verify_signature(&account.public()));
+		assert!(!metadata.verify_signature(&sr25519::Pair::from_seed(&[2; 32]).public()));
+
+		// the signature doesn't hold for another root hash
+		metadata.hash[0] ^= 1;
+		assert!(!metadata.verify_signature(&account.public()));
+	}
+}
@@ -1,4 +1,4 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use crypto_box::{
 	aead::{AeadCore, OsRng},
 	PublicKey, SalsaBox, SecretKey,
@@ -8,7 +8,10 @@ use parity_scale_codec::{Decode, Encode};
 use sp_core::{crypto::Pair, offchain::StorageKind};
 
 use sp_keyring::AccountKeyring;
-use std::{path::PathBuf, sync::Arc};
+use std::{
+	path::{Path, PathBuf},
+	sync::Arc,
+};
 use subxt::{
 	client::default_rpc_client,
 	error::Error as subxtError,
@@ -16,9 +19,10 @@ use subxt::{
 	tx::PairSigner,
 	OnlineClient, PolkadotConfig,
 };
+use zeroize::Zeroizing;
 
-use nolik_cli::{polkadot, PolkadotMessageMetadata};
-use nolik_metadata::{Message, MessageEntry, MessageType};
+use nolik_cli::{keys, polkadot, PolkadotMessageMetadata};
+use nolik_metadata::{Message, MessageEntry, MessageMetadata, MessageType};
 
 fn to_hex(bytes: impl AsRef<[u8]>) -> String {
 	format!("0x{}", hex::encode(bytes.as_ref()))
@@ -50,9 +54,201 @@ struct Args {
 	#[arg(long)]
 	entries: Option<Vec<String>>,
 
-	/// Specify secretkey path to sign a message.
+	/// Specify secretkey path to sign a message, required unless verifying or generating keys.
+	#[arg(long, value_name = "PATH")]
+	pub secretkey_path: Option<PathBuf>,
+
+	/// Attach a file to the message.
 	#[arg(long, value_name = "PATH")]
-	pub secretkey_path: PathBuf,
+	file: Option<PathBuf>,
+
+	/// MIME type of the attached file.
+	#[arg(long, default_value = "application/octet-stream")]
+	mime: String,
+
+	/// Compress message entries before encryption.
+	#[arg(long)]
+	compress: bool,
+
+	/// Maximum size of an encrypted message accepted by the node (`MaxMessageSize`), checked
+	/// before the message is sent.
+	#[arg(long, default_value_t = 1024 * 1024)]
+	max_message_size: u32,
+
+	#[command(subcommand)]
+	command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+	/// Print encrypted metadata of the message for multiple recipients instead of sending it.
+	Metadata {
+		/// File with recipient public keys, one hex key per line.
+		#[arg(long, value_name = "PATH")]
+		recipients: PathBuf,
+
+		/// File with the hex encoded sender encryption secret key, derived from the account seed
+		/// if not set.
+		#[arg(long, value_name = "PATH")]
+		sender_key: Option<PathBuf>,
+
+		/// Encrypt with a sender key generated for this message only, so messages can't be
+		/// linked by the sender key. The account only signs the extrinsic.
+		#[arg(long, conflicts_with = "sender_key")]
+		ephemeral: bool,
+	},
+	/// Check a message downloaded from off-chain storage against the root hash of its metadata,
+	/// exits with a non-zero code on mismatch.
+	Verify {
+		/// File with the metadata JSON.
+		#[arg(long, value_name = "PATH")]
+		metadata: PathBuf,
+
+		/// File with the SCALE encoded encrypted message as stored off-chain.
+		#[arg(long, value_name = "PATH")]
+		message: PathBuf,
+
+		/// Hex encoded account of the message sender.
+		#[arg(long)]
+		origin: String,
+
+		/// File with the hex encoded receiver encryption secret key.
+		#[arg(long, value_name = "PATH")]
+		receiver_key: PathBuf,
+	},
+	/// Generate an encryption key, write the secret key to a new file readable by the owner
+	/// only and print the public key to share with senders.
+	Keygen {
+		/// File to write the hex encoded encryption secret key to, must not exist.
+		#[arg(long, value_name = "PATH")]
+		out: PathBuf,
+
+		/// Derive the key from the account seed at `--secretkey-path` instead of generating a
+		/// random one, so the key is recoverable from the account mnemonic.
+		#[arg(long)]
+		from_seed: bool,
+	},
+}
+
+/// Read the hex encoded sr25519 account seed
+fn read_seed(path: &Path) -> Result<Zeroizing<Vec<u8>>, Box<dyn std::error::Error>> {
+	let secret = Zeroizing::new(std::fs::read_to_string(path)?);
+	let seed =
+		hex::decode(secret.trim()).map_err(|e| format!("Could't decode secret from hex: {}", e))?;
+	Ok(Zeroizing::new(seed))
+}
+
+/// Write a new encryption secret key to `out` and print the public keys
+fn keygen(out: &Path, seed_path: Option<&Path>) -> Result<(), Box<dyn std::error::Error>> {
+	let sk = match seed_path {
+		Some(path) => {
+			let seed = read_seed(path)?;
+			let account = sp_core::sr25519::Pair::from_seed_slice(&seed)
+				.map_err(|e| format!("Secret seed is not valid: {:?}", e))?;
+			println!("Account {}", to_hex(account.public()));
+			keys::encryption_key_from_seed(&seed)
+		},
+		None => SecretKey::generate(&mut OsRng),
+	};
+
+	keys::write_secret_key(out, &sk)
+		.map_err(|e| format!("Couldn't write the key to {}: {}", out.display(), e))?;
+	println!("Encryption public key {}", to_hex(sk.public_key().as_bytes()));
+
+	Ok(())
+}
+
+/// Read a file to a message entry keyed by the file name
+fn file_entry(path: &Path, mime: &str) -> Result<MessageEntry, Box<dyn std::error::Error>> {
+	let filename = path
+		.file_name()
+		.and_then(|name| name.to_str())
+		.ok_or_else(|| format!("Invalid file name {}", path.display()))?;
+	Ok(MessageEntry {
+		key: filename.into(),
+		value: std::fs::read(path)?,
+		kind: MessageType::File { filename: filename.into(), mime: mime.into() },
+	})
+}
+
+/// Build the message from the command line arguments
+fn build_message(args: &Args) -> Result<Message, Box<dyn std::error::Error>> {
+	let mut message = Message {
+		entries: vec![MessageEntry {
+			key: "key".into(),
+			value: "value".into(),
+			kind: MessageType::default(),
+		}],
+		headers: vec![],
+	};
+	if let Some(path) = &args.file {
+		message.entries.push(file_entry(path, &args.mime)?);
+	}
+	Ok(message)
+}
+
+/// Print metadata of the message for every recipient listed in the `recipients` file. Without
+/// the `sender_sk` an ephemeral sender key is generated and printed
+fn print_metadata(
+	origin: &PublicKey,
+	message: &Message,
+	recipients: &Path,
+	sender_sk: Option<&SecretKey>,
+) -> Result<(), Box<dyn std::error::Error>> {
+	let recipients = keys::parse_recipients(&std::fs::read_to_string(recipients)?)?;
+	for recipient in &recipients {
+		eprintln!("Accepted recipient {}", to_hex(recipient.as_bytes()));
+	}
+
+	let recipients = recipients.iter().collect::<Vec<_>>();
+	let public_nonce = SalsaBox::generate_nonce(&mut OsRng);
+	let (metadata, secret_nonce) = match sender_sk {
+		Some(sender_sk) => MessageMetadata::new_encrypted(
+			origin,
+			&public_nonce,
+			&sender_sk.public_key(),
+			&recipients,
+			message,
+			None,
+			None,
+		)?,
+		None => {
+			let (metadata, secret_nonce, sender_sk) =
+				MessageMetadata::new_ephemeral(origin, &public_nonce, &recipients, message, None)?;
+			eprintln!("Ephemeral sender secret key {}", to_hex(sender_sk.as_bytes()));
+			(metadata, secret_nonce)
+		},
+	};
+	eprintln!("Secret nonce {}", to_hex(secret_nonce));
+	println!("{}", metadata.to_json()?);
+
+	Ok(())
+}
+
+/// Print the result of the message verification, returns `false` on mismatch
+fn verify(
+	metadata: &Path,
+	message: &Path,
+	origin: &str,
+	receiver_key: &Path,
+) -> Result<bool, Box<dyn std::error::Error>> {
+	let metadata = MessageMetadata::from_json(&std::fs::read_to_string(metadata)?)?;
+	let message = Message::decode(&mut &std::fs::read(message)?[..])?;
+	let origin = nolik_cypher::parse_pubkey_hex(origin)?;
+
+	let receiver_key = Zeroizing::new(std::fs::read_to_string(receiver_key)?);
+	let receiver_sk = nolik_cypher::parse_secret_key_hex(&receiver_key)?;
+
+	match nolik_cli::verify_message(&origin, &metadata, &message, &receiver_sk) {
+		Ok(()) => {
+			println!("Message verification passed");
+			Ok(true)
+		},
+		Err(e) => {
+			println!("Message verification failed: {}", e);
+			Ok(false)
+		},
+	}
 }
 
 #[tokio::main]
@@ -60,12 +256,39 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 	tracing_subscriber::fmt::init();
 	let args = Args::parse();
 
-	let secret = std::fs::read_to_string(args.secretkey_path)?;
-	let secret = sp_core::sr25519::Pair::from_seed_slice(
-		&hex::decode(secret.trim())
-			.map_err(|e| format!("Could't decode secret from hex: {}", e))?,
-	)
-	.expect("Secreet seed is not valid");
+	if let Some(Command::Verify { metadata, message, origin, receiver_key }) = &args.command {
+		if !verify(metadata, message, origin, receiver_key)? {
+			std::process::exit(1);
+		}
+		return Ok(())
+	}
+
+	if let Some(Command::Keygen { out, from_seed }) = &args.command {
+		let seed_path = match from_seed {
+			true =>
+				Some(args.secretkey_path.as_deref().ok_or("--from-seed needs --secretkey-path")?),
+			false => None,
+		};
+		return keygen(out, seed_path)
+	}
+
+	let secretkey_path = args.secretkey_path.as_ref().ok_or("--secretkey-path is required")?;
+	let seed = read_seed(secretkey_path)?;
+	let secret = sp_core::sr25519::Pair::from_seed_slice(&seed).expect("Secreet seed is not valid");
+
+	let message = build_message(&args)?;
+	if let Some(Command::Metadata { recipients, sender_key, ephemeral }) = &args.command {
+		let origin = PublicKey::from(secret.public().0);
+		let sender_sk = match (sender_key, ephemeral) {
+			(_, true) => None,
+			(Some(path), false) => {
+				let sender_key = Zeroizing::new(std::fs::read_to_string(path)?);
+				Some(nolik_cypher::parse_secret_key_hex(&sender_key)?)
+			},
+			(None, false) => Some(keys::encryption_key_from_seed(&seed)),
+		};
+		return print_metadata(&origin, &message, recipients, sender_sk.as_ref())
+	}
 
 	let url = format!("ws://{}:{}", args.host, args.port);
 	let client = Arc::new(default_rpc_client(url).await?);
@@ -79,27 +302,41 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 	let receiver_sk = SecretKey::generate(&mut OsRng);
 	let receiver_pk = receiver_sk.public_key();
 
-	let message = Message {
-		entries: vec![MessageEntry {
-			key: "key".into(),
-			value: "value".into(),
-			kind: MessageType::default(),
-		}],
-	};
-
-	let (encrypted_metadata, secret_nonce) = PolkadotMessageMetadata::new_encrypted(
+	let (metadata, secret_nonce) = MessageMetadata::new_encrypted(
 		&PublicKey::from(AccountKeyring::Alice.public().0),
 		&nonce,
 		&sender_pk,
 		&[&receiver_pk],
 		&message,
+		None,
+		None,
 	)?;
 
-	let encrypted_message = message.encrypt(&secret_nonce, &receiver_pk, &sender_sk)?;
+	// the root hash covers original entries, so compress after the metadata is created
+	let encrypted_message = if args.compress {
+		message.compress()?.encrypt(&secret_nonce, &receiver_pk, &sender_sk)?
+	} else {
+		message.encrypt(&secret_nonce, &receiver_pk, &sender_sk)?
+	};
+
+	let encoded_message = encrypted_message.encode();
+	if encoded_message.len() > args.max_message_size as usize {
+		return Err(format!(
+			"Message of {} bytes exceeds the max message size of {} bytes",
+			encoded_message.len(),
+			args.max_message_size
+		)
+		.into())
+	}
+	eprintln!(
+		"Estimated call size {} bytes",
+		nolik_cli::estimate_encoded_len(&metadata, &encoded_message)
+	);
 
+	// the call of the static metadata, without the TTL and class arguments of the runtime
 	let tx = polkadot::tx()
 		.nolik()
-		.send_message(encrypted_metadata.clone(), encrypted_message.encode());
+		.send_message(PolkadotMessageMetadata::try_from(metadata)?, encoded_message);
 
 	let sub_ext = api.tx().create_signed(&tx, &signer, Default::default()).await?;
 	let events = sub_ext.submit_and_watch().await?.wait_for_finalized_success().await?;
@@ -120,8 +357,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 			.await?
 			.ok_or("No off-chain data found")?;
 		let receiver_message = Message::decode(&mut &receiver_data.0[..])?;
-		let receiver_message = receiver_message.decrypt(&secret_nonce, &sender_pk, &receiver_sk)?;
-		assert_eq!(message, receiver_message);
+		let receiver_message =
+			Zeroizing::new(receiver_message.decrypt(&secret_nonce, &sender_pk, &receiver_sk)?);
+		assert_eq!(message, *receiver_message);
 	} else {
 		println!("Failed to find MessageSent Event");
 	}
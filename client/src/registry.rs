@@ -0,0 +1,169 @@
+//! Encryption public keys registered on-chain with `register_public_key`
+
+use crypto_box::PublicKey;
+use nolik_metadata::KEY_SIZE;
+use parity_scale_codec::{Decode, Encode};
+use sp_core::{
+	crypto::AccountId32,
+	hashing::{blake2_128, twox_128},
+};
+use std::future::Future;
+use subxt::{Config, OnlineClient};
+
+/// Raw storage prefix of all entries of the `PublicKeys` map of the Nolik pallet
+pub fn public_keys_prefix() -> Vec<u8> {
+	[twox_128(b"Nolik"), twox_128(b"PublicKeys")].concat()
+}
+
+/// Raw storage key of the `account` entry of the `PublicKeys` map of the Nolik pallet
+pub fn public_key_storage_key(account: &AccountId32) -> Vec<u8> {
+	let account = account.encode();
+	[
+		public_keys_prefix().as_slice(),
+		// `Blake2_128Concat` hasher
+		blake2_128(&account).as_slice(),
+		&account,
+	]
+	.concat()
+}
+
+/// Decode a raw `key` and `value` of the `PublicKeys` map back into the account and its public
+/// key, `None` if the entry is not of the map or malformed
+pub fn decode_public_key_entry(key: &[u8], value: &[u8]) -> Option<(AccountId32, PublicKey)> {
+	// the account follows its `Blake2_128Concat` hash
+	let mut account = key.strip_prefix(public_keys_prefix().as_slice())?.get(16..)?;
+	let account = AccountId32::decode(&mut account).ok().filter(|_| account.is_empty())?;
+	let pk = <[u8; KEY_SIZE]>::decode(&mut &value[..]).ok()?;
+	Some((account, PublicKey::from(pk)))
+}
+
+/// Find all accounts that registered the `pk` among raw `entries` of the `PublicKeys` map, e.g.
+/// read from storage by `public_keys_prefix`. The chain keeps no map from public keys to
+/// accounts, so all entries are scanned. Keys are not unique, anyone may register a key of
+/// another account, so more than one account is a key claimed by someone else and none of them
+/// should be trusted to be the owner
+pub fn accounts_of_public_key<I>(pk: &PublicKey, entries: I) -> Vec<AccountId32>
+where
+	I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+{
+	entries
+		.into_iter()
+		.filter_map(|(key, value)| decode_public_key_entry(&key, &value))
+		.filter(|(_, registered)| registered.as_bytes() == pk.as_bytes())
+		.map(|(account, _)| account)
+		.collect()
+}
+
+/// Look up the public key registered by the `account`, `fetch` reads raw storage by the key.
+/// Fails if the account hasn't registered a key
+pub async fn registered_public_key<F, Fut, E>(
+	account: &AccountId32,
+	fetch: F,
+) -> Result<PublicKey, String>
+where
+	F: FnOnce(Vec<u8>) -> Fut,
+	Fut: Future<Output = Result<Option<Vec<u8>>, E>>,
+	E: std::fmt::Display,
+{
+	let data = fetch(public_key_storage_key(account))
+		.await
+		.map_err(|e| format!("Couldn't fetch the public key of {}: {}", account, e))?
+		.ok_or_else(|| format!("Account {} has not registered a public key", account))?;
+	let pk = <[u8; KEY_SIZE]>::decode(&mut data.as_slice())
+		.map_err(|e| format!("Couldn't decode the public key of {}: {}", account, e))?;
+	Ok(PublicKey::from(pk))
+}
+
+/// Fetch the public key registered by the `account` from the best block of the node, so a
+/// message can be encrypted to a recipient known by the account id only
+pub async fn fetch_public_key<T: Config>(
+	api: &OnlineClient<T>,
+	account: &AccountId32,
+) -> Result<PublicKey, String> {
+	registered_public_key(account, |key| async move {
+		api.rpc().storage(&key, None).await.map(|data| data.map(|data| data.0))
+	})
+	.await
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crypto_box::{aead::OsRng, SecretKey};
+	use std::collections::HashMap;
+
+	#[tokio::test]
+	async fn public_key_from_storage() {
+		let account = AccountId32::from([1; 32]);
+		let pk = SecretKey::generate(&mut OsRng).public_key();
+
+		let expected_key = public_key_storage_key(&account);
+		let fetched = registered_public_key(&account, |key| async move {
+			assert_eq!(key, expected_key);
+			Ok::<_, String>(Some(pk.as_bytes().to_vec()))
+		})
+		.await
+		.unwrap();
+		assert_eq!(fetched.as_bytes(), pk.as_bytes());
+
+		let err = registered_public_key(&account, |_| async { Ok::<_, String>(None) }).await;
+		assert!(err.unwrap_err().contains("has not registered"));
+
+		let err =
+			registered_public_key(&account, |_| async { Err::<Option<Vec<u8>>, _>("offline") })
+				.await;
+		assert!(err.unwrap_err().contains("offline"));
+	}
+
+	#[tokio::test]
+	async fn account_round_trip() {
+		let accounts = [AccountId32::from([1; 32]), AccountId32::from([2; 32])];
+		let pks: Vec<_> = (0..2).map(|_| SecretKey::generate(&mut OsRng).public_key()).collect();
+		// raw storage of a registry holding both accounts
+		let storage: HashMap<_, _> = accounts
+			.iter()
+			.zip(&pks)
+			.map(|(account, pk)| (public_key_storage_key(account), pk.as_bytes().encode()))
+			.collect();
+
+		for (account, pk) in accounts.iter().zip(&pks) {
+			let fetched = registered_public_key(account, |key| {
+				let data = storage.get(&key).cloned();
+				async move { Ok::<_, String>(data) }
+			})
+			.await
+			.unwrap();
+			assert_eq!(fetched.as_bytes(), pk.as_bytes());
+			assert_eq!(accounts_of_public_key(&fetched, storage.clone()), vec![account.clone()]);
+		}
+
+		let unknown = SecretKey::generate(&mut OsRng).public_key();
+		assert!(accounts_of_public_key(&unknown, storage.clone()).is_empty());
+		// entries of other maps are skipped
+		let other = (vec![0; 80], pks[0].as_bytes().encode());
+		assert!(accounts_of_public_key(&pks[0], [other]).is_empty());
+	}
+
+	#[test]
+	fn claimed_key_lists_every_account() {
+		let owner = AccountId32::from([1; 32]);
+		let claimant = AccountId32::from([2; 32]);
+		let pk = SecretKey::generate(&mut OsRng).public_key();
+		// the claimant registered the key of the owner
+		let storage = [&owner, &claimant]
+			.map(|account| (public_key_storage_key(account), pk.as_bytes().encode()));
+
+		let mut accounts = accounts_of_public_key(&pk, storage);
+		accounts.sort();
+		assert_eq!(accounts, vec![owner, claimant]);
+	}
+
+	#[test]
+	fn storage_key_layout() {
+		let account = AccountId32::from([2; 32]);
+		let key = public_key_storage_key(&account);
+		// two prefix hashes, the account hash and the account itself
+		assert_eq!(key.len(), 16 + 16 + 16 + 32);
+		assert!(key.ends_with(&[2; 32]));
+	}
+}
@@ -0,0 +1,118 @@
+//! RPC interface for the Nolik pallet.
+
+use std::{marker::PhantomData, sync::Arc};
+
+use codec::Codec;
+use jsonrpsee::{
+	core::RpcResult,
+	proc_macros::rpc,
+	types::error::{CallError, ErrorObject},
+};
+use sp_api::ProvideRuntimeApi;
+use sp_blockchain::HeaderBackend;
+use sp_core::Bytes;
+use sp_runtime::{generic::BlockId, traits::Block as BlockT};
+
+pub use pallet_nolik_runtime_api::NolikApi as NolikRuntimeApi;
+
+#[rpc(client, server)]
+pub trait NolikApi<BlockHash, AccountId> {
+	/// The current message counter of the `account`, lets a client predict the key of its next
+	/// message without waiting for the `MessageSent` event
+	#[method(name = "nolik_messageCounter")]
+	fn message_counter(&self, account: AccountId, at: Option<BlockHash>) -> RpcResult<u128>;
+
+	/// The off-chain key of the `account` message with the `counter`, lets a client know where
+	/// its message is stored before submitting it
+	#[method(name = "nolik_predictKey")]
+	fn predict_key(
+		&self,
+		account: AccountId,
+		counter: u128,
+		at: Option<BlockHash>,
+	) -> RpcResult<Bytes>;
+
+	/// Whether the `account` registered an encryption public key, lets a client check a
+	/// recipient is reachable before paying for a message
+	#[method(name = "nolik_isRegistered")]
+	fn is_registered(&self, account: AccountId, at: Option<BlockHash>) -> RpcResult<bool>;
+}
+
+/// Provides RPC methods to query the Nolik pallet state
+pub struct Nolik<C, Block> {
+	client: Arc<C>,
+	_marker: PhantomData<Block>,
+}
+
+impl<C, Block> Nolik<C, Block> {
+	/// Create a new instance with the given reference to the client
+	pub fn new(client: Arc<C>) -> Self {
+		Self { client, _marker: Default::default() }
+	}
+}
+
+/// Error code of a failed runtime API call
+const RUNTIME_ERROR: i32 = 1;
+
+impl<C, Block, AccountId> NolikApiServer<<Block as BlockT>::Hash, AccountId> for Nolik<C, Block>
+where
+	Block: BlockT,
+	C: ProvideRuntimeApi<Block> + HeaderBackend<Block> + Send + Sync + 'static,
+	C::Api: NolikRuntimeApi<Block, AccountId>,
+	AccountId: Codec,
+{
+	fn message_counter(
+		&self,
+		account: AccountId,
+		at: Option<<Block as BlockT>::Hash>,
+	) -> RpcResult<u128> {
+		let api = self.client.runtime_api();
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+
+		api.message_counter(&at, account).map_err(|e| {
+			CallError::Custom(ErrorObject::owned(
+				RUNTIME_ERROR,
+				"Unable to query the message counter.",
+				Some(e.to_string()),
+			))
+			.into()
+		})
+	}
+
+	fn predict_key(
+		&self,
+		account: AccountId,
+		counter: u128,
+		at: Option<<Block as BlockT>::Hash>,
+	) -> RpcResult<Bytes> {
+		let api = self.client.runtime_api();
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+
+		api.predict_key(&at, account, counter).map(Into::into).map_err(|e| {
+			CallError::Custom(ErrorObject::owned(
+				RUNTIME_ERROR,
+				"Unable to predict the message key.",
+				Some(e.to_string()),
+			))
+			.into()
+		})
+	}
+
+	fn is_registered(
+		&self,
+		account: AccountId,
+		at: Option<<Block as BlockT>::Hash>,
+	) -> RpcResult<bool> {
+		let api = self.client.runtime_api();
+		let at = BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash));
+
+		api.is_registered(&at, account).map_err(|e| {
+			CallError::Custom(ErrorObject::owned(
+				RUNTIME_ERROR,
+				"Unable to query the public key registry.",
+				Some(e.to_string()),
+			))
+			.into()
+		})
+	}
+}
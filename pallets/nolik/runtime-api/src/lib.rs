@@ -0,0 +1,25 @@
+//! Runtime API definition for the Nolik pallet.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use codec::Codec;
+use sp_std::vec::Vec;
+
+sp_api::decl_runtime_apis! {
+	pub trait NolikApi<AccountId>
+	where
+		AccountId: Codec,
+	{
+		/// The number of messages sent by the `account`, which is also the counter of its next
+		/// message
+		fn message_counter(account: AccountId) -> u128;
+
+		/// The off-chain key of the `account` message with the `counter`, where the message is
+		/// stored once sent
+		fn predict_key(account: AccountId, counter: u128) -> Vec<u8>;
+
+		/// Whether the `account` registered an encryption public key, so it's possible to send
+		/// a message to it
+		fn is_registered(account: AccountId) -> bool;
+	}
+}
@@ -0,0 +1,230 @@
+//! Benchmarking setup for pallet-nolik
+
+use super::*;
+
+#[allow(unused)]
+use crate::Pallet as Nolik;
+use frame_benchmarking::{account, benchmarks, whitelisted_caller};
+use frame_support::{
+	sp_io,
+	sp_runtime::{traits::Bounded, KeyTypeId},
+	traits::{Currency, Get},
+	BoundedVec,
+};
+use frame_system::RawOrigin;
+use nolik_metadata::{Channel, MessageMetadata, KEY_SIZE, MAC_SIZE, NONCE_SIZE, SUBJECT_SIZE};
+use scale_info::prelude::{vec, vec::Vec};
+
+/// Metadata with `count` channels of `count` parties each, sized like a real encrypted one
+fn worst_case_metadata(count: u32) -> MessageMetadata {
+	let channel = Channel {
+		nonce: vec![1; NONCE_SIZE + MAC_SIZE],
+		parties: vec![vec![1; KEY_SIZE + MAC_SIZE]; count as usize],
+	};
+	MessageMetadata {
+		nonce: [1; NONCE_SIZE],
+		broker: [1; KEY_SIZE],
+		hash: [1; KEY_SIZE],
+		hash_algo: Default::default(),
+		in_reply_to: Some([1; KEY_SIZE]),
+		entry_count: Some(1),
+		part_count: Some(1),
+		subject: Some([1; SUBJECT_SIZE + MAC_SIZE]),
+		signature: Some([1; 64]),
+		channels: vec![channel; count as usize],
+	}
+}
+
+/// An account with enough balance to reserve any message deposit
+fn funded<T: Config>(who: T::AccountId) -> T::AccountId {
+	T::Currency::make_free_balance_be(&who, BalanceOf::<T>::max_value() / 2u32.into());
+	who
+}
+
+/// The smallest message accepted by `check_message`
+fn min_len<T: Config>() -> u32 {
+	T::MinMessageSize::get().max(1)
+}
+
+fn message<T: Config>(len: u32) -> BoundedVec<u8, T::MaxMessageSize> {
+	vec![1; len as usize].try_into().expect("len is bounded by MaxMessageSize")
+}
+
+benchmarks! {
+	send_message {
+		let l in min_len::<T>() .. T::MaxMessageSize::get();
+		let c in 1 .. T::MaxChannels::get().min(T::MaxPartiesPerChannel::get());
+		let caller = funded::<T>(whitelisted_caller());
+	}: _(
+		RawOrigin::Signed(caller.clone()),
+		worst_case_metadata(c),
+		message::<T>(l),
+		Some(1u32.into()),
+		MessageClass::Normal
+	)
+	verify {
+		assert_eq!(MessageCounter::<T>::get(&caller), 1);
+	}
+
+	// `l` is the total length of the messages, split evenly between the `n` items
+	send_messages {
+		let n in 1 .. T::MaxBatch::get();
+		let l in min_len::<T>() .. T::MaxMessageSize::get();
+		let c = T::MaxChannels::get().min(T::MaxPartiesPerChannel::get());
+		let caller = funded::<T>(whitelisted_caller());
+		let len = (l / n).max(min_len::<T>());
+		let items: BoundedVec<_, _> = (0..n)
+			.map(|_| (worst_case_metadata(c), message::<T>(len)))
+			.collect::<Vec<_>>()
+			.try_into()
+			.expect("n is bounded by MaxBatch");
+	}: _(RawOrigin::Signed(caller.clone()), items)
+	verify {
+		assert_eq!(MessageCounter::<T>::get(&caller), n as u128);
+	}
+
+	check_message {
+		let c in 1 .. T::MaxChannels::get().min(T::MaxPartiesPerChannel::get());
+		let metadata = worst_case_metadata(c);
+		let message = message::<T>(min_len::<T>());
+	}: {
+		Nolik::<T>::check_message(&message, &metadata)?;
+	}
+
+	send_sealed_message {
+		let l in min_len::<T>() .. T::MaxMessageSize::get();
+		let c in 1 .. T::MaxChannels::get().min(T::MaxPartiesPerChannel::get());
+		let payer = funded::<T>(whitelisted_caller());
+		let key = Nolik::<T>::sealed_key(TotalMessages::<T>::get());
+	}: _(RawOrigin::Signed(payer), worst_case_metadata(c), message::<T>(l), Some(1u32.into()))
+	verify {
+		assert!(MessageCommitments::<T>::contains_key(&key));
+		assert!(MessageDeposits::<T>::contains_key(&key));
+	}
+
+	supersede_message {
+		let l in min_len::<T>() .. T::MaxMessageSize::get();
+		let c in 1 .. T::MaxChannels::get().min(T::MaxPartiesPerChannel::get());
+		let caller = funded::<T>(whitelisted_caller());
+		Nolik::<T>::send_message(
+			RawOrigin::Signed(caller.clone()).into(),
+			worst_case_metadata(1),
+			message::<T>(min_len::<T>()),
+			None,
+			MessageClass::Normal,
+		)?;
+		let original_key = Nolik::<T>::derived_key(&caller, 0);
+	}: _(
+		RawOrigin::Signed(caller.clone()),
+		original_key.clone(),
+		worst_case_metadata(c),
+		message::<T>(l)
+	)
+	verify {
+		assert_eq!(
+			LatestVersions::<T>::get(&original_key),
+			Some(Nolik::<T>::derived_key(&caller, 1))
+		);
+	}
+
+	retract_message {
+		let caller = funded::<T>(whitelisted_caller());
+		Nolik::<T>::send_message(
+			RawOrigin::Signed(caller.clone()).into(),
+			worst_case_metadata(1),
+			message::<T>(min_len::<T>()),
+			Some(1u32.into()),
+			MessageClass::Normal,
+		)?;
+	}: _(RawOrigin::Signed(caller.clone()), 0)
+	verify {
+		assert!(MessageCommitments::<T>::get(Nolik::<T>::derived_key(&caller, 0)).is_none());
+	}
+
+	prune_account {
+		let m in 0 .. 1000;
+		let caller = funded::<T>(whitelisted_caller());
+		for _ in 0..m {
+			Nolik::<T>::send_message(
+				RawOrigin::Signed(caller.clone()).into(),
+				worst_case_metadata(1),
+				message::<T>(min_len::<T>()),
+				Some(1u32.into()),
+				MessageClass::Normal,
+			)?;
+			// all messages are sent in the same block
+			SentInBlock::<T>::remove(frame_system::Pallet::<T>::block_number(), &caller);
+		}
+	}: _(RawOrigin::Signed(caller.clone()), m)
+	verify {
+		assert_eq!(PrunedMessages::<T>::get(&caller), m as u128);
+		assert!(MessageCommitments::<T>::iter().next().is_none());
+	}
+
+	acknowledge {
+		let sender = funded::<T>(account("sender", 0, 0));
+		Nolik::<T>::send_message(
+			RawOrigin::Signed(sender.clone()).into(),
+			worst_case_metadata(1),
+			message::<T>(min_len::<T>()),
+			None,
+			MessageClass::Normal,
+		)?;
+		let key = Nolik::<T>::derived_key(&sender, 0);
+		let caller: T::AccountId = whitelisted_caller();
+	}: _(RawOrigin::Signed(caller.clone()), key.clone())
+	verify {
+		assert!(ReadReceipts::<T>::contains_key(&key, &caller));
+	}
+
+	block_sender {
+		let sender: T::AccountId = account("sender", 0, 0);
+		let caller: T::AccountId = whitelisted_caller();
+	}: _(RawOrigin::Signed(caller.clone()), sender.clone())
+	verify {
+		assert!(BlockedSenders::<T>::get(&caller, &sender));
+	}
+
+	unblock_sender {
+		let sender: T::AccountId = account("sender", 0, 0);
+		let caller: T::AccountId = whitelisted_caller();
+		Nolik::<T>::block_sender(RawOrigin::Signed(caller.clone()).into(), sender.clone())?;
+	}: _(RawOrigin::Signed(caller.clone()), sender.clone())
+	verify {
+		assert!(!BlockedSenders::<T>::get(&caller, &sender));
+	}
+
+	register_public_key {
+		let caller: T::AccountId = whitelisted_caller();
+	}: _(RawOrigin::Signed(caller.clone()), [1; KEY_SIZE])
+	verify {
+		assert_eq!(PublicKeys::<T>::get(&caller), Some([1; KEY_SIZE]));
+	}
+
+	rotate_public_key {
+		let caller: T::AccountId = whitelisted_caller();
+		Nolik::<T>::register_public_key(RawOrigin::Signed(caller.clone()).into(), [1; KEY_SIZE])?;
+		let key_type = KeyTypeId(*b"nolk");
+		let new_pk = sp_io::crypto::ed25519_generate(key_type, None);
+		let payload = Nolik::<T>::rotation_payload(&caller);
+		let sig = sp_io::crypto::ed25519_sign(key_type, &new_pk, &payload)
+			.expect("the key is in the keystore");
+	}: _(RawOrigin::Signed(caller.clone()), new_pk.0, sig.0)
+	verify {
+		assert_ne!(PublicKeys::<T>::get(&caller), Some([1; KEY_SIZE]));
+	}
+
+	// the worst case releases a previous alias of the caller
+	set_alias {
+		let caller: T::AccountId = whitelisted_caller();
+		let previous: BoundedVec<_, _> = vec![1; T::MaxAliasLen::get() as usize].try_into().unwrap();
+		Nolik::<T>::set_alias(RawOrigin::Signed(caller.clone()).into(), previous.clone())?;
+		let alias: BoundedVec<_, _> = vec![2; T::MaxAliasLen::get() as usize].try_into().unwrap();
+	}: _(RawOrigin::Signed(caller.clone()), alias.clone())
+	verify {
+		assert_eq!(Aliases::<T>::get(&caller), Some(alias));
+		assert_eq!(AliasOwners::<T>::get(&previous), None);
+	}
+
+	impl_benchmark_test_suite!(Nolik, crate::mock::new_test_ext(), crate::mock::Test);
+}
@@ -5,23 +5,140 @@ mod mock;
 #[cfg(test)]
 mod tests;
 
+#[cfg(feature = "runtime-benchmarks")]
+mod benchmarking;
+pub mod migrations;
+pub mod traits;
+pub mod weights;
+
 pub use pallet::*;
+pub use traits::*;
+pub use weights::*;
 
 #[frame_support::pallet]
 pub mod pallet {
-	use frame_support::{pallet_prelude::*, sp_io::offchain_index};
+	use curve25519_dalek::edwards::CompressedEdwardsY;
+	use frame_support::{
+		dispatch::WithPostDispatchInfo,
+		pallet_prelude::*,
+		sp_io,
+		sp_runtime::{
+			app_crypto::ed25519,
+			offchain::StorageKind,
+			traits::{CheckedSub, Hash, Saturating, Zero},
+		},
+		traits::{Currency, ReservableCurrency},
+		weights::Weight,
+	};
 	use frame_system::pallet_prelude::*;
-	use nolik_metadata::{Channel, MessageMetadata};
+	use nolik_metadata::{join_parts, MessageMetadata, MetadataError, MetadataLimits};
 	use scale_info::prelude::vec::Vec;
 
+	use crate::{MessageStore, OnMessageSent, WeightInfo};
+
+	pub type BalanceOf<T> =
+		<<T as Config>::Currency as Currency<<T as frame_system::Config>::AccountId>>::Balance;
+
+	/// The current storage version, see `crate::migrations`
+	pub const STORAGE_VERSION: StorageVersion = StorageVersion::new(1);
+
+	/// Prefix of off-chain keys of messages, so they don't clash with keys of other users of the
+	/// off-chain index
+	pub const MESSAGE_KEY_PREFIX: &[u8] = b"nolik/msg/";
+
+	/// Prefix of off-chain keys of sealed messages
+	pub const SEALED_KEY_PREFIX: &[u8] = b"nolik/sealed/";
+
+	/// Prefix of the payload signed by a new public key in `rotate_public_key`
+	pub const ROTATE_KEY_PREFIX: &[u8] = b"nolik/rotate-key";
+
+	/// Local storage key of the encoded off-chain keys of committed messages missing from
+	/// off-chain storage, put by the offchain worker so an operator is able to re-seed them
+	pub const MISSING_MESSAGES_KEY: &[u8] = b"nolik/missing";
+
 	#[pallet::pallet]
 	#[pallet::generate_store(pub(super) trait Store)]
+	#[pallet::storage_version(STORAGE_VERSION)]
+	#[pallet::without_storage_info]
 	pub struct Pallet<T>(_);
 
 	#[pallet::config]
 	pub trait Config: frame_system::Config {
 		/// Because this pallet emits events, it depends on the runtime's definition of an event.
 		type RuntimeEvent: From<Event<Self>> + IsType<<Self as frame_system::Config>::RuntimeEvent>;
+
+		/// The maximum size of an encrypted message in bytes
+		#[pallet::constant]
+		type MaxMessageSize: Get<u32>;
+
+		/// The minimum size of an encrypted message in bytes, deters spam with dust messages
+		#[pallet::constant]
+		type MinMessageSize: Get<u32>;
+
+		/// The maximum number of channels in message metadata
+		#[pallet::constant]
+		type MaxChannels: Get<u32>;
+
+		/// The maximum number of parties in a single metadata channel
+		#[pallet::constant]
+		type MaxPartiesPerChannel: Get<u32>;
+
+		/// The maximum size of encoded message metadata in bytes, checked before the channels are
+		/// walked, so the work of the validation is bounded
+		#[pallet::constant]
+		type MaxMetadataBytes: Get<u32>;
+
+		/// The maximum number of entries a message metadata may claim with `entry_count`
+		#[pallet::constant]
+		type MaxEntries: Get<u32>;
+
+		/// The maximum number of messages sent in a single batch
+		#[pallet::constant]
+		type MaxBatch: Get<u32>;
+
+		/// The maximum length of an account alias in bytes
+		#[pallet::constant]
+		type MaxAliasLen: Get<u32>;
+
+		/// The currency to reserve message deposits in
+		type Currency: ReservableCurrency<Self::AccountId>;
+
+		/// The deposit reserved for every byte of a message, released when the message is
+		/// retracted
+		#[pallet::constant]
+		type DepositPerByte: Get<BalanceOf<Self>>;
+
+		/// `MilestoneReached` is emitted every time the total number of messages is a multiple of
+		/// the interval, `0` disables the event
+		#[pallet::constant]
+		type MilestoneInterval: Get<u128>;
+
+		/// The maximum number of calls sending messages an account may make in a single block, so
+		/// a single account can't fill a block
+		#[pallet::constant]
+		type MaxPerBlock: Get<u32>;
+
+		/// Handler called after a message is stored
+		type OnMessageSent: OnMessageSent<Self::AccountId>;
+
+		/// Number of recent blocks whose messages the offchain worker checks to be stored locally
+		#[pallet::constant]
+		type MissingCheckWindow: Get<u32>;
+
+		/// Storage of message payloads, `OffchainIndex` puts them to the local storage of the node
+		type MessageStore: MessageStore;
+
+		/// The maximum number of blocks a message may be kept for with `ttl_blocks`
+		#[pallet::constant]
+		type MaxTtl: Get<Self::BlockNumber>;
+
+		/// The maximum number of messages expiring at a single block, bounds the work of
+		/// `on_initialize` clearing them
+		#[pallet::constant]
+		type MaxExpiringPerBlock: Get<u32>;
+
+		/// Weight information for extrinsics in this pallet.
+		type WeightInfo: WeightInfo;
 	}
 
 	#[pallet::error]
@@ -30,22 +147,261 @@ pub mod pallet {
 		MessageCounterOverflow,
 		/// Message has a bad format
 		MessageMalformed,
+		/// Message is smaller than `MinMessageSize`
+		MessageTooSmall,
 		/// Message metadata has a bad format
 		MetadataMalformed,
+		/// Message with the given counter was not sent or was already retracted
+		MessageNotFound,
+		/// Message metadata has more channels than allowed
+		TooManyChannels,
+		/// Message metadata channel has more parties than allowed
+		TooManyParties,
+		/// Message metadata root hash is not set
+		InvalidMetadataHash,
+		/// Message metadata claims no entries or more than `MaxEntries`
+		InvalidEntryCount,
+		/// The recipient has blocked the sender
+		SenderBlocked,
+		/// The sender can't afford the message deposit
+		InsufficientDeposit,
+		/// The origin is not the sender of the message
+		NotMessageSender,
+		/// The alias is already set by another account
+		AliasTaken,
+		/// The account already sent `MaxPerBlock` times in this block
+		RateLimited,
+		/// The account has not registered a public key to rotate
+		PublicKeyNotRegistered,
+		/// The new public key didn't sign the rotation payload or is not a valid key
+		InvalidKeyProof,
+		/// Encoded message metadata is larger than `MaxMetadataBytes`
+		MetadataTooLarge,
+		/// The account already registered a public key, it can only be replaced with
+		/// `rotate_public_key`
+		PublicKeyAlreadyRegistered,
+		/// `ttl_blocks` is zero or longer than `MaxTtl`
+		InvalidTtl,
+		/// `MaxExpiringPerBlock` messages already expire at the block
+		TooManyExpiring,
+	}
+
+	/// Metadata checks are shared with clients, each rule maps to an error of the pallet
+	impl<T> From<MetadataError> for Error<T> {
+		fn from(error: MetadataError) -> Self {
+			match error {
+				MetadataError::InvalidHash => Error::InvalidMetadataHash,
+				MetadataError::NoChannels |
+				MetadataError::LowOrderBroker |
+				MetadataError::NoParts |
+				MetadataError::InvalidNonce { .. } |
+				MetadataError::PartyCountMismatch { .. } |
+				MetadataError::EmptyParty { .. } => Error::MetadataMalformed,
+				MetadataError::TooManyChannels => Error::TooManyChannels,
+				MetadataError::TooManyParties => Error::TooManyParties,
+				MetadataError::InvalidEntryCount => Error::InvalidEntryCount,
+			}
+		}
 	}
 
 	// Events.
 	#[pallet::event]
 	#[pallet::generate_deposit(pub(super) fn deposit_event)]
 	pub enum Event<T: Config> {
-		/// A new message was sent
-		MessageSent { key: Vec<u8>, metadata: MessageMetadata },
+		/// A new message was sent. `sender` lets indexers filter messages without decoding the
+		/// key, `channel_count` lets subscribers skip messages without attempting to decrypt them
+		/// and `encoded_metadata_len` lets fee estimators account for the metadata size
+		MessageSent {
+			sender: T::AccountId,
+			key: Vec<u8>,
+			metadata: MessageMetadata,
+			channel_count: u32,
+			encoded_metadata_len: u32,
+			class: MessageClass,
+		},
+		/// A new sealed message was sent. The sender is not disclosed, recipients find the origin
+		/// encrypted inside the message
+		SealedMessageSent { key: Vec<u8>, metadata: MessageMetadata, channel_count: u32 },
+		/// A message was retracted by its sender
+		MessageRetracted { key: Vec<u8> },
+		/// Messages of an account were cleared by `prune_account`, `count` of them were found
+		AccountPruned { who: T::AccountId, count: u32 },
+		/// A message was replaced by a new version sent under `new_key`
+		Superseded { original_key: Vec<u8>, new_key: Vec<u8> },
+		/// A message was acknowledged by a recipient
+		MessageAcknowledged { key: Vec<u8>, by: T::AccountId },
+		/// A recipient blocked messages from a sender
+		SenderBlocked { recipient: T::AccountId, sender: T::AccountId },
+		/// A recipient unblocked messages from a sender
+		SenderUnblocked { recipient: T::AccountId, sender: T::AccountId },
+		/// An account registered its encryption public key
+		PublicKeyRegistered { who: T::AccountId, pk: [u8; 32] },
+		/// An account set its alias
+		AliasSet { who: T::AccountId, alias: BoundedVec<u8, T::MaxAliasLen> },
+		/// The total number of sent messages reached a multiple of `MilestoneInterval`
+		MilestoneReached { total: u128 },
+		/// An account replaced its encryption public key with a key it proved to control
+		PublicKeyRotated { who: T::AccountId, pk: [u8; 32] },
 	}
 
-	/// Keeps track of a total number of sent messages by all users
+	/// Keeps track of a number of sent messages by each user
 	#[pallet::storage]
 	#[pallet::getter(fn message_counter)]
-	pub(super) type MessageCounter<T> = StorageValue<_, u128, ValueQuery>;
+	pub(super) type MessageCounter<T: Config> =
+		StorageMap<_, Blake2_128Concat, T::AccountId, u128, ValueQuery>;
+
+	/// Keeps track of a total number of sent messages by all users
+	#[pallet::storage]
+	#[pallet::getter(fn total_messages)]
+	pub(super) type TotalMessages<T> = StorageValue<_, u128, ValueQuery>;
+
+	/// Number of messages of each account already visited by `prune_account`, the next call
+	/// continues from it
+	#[pallet::storage]
+	#[pallet::getter(fn pruned_messages)]
+	pub(super) type PrunedMessages<T: Config> =
+		StorageMap<_, Blake2_128Concat, T::AccountId, u128, ValueQuery>;
+
+	/// Number of calls sending messages each account made in a block, the counts of a block are
+	/// removed in the next one
+	#[pallet::storage]
+	pub(super) type SentInBlock<T: Config> = StorageDoubleMap<
+		_,
+		Twox64Concat,
+		BlockNumberFor<T>,
+		Blake2_128Concat,
+		T::AccountId,
+		u32,
+		ValueQuery,
+	>;
+
+	/// Hash of a message committed by the sender, keyed by the message off-chain key
+	#[pallet::storage]
+	#[pallet::getter(fn message_commitment)]
+	pub(super) type MessageCommitments<T: Config> =
+		StorageMap<_, Blake2_128Concat, Vec<u8>, T::Hash, OptionQuery>;
+
+	/// Block number at which a recipient acknowledged a message, keyed by the message off-chain
+	/// key and the recipient account
+	#[pallet::storage]
+	#[pallet::getter(fn read_receipt)]
+	pub(super) type ReadReceipts<T: Config> = StorageDoubleMap<
+		_,
+		Blake2_128Concat,
+		Vec<u8>,
+		Blake2_128Concat,
+		T::AccountId,
+		T::BlockNumber,
+		OptionQuery,
+	>;
+
+	/// Deposit reserved from the sender, keyed by the message off-chain key
+	#[pallet::storage]
+	#[pallet::getter(fn message_deposit)]
+	pub(super) type MessageDeposits<T: Config> =
+		StorageMap<_, Blake2_128Concat, Vec<u8>, BalanceOf<T>, OptionQuery>;
+
+	/// Block number at which a message expires, keyed by the message off-chain key. Messages
+	/// without an entry never expire
+	#[pallet::storage]
+	#[pallet::getter(fn message_expiry)]
+	pub(super) type MessageExpiry<T: Config> =
+		StorageMap<_, Blake2_128Concat, Vec<u8>, T::BlockNumber, OptionQuery>;
+
+	/// Off-chain keys of messages committed at each block, kept for `MissingCheckWindow` blocks
+	/// so the offchain worker checks recent messages only
+	#[pallet::storage]
+	pub(super) type CommittedMessages<T: Config> = StorageDoubleMap<
+		_,
+		Twox64Concat,
+		T::BlockNumber,
+		Blake2_128Concat,
+		Vec<u8>,
+		(),
+		OptionQuery,
+	>;
+
+	/// Off-chain keys of messages expiring at each block, `on_initialize` of the block clears
+	/// their payloads and removes the entries
+	#[pallet::storage]
+	pub(super) type ExpiringMessages<T: Config> = StorageDoubleMap<
+		_,
+		Twox64Concat,
+		T::BlockNumber,
+		Blake2_128Concat,
+		Vec<u8>,
+		(),
+		OptionQuery,
+	>;
+
+	/// Number of `ExpiringMessages` entries of each block, bounded by `MaxExpiringPerBlock`
+	#[pallet::storage]
+	pub(super) type ExpiringCount<T: Config> =
+		StorageMap<_, Twox64Concat, T::BlockNumber, u32, ValueQuery>;
+
+	/// Senders blocked by each recipient, keyed by the recipient and the sender account
+	#[pallet::storage]
+	#[pallet::getter(fn is_blocked)]
+	pub(super) type BlockedSenders<T: Config> = StorageDoubleMap<
+		_,
+		Blake2_128Concat,
+		T::AccountId,
+		Blake2_128Concat,
+		T::AccountId,
+		bool,
+		ValueQuery,
+	>;
+
+	/// The key of the latest version of a superseded message, keyed by the key of the original
+	/// message
+	#[pallet::storage]
+	#[pallet::getter(fn latest_version)]
+	pub(super) type LatestVersions<T: Config> =
+		StorageMap<_, Blake2_128Concat, Vec<u8>, Vec<u8>, OptionQuery>;
+
+	/// Encryption public key of each account, lets a sender look up recipients by account id
+	#[pallet::storage]
+	#[pallet::getter(fn public_key_of)]
+	pub(super) type PublicKeys<T: Config> =
+		StorageMap<_, Blake2_128Concat, T::AccountId, [u8; 32], OptionQuery>;
+
+	/// Human readable alias of each account
+	#[pallet::storage]
+	#[pallet::getter(fn alias_of)]
+	pub(super) type Aliases<T: Config> =
+		StorageMap<_, Blake2_128Concat, T::AccountId, BoundedVec<u8, T::MaxAliasLen>, OptionQuery>;
+
+	/// Account of each alias, the reverse of `Aliases`. Lets a client resolve an alias to an
+	/// account and keeps aliases unique
+	#[pallet::storage]
+	#[pallet::getter(fn alias_owner)]
+	pub(super) type AliasOwners<T: Config> =
+		StorageMap<_, Blake2_128Concat, BoundedVec<u8, T::MaxAliasLen>, T::AccountId, OptionQuery>;
+
+	/// Class of service of a message sent with `send_message`, recorded in `MessageSent` so
+	/// runtimes and indexers are able to serve classes differently
+	#[derive(
+		Clone, Copy, Debug, Default, Encode, Decode, PartialEq, Eq, TypeInfo, MaxEncodedLen,
+	)]
+	pub enum MessageClass {
+		/// An urgent message, charged twice the weight of a normal one
+		Priority,
+		#[default]
+		Normal,
+		/// A message sent in bulk, e.g. a newsletter. It is charged like a normal message, since
+		/// the weight can't go below the benchmarked one
+		Bulk,
+	}
+
+	impl MessageClass {
+		/// Weight charged for a message of the class, the `weight` of a normal message is scaled
+		pub fn weight(self, weight: Weight) -> Weight {
+			match self {
+				MessageClass::Priority => weight.saturating_mul(2),
+				MessageClass::Normal | MessageClass::Bulk => weight,
+			}
+		}
+	}
 
 	/// The encoded key is used to store a message in off-chain storage
 	#[derive(Debug, Encode, Decode)]
@@ -55,6 +411,68 @@ pub mod pallet {
 		counter: u128,
 	}
 
+	/// The encoded key is used to store a sealed message in off-chain storage, the sender is
+	/// unknown so the key is made unique by the sequence number of the message among all
+	/// messages, which the submitter can't choose
+	#[derive(Debug, Encode, Decode)]
+	pub struct SealedMessageKey {
+		sequence: u128,
+	}
+
+	#[pallet::hooks]
+	impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T> {
+		fn on_runtime_upgrade() -> Weight {
+			crate::migrations::migrate::<T>()
+		}
+
+		/// Clear payloads of messages expiring at the block `now` from `T::MessageStore`, during
+		/// block execution so `offchain_index` writes take effect, and remove their keys. Also
+		/// remove the sending counts of the previous block and the keys of messages committed
+		/// before `MissingCheckWindow`
+		fn on_initialize(now: BlockNumberFor<T>) -> Weight {
+			// `MaxExpiringPerBlock` bounds the entries of a block
+			let mut expired = 0u64;
+			for (key, ()) in ExpiringMessages::<T>::drain_prefix(now) {
+				T::MessageStore::clear(&key);
+				expired += 1;
+			}
+			ExpiringCount::<T>::remove(now);
+			let mut weight = T::DbWeight::get().reads_writes(expired, expired + 1);
+
+			let previous = now.saturating_sub(1u32.into());
+			let removed = SentInBlock::<T>::clear_prefix(previous, u32::MAX, None);
+			weight = weight.saturating_add(
+				T::DbWeight::get().reads_writes(removed.loops.into(), removed.unique.into()),
+			);
+
+			if let Some(old) = now.checked_sub(&T::MissingCheckWindow::get().into()) {
+				let removed = CommittedMessages::<T>::clear_prefix(old, u32::MAX, None);
+				weight = weight.saturating_add(
+					T::DbWeight::get().reads_writes(removed.loops.into(), removed.unique.into()),
+				);
+			}
+			weight
+		}
+
+		/// Flag messages committed in the last `MissingCheckWindow` blocks whose payloads are
+		/// missing under `MISSING_MESSAGES_KEY`
+		fn offchain_worker(now: BlockNumberFor<T>) {
+			let missing = Self::missing_keys(now);
+			if !missing.is_empty() {
+				frame_support::log::warn!(
+					target: "runtime::nolik",
+					"{} committed messages are missing from off-chain storage",
+					missing.len(),
+				);
+			}
+			sp_io::offchain::local_storage_set(
+				StorageKind::PERSISTENT,
+				MISSING_MESSAGES_KEY,
+				&missing.encode(),
+			);
+		}
+	}
+
 	#[pallet::call]
 	impl<T: Config> Pallet<T> {
 		/// Send the `message`.
@@ -65,50 +483,646 @@ pub mod pallet {
 		/// # Arguments
 		///
 		/// * `metadata` - Metadata to describe the message and to decrypt it
-		/// * `message` - Encrypted message data, possibly having a big size. We pass message as raw
-		///   bytes so no encoding and no heap allocation is needed prior to putting the message to
-		///   off-chain storage.
+		/// * `message` - Encrypted message data, possibly having a big size, bounded by
+		///   `MaxMessageSize`. We pass message as raw bytes so no encoding is needed prior to
+		///   putting the message to off-chain storage.
+		/// * `ttl_blocks` - Number of blocks after which the message is pruned from off-chain
+		///   storage, from 1 to `MaxTtl`, `None` to keep it forever
+		/// * `class` - Class of service of the message, scales the charged weight
+		///
+		/// A message rejected by `check_message` is only charged for the check. An account may
+		/// make at most `MaxPerBlock` calls sending messages in a block.
 		#[pallet::call_index(0)]
-		// SBP-M1 review: Implement benchmarking and use benchmarked weight
-		#[pallet::weight(10_000)]
+		#[pallet::weight(class.weight(T::WeightInfo::send_message(
+			message.len() as u32,
+			metadata.channels.len() as u32,
+		)))]
 		pub fn send_message(
 			origin: OriginFor<T>,
 			metadata: MessageMetadata,
-			// SBP-M1 review: BoundedVec should be used to improve security
-			message: Vec<u8>,
+			message: BoundedVec<u8, T::MaxMessageSize>,
+			ttl_blocks: Option<T::BlockNumber>,
+			class: MessageClass,
+		) -> DispatchResultWithPostInfo {
+			let account = ensure_signed(origin)?;
+			Self::rate_limit(&account)?;
+			// nothing is written yet, so refund the weight of storing the message
+			Self::check_message(&message, &metadata).map_err(|e| {
+				e.with_weight(T::WeightInfo::check_message(metadata.channels.len() as u32))
+			})?;
+
+			Self::store_message(&account, &message, metadata, ttl_blocks, class)?;
+			Ok(().into())
+		}
+
+		/// Retract a message previously sent by the origin.
+		///
+		/// Clears the message from off-chain storage, removes its on-chain commitment and releases
+		/// the deposit. The key is derived from the signed origin, so only the sender is able to
+		/// retract a message.
+		///
+		/// # Arguments
+		///
+		/// * `counter` - Sequence number of the sender's message to retract
+		#[pallet::call_index(1)]
+		#[pallet::weight(T::WeightInfo::retract_message())]
+		pub fn retract_message(origin: OriginFor<T>, counter: u128) -> DispatchResult {
+			let account = ensure_signed(origin)?;
+
+			let key = Self::derived_key(&account, counter);
+			ensure!(Self::remove_message(&account, &key), <Error<T>>::MessageNotFound);
+			Self::deposit_event(Event::MessageRetracted { key });
+
+			Ok(())
+		}
+
+		/// Clear all messages sent by the origin, e.g. when leaving the service.
+		///
+		/// Every message is removed as with `retract_message`. At most `max` messages are
+		/// visited in a call, starting where the previous call stopped, so an account with many
+		/// messages is pruned with several calls. The weight of messages not visited is refunded.
+		///
+		/// # Arguments
+		///
+		/// * `max` - Maximum number of messages to visit in this call
+		#[pallet::call_index(11)]
+		#[pallet::weight(T::WeightInfo::prune_account(*max))]
+		pub fn prune_account(origin: OriginFor<T>, max: u32) -> DispatchResultWithPostInfo {
+			let account = ensure_signed(origin)?;
+
+			let start = PrunedMessages::<T>::get(&account);
+			let end = MessageCounter::<T>::get(&account).min(start.saturating_add(max.into()));
+
+			let mut count = 0;
+			for counter in start..end {
+				if Self::remove_message(&account, &Self::derived_key(&account, counter)) {
+					count += 1;
+				}
+			}
+			PrunedMessages::<T>::insert(&account, end);
+			Self::deposit_event(Event::AccountPruned { who: account, count });
+
+			// `end - start` is at most `max`
+			Ok(Some(T::WeightInfo::prune_account((end - start) as u32)).into())
+		}
+
+		/// Acknowledge a message was read by the origin.
+		///
+		/// Only the first acknowledgement is recorded, acknowledging the same message again is a
+		/// no-op.
+		///
+		/// # Arguments
+		///
+		/// * `key` - Off-chain key of the message, as emitted in the `MessageSent` event
+		#[pallet::call_index(2)]
+		#[pallet::weight(T::WeightInfo::acknowledge())]
+		pub fn acknowledge(origin: OriginFor<T>, key: Vec<u8>) -> DispatchResult {
+			let account = ensure_signed(origin)?;
+			ensure!(MessageCommitments::<T>::contains_key(&key), <Error<T>>::MessageNotFound);
+
+			if ReadReceipts::<T>::contains_key(&key, &account) {
+				return Ok(())
+			}
+
+			let block_number = <frame_system::Pallet<T>>::block_number();
+			ReadReceipts::<T>::insert(&key, &account, block_number);
+			Self::deposit_event(Event::MessageAcknowledged { key, by: account });
+
+			Ok(())
+		}
+
+		/// Send a batch of messages.
+		///
+		/// Every message is checked before any of them is stored, so either the whole batch is
+		/// sent or nothing is. Each message gets its own key and `MessageSent` event.
+		///
+		/// # Arguments
+		///
+		/// * `items` - Pairs of metadata and encrypted message data, as in `send_message`
+		///
+		/// Every item is charged a fixed overhead on top of its bytes, so packing many tiny
+		/// messages in a batch costs more than sending the same bytes in a single message.
+		#[pallet::call_index(3)]
+		#[pallet::weight(T::WeightInfo::send_messages(
+			items.len() as u32,
+			items.iter().map(|(_, message)| message.len() as u32).sum(),
+		))]
+		pub fn send_messages(
+			origin: OriginFor<T>,
+			items: BoundedVec<(MessageMetadata, BoundedVec<u8, T::MaxMessageSize>), T::MaxBatch>,
 		) -> DispatchResult {
 			let account = ensure_signed(origin)?;
+			Self::rate_limit(&account)?;
+			for (metadata, message) in items.iter() {
+				Self::check_message(message, metadata)?;
+			}
+
+			for (metadata, message) in items.into_iter() {
+				Self::store_message(&account, &message, metadata, None, MessageClass::Normal)?;
+			}
+
+			Ok(())
+		}
+
+		/// Block messages from the `sender` to the origin.
+		///
+		/// The block list is only checked by `send_message_to`, since recipients of other messages
+		/// are encrypted.
+		///
+		/// # Arguments
+		///
+		/// * `sender` - Account to block
+		#[pallet::call_index(4)]
+		#[pallet::weight(T::WeightInfo::block_sender())]
+		pub fn block_sender(origin: OriginFor<T>, sender: T::AccountId) -> DispatchResult {
+			let recipient = ensure_signed(origin)?;
+
+			BlockedSenders::<T>::insert(&recipient, &sender, true);
+			Self::deposit_event(Event::SenderBlocked { recipient, sender });
+
+			Ok(())
+		}
+
+		/// Unblock messages from the `sender` to the origin.
+		///
+		/// # Arguments
+		///
+		/// * `sender` - Account to unblock
+		#[pallet::call_index(5)]
+		#[pallet::weight(T::WeightInfo::unblock_sender())]
+		pub fn unblock_sender(origin: OriginFor<T>, sender: T::AccountId) -> DispatchResult {
+			let recipient = ensure_signed(origin)?;
+
+			BlockedSenders::<T>::remove(&recipient, &sender);
+			Self::deposit_event(Event::SenderUnblocked { recipient, sender });
+
+			Ok(())
+		}
+
+		/// Send the `message` with a cleartext hint of its recipients.
+		///
+		/// Works as `send_message`, but fails if any of the `recipients` has blocked the origin.
+		/// The hint discloses the recipients, so it is opt-in.
+		///
+		/// # Arguments
+		///
+		/// * `metadata` - Metadata to describe the message and to decrypt it
+		/// * `message` - Encrypted message data, bounded by `MaxMessageSize`
+		/// * `recipients` - Accounts the message is sent to
+		#[pallet::call_index(6)]
+		#[pallet::weight(T::WeightInfo::send_message(
+			message.len() as u32,
+			metadata.channels.len() as u32,
+		)
+		.saturating_add(T::DbWeight::get().reads(recipients.len() as u64)))]
+		pub fn send_message_to(
+			origin: OriginFor<T>,
+			metadata: MessageMetadata,
+			message: BoundedVec<u8, T::MaxMessageSize>,
+			recipients: BoundedVec<T::AccountId, T::MaxChannels>,
+		) -> DispatchResult {
+			let account = ensure_signed(origin)?;
+			Self::rate_limit(&account)?;
 			Self::check_message(&message, &metadata)?;
 
-			let counter = MessageCounter::<T>::get();
+			for recipient in &recipients {
+				ensure!(!Self::is_blocked(recipient, &account), <Error<T>>::SenderBlocked);
+			}
 
-			// SBP-M1: Can be simplified like this `counter.checked_add(1).ok_or(<Error<T>>::MessageCounterOverflow)?`
-			let (counter, overflowed) = counter.overflowing_add(1);
-			// u128 should not overflow, practically impossible
-			if overflowed {
-				Err(<Error<T>>::MessageCounterOverflow)?;
+			Self::store_message(&account, &message, metadata, None, MessageClass::Normal)
+		}
+
+		/// Register an encryption public key of the origin.
+		///
+		/// A key is registered once, `rotate_public_key` replaces it with a key the origin proves
+		/// to control.
+		///
+		/// # Arguments
+		///
+		/// * `pk` - `crypto_box` public key used to encrypt messages to the origin
+		#[pallet::call_index(7)]
+		#[pallet::weight(T::WeightInfo::register_public_key())]
+		pub fn register_public_key(origin: OriginFor<T>, pk: [u8; 32]) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+			ensure!(!PublicKeys::<T>::contains_key(&who), <Error<T>>::PublicKeyAlreadyRegistered);
+
+			PublicKeys::<T>::insert(&who, pk);
+			Self::deposit_event(Event::PublicKeyRegistered { who, pk });
+
+			Ok(())
+		}
+
+		/// Replace the encryption public key registered by the origin with a key it controls.
+		///
+		/// `crypto_box` keys can't sign, so the new key is given in its Ed25519 form, which signs
+		/// `rotation_payload` of the origin. The X25519 form of it is registered, the same as
+		/// `crypto_sign_ed25519_pk_to_curve25519` of libsodium derives, so a key of another user
+		/// can't be registered without its secret key.
+		///
+		/// # Arguments
+		///
+		/// * `new_pk` - Ed25519 public key, its X25519 form is registered
+		/// * `sig` - Ed25519 signature of `rotation_payload` of the origin by `new_pk`
+		#[pallet::call_index(12)]
+		#[pallet::weight(T::WeightInfo::rotate_public_key())]
+		pub fn rotate_public_key(
+			origin: OriginFor<T>,
+			new_pk: [u8; 32],
+			sig: [u8; 64],
+		) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+			ensure!(PublicKeys::<T>::contains_key(&who), <Error<T>>::PublicKeyNotRegistered);
+
+			let signed = sp_io::crypto::ed25519_verify(
+				&ed25519::Signature::from_raw(sig),
+				&Self::rotation_payload(&who),
+				&ed25519::Public::from_raw(new_pk),
+			);
+			ensure!(signed, <Error<T>>::InvalidKeyProof);
+			let pk = CompressedEdwardsY(new_pk)
+				.decompress()
+				.ok_or(<Error<T>>::InvalidKeyProof)?
+				.to_montgomery()
+				.to_bytes();
+
+			PublicKeys::<T>::insert(&who, pk);
+			Self::deposit_event(Event::PublicKeyRotated { who, pk });
+
+			Ok(())
+		}
+
+		/// Set a human readable alias of the origin.
+		///
+		/// Aliases are unique, an alias set by another account is rejected. A previously set
+		/// alias of the origin is released, so another account may claim it.
+		///
+		/// # Arguments
+		///
+		/// * `alias` - Alias to resolve to the origin, bounded by `MaxAliasLen`
+		#[pallet::call_index(10)]
+		#[pallet::weight(T::WeightInfo::set_alias())]
+		pub fn set_alias(
+			origin: OriginFor<T>,
+			alias: BoundedVec<u8, T::MaxAliasLen>,
+		) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+
+			match AliasOwners::<T>::get(&alias) {
+				Some(owner) if owner != who => Err(<Error<T>>::AliasTaken)?,
+				_ => {},
 			}
 
-			let key = Self::derived_key(&account, counter - 1);
-			// SBP-M1 review: please remove commented code
-			// frame_support::log::info!("The offchain key !!! {:02x?}", key);
+			if let Some(previous) = Aliases::<T>::get(&who) {
+				AliasOwners::<T>::remove(&previous);
+			}
+			Aliases::<T>::insert(&who, &alias);
+			AliasOwners::<T>::insert(&alias, &who);
+			Self::deposit_event(Event::AliasSet { who, alias });
 
-			// save message to offchain storage
-			offchain_index::set(&key, &message);
-			// update the message counter
-			MessageCounter::<T>::put(counter);
-			// emit an event
-			Self::deposit_event(Event::MessageSent { key, metadata });
+			Ok(())
+		}
+
+		/// Supersede a message previously sent by the origin with a new version, e.g. to edit it.
+		///
+		/// The new version is sent as with `send_message` and the original message is kept.
+		/// `LatestVersions` maps the original key to the key of the latest version, so the
+		/// original key should be passed for every new version, superseding a new version
+		/// starts a separate chain.
+		///
+		/// # Arguments
+		///
+		/// * `original_key` - Off-chain key of the superseded message, as emitted in the
+		///   `MessageSent` event
+		/// * `metadata` - Metadata to describe the new version and to decrypt it
+		/// * `message` - Encrypted data of the new version, bounded by `MaxMessageSize`
+		#[pallet::call_index(9)]
+		#[pallet::weight(T::WeightInfo::supersede_message(
+			message.len() as u32,
+			metadata.channels.len() as u32,
+		))]
+		pub fn supersede_message(
+			origin: OriginFor<T>,
+			original_key: Vec<u8>,
+			metadata: MessageMetadata,
+			message: BoundedVec<u8, T::MaxMessageSize>,
+		) -> DispatchResult {
+			let account = ensure_signed(origin)?;
+			Self::rate_limit(&account)?;
+			ensure!(
+				MessageCommitments::<T>::contains_key(&original_key),
+				<Error<T>>::MessageNotFound
+			);
+			// the sender is encoded in the key, sealed messages have no sender
+			match Self::decode_key(&original_key) {
+				Some((sender, _)) if sender == account => {},
+				_ => Err(<Error<T>>::NotMessageSender)?,
+			}
+			Self::check_message(&message, &metadata)?;
+
+			let new_key = Self::derived_key(&account, MessageCounter::<T>::get(&account));
+			Self::store_message(&account, &message, metadata, None, MessageClass::Normal)?;
+
+			LatestVersions::<T>::insert(&original_key, &new_key);
+			Self::deposit_event(Event::Superseded { original_key, new_key });
+
+			Ok(())
+		}
+
+		/// Send the `message` without revealing the sender account.
+		///
+		/// The call is signed by a fee payer, e.g. a relayer, not by the sender. The payer is
+		/// charged the transaction fee for the bytes of the message, reserves the same deposit as
+		/// `send_message` and is rate limited like other senders. The origin of the message is
+		/// expected to be encrypted inside it, so only recipients are able to attribute it. A
+		/// sealed message can't be retracted, so it should be sent with `ttl_blocks` set.
+		///
+		/// # Arguments
+		///
+		/// * `metadata` - Metadata to describe the message and to decrypt it
+		/// * `message` - Encrypted message data, bounded by `MaxMessageSize`
+		/// * `ttl_blocks` - Number of blocks after which the message is pruned from off-chain
+		///   storage, from 1 to `MaxTtl`, `None` to keep it forever
+		#[pallet::call_index(8)]
+		#[pallet::weight(T::WeightInfo::send_sealed_message(
+			message.len() as u32,
+			metadata.channels.len() as u32,
+		))]
+		pub fn send_sealed_message(
+			origin: OriginFor<T>,
+			metadata: MessageMetadata,
+			message: BoundedVec<u8, T::MaxMessageSize>,
+			ttl_blocks: Option<T::BlockNumber>,
+		) -> DispatchResult {
+			let payer = ensure_signed(origin)?;
+			Self::rate_limit(&payer)?;
+			Self::check_message(&message, &metadata)?;
+
+			let sequence = TotalMessages::<T>::get();
+			let total = sequence.checked_add(1).ok_or(<Error<T>>::MessageCounterOverflow)?;
+
+			let key = Self::sealed_key(sequence);
+
+			// reserve a deposit for the off-chain storage of the message
+			let deposit = T::DepositPerByte::get().saturating_mul((message.len() as u32).into());
+			T::Currency::reserve(&payer, deposit).map_err(|_| <Error<T>>::InsufficientDeposit)?;
+
+			Self::put_message(&key, &message, ttl_blocks)?;
+			MessageDeposits::<T>::insert(&key, deposit);
+
+			let channel_count = metadata.channels.len() as u32;
+			T::OnMessageSent::on_message_sent(&payer, &key, channel_count);
+			Self::deposit_event(Event::SealedMessageSent { key, metadata, channel_count });
+			Self::put_total(total);
 
 			Ok(())
 		}
 	}
 
 	impl<T: Config> Pallet<T> {
-		/// Combines a user account with a message counter to make it unique
+		/// Whether the `account` registered an encryption public key, messages can't be
+		/// encrypted to an account without one
+		pub fn is_registered(account: &T::AccountId) -> bool {
+			PublicKeys::<T>::contains_key(account)
+		}
+
+		/// Combines a user account with its message counter to make it unique
 		pub fn derived_key(account: &T::AccountId, counter: u128) -> Vec<u8> {
-			// e.g. "my_account_id/623451"
-			MessageKey::<T> { account, counter }.encode()
+			// e.g. "nolik/msg/my_account_id/623451"
+			let mut key = MESSAGE_KEY_PREFIX.to_vec();
+			MessageKey::<T> { account, counter }.encode_to(&mut key);
+			key
+		}
+
+		/// Inverse of `derived_key`, decodes an off-chain key back into the sender account and the
+		/// message sequence number. Returns `None` if the key is malformed.
+		pub fn decode_key(bytes: &[u8]) -> Option<(T::AccountId, u128)> {
+			let mut input = bytes.strip_prefix(MESSAGE_KEY_PREFIX)?;
+			let decoded = <(T::AccountId, u128)>::decode(&mut input).ok()?;
+			// the whole key has to be consumed
+			input.is_empty().then_some(decoded)
+		}
+
+		/// Check the off-chain `key`, e.g. of a `MessageSent` event, is a key of a message sent by
+		/// the `expected_sender`. Guards a client against an indexer mislabeling messages
+		pub fn verify_key(key: &[u8], expected_sender: &T::AccountId) -> bool {
+			Self::decode_key(key).map_or(false, |(sender, _)| sender == *expected_sender)
+		}
+
+		/// Payload the new key signs in `rotate_public_key` of the `account`
+		pub fn rotation_payload(account: &T::AccountId) -> Vec<u8> {
+			let mut payload = ROTATE_KEY_PREFIX.to_vec();
+			account.encode_to(&mut payload);
+			payload
+		}
+
+		/// Off-chain key of a sealed message sent as the message number `sequence` of all
+		/// messages, as counted by `TotalMessages`
+		pub fn sealed_key(sequence: u128) -> Vec<u8> {
+			let mut key = SEALED_KEY_PREFIX.to_vec();
+			SealedMessageKey { sequence }.encode_to(&mut key);
+			key
+		}
+
+		/// Count a call of the `account` sending messages in the current block, failing with
+		/// `RateLimited` once it made `MaxPerBlock` calls. A failed call reverts the count
+		fn rate_limit(account: &T::AccountId) -> DispatchResult {
+			let now = <frame_system::Pallet<T>>::block_number();
+			let sent = SentInBlock::<T>::get(now, account);
+			ensure!(sent < T::MaxPerBlock::get(), <Error<T>>::RateLimited);
+			SentInBlock::<T>::insert(now, account, sent + 1);
+			Ok(())
+		}
+
+		/// Put the `message` to off-chain storage under the next key of the `account`, commit to
+		/// it on-chain and emit an event
+		fn store_message(
+			account: &T::AccountId,
+			message: &[u8],
+			metadata: MessageMetadata,
+			ttl_blocks: Option<T::BlockNumber>,
+			class: MessageClass,
+		) -> DispatchResult {
+			let counter = MessageCounter::<T>::get(account);
+			// u128 should not overflow, practically impossible
+			let next_counter = counter.checked_add(1).ok_or(<Error<T>>::MessageCounterOverflow)?;
+
+			let total = TotalMessages::<T>::get()
+				.checked_add(1)
+				.ok_or(<Error<T>>::MessageCounterOverflow)?;
+
+			let key = Self::derived_key(account, counter);
+
+			// reserve a deposit for the off-chain storage of the message
+			let deposit = T::DepositPerByte::get().saturating_mul((message.len() as u32).into());
+			T::Currency::reserve(account, deposit).map_err(|_| <Error<T>>::InsufficientDeposit)?;
+
+			Self::put_message(&key, message, ttl_blocks)?;
+			MessageDeposits::<T>::insert(&key, deposit);
+			// update the message counter
+			MessageCounter::<T>::insert(account, next_counter);
+			// emit an event
+			let channel_count = metadata.channels.len() as u32;
+			let encoded_metadata_len = metadata.encoded_size() as u32;
+			T::OnMessageSent::on_message_sent(account, &key, channel_count);
+			Self::deposit_event(Event::MessageSent {
+				sender: account.clone(),
+				key,
+				metadata,
+				channel_count,
+				encoded_metadata_len,
+				class,
+			});
+			Self::put_total(total);
+
+			Ok(())
+		}
+
+		/// Clear a message of the `account` from off-chain storage, remove its commitment and
+		/// release the deposit. Returns `false` if there is no such message
+		fn remove_message(account: &T::AccountId, key: &[u8]) -> bool {
+			if MessageCommitments::<T>::take(key).is_none() {
+				return false
+			}
+
+			// clear message from offchain storage
+			T::MessageStore::clear(key);
+			if let Some(expiry) = MessageExpiry::<T>::take(key) {
+				ExpiringMessages::<T>::remove(expiry, key);
+				ExpiringCount::<T>::mutate(expiry, |count| *count = count.saturating_sub(1));
+			}
+			if let Some(deposit) = MessageDeposits::<T>::take(key) {
+				T::Currency::unreserve(account, deposit);
+			}
+			true
+		}
+
+		/// Put the `message` to off-chain storage under the `key`, commit to it on-chain and set
+		/// its expiry. Fails before anything is stored if `ttl_blocks` is zero, longer than
+		/// `MaxTtl` or the expiry block already has `MaxExpiringPerBlock` messages
+		fn put_message(
+			key: &[u8],
+			message: &[u8],
+			ttl_blocks: Option<T::BlockNumber>,
+		) -> DispatchResult {
+			let now = <frame_system::Pallet<T>>::block_number();
+			// a message expiring now would never be cleared, `on_initialize` already ran
+			let expiry = match ttl_blocks {
+				Some(ttl_blocks) => {
+					ensure!(
+						!ttl_blocks.is_zero() && ttl_blocks <= T::MaxTtl::get(),
+						<Error<T>>::InvalidTtl
+					);
+					let expiry = now.saturating_add(ttl_blocks);
+					let count = ExpiringCount::<T>::get(expiry);
+					ensure!(count < T::MaxExpiringPerBlock::get(), <Error<T>>::TooManyExpiring);
+					Some((expiry, count))
+				},
+				None => None,
+			};
+
+			// save message to offchain storage
+			Self::store_offchain(key, message);
+			// commit to the message bytes on-chain
+			MessageCommitments::<T>::insert(key, T::Hashing::hash(message));
+			CommittedMessages::<T>::insert(now, key, ());
+			if let Some((expiry, count)) = expiry {
+				MessageExpiry::<T>::insert(key, expiry);
+				ExpiringMessages::<T>::insert(expiry, key, ());
+				ExpiringCount::<T>::insert(expiry, count + 1);
+			}
+			Ok(())
+		}
+
+		/// Whether extrinsics of a block are being applied, the only time `offchain_index` writes
+		/// take effect. Elsewhere, e.g. in an offchain worker or a runtime API call, they are
+		/// dropped silently
+		pub fn in_block_execution() -> bool {
+			<frame_system::Pallet<T>>::extrinsic_index().is_some()
+		}
+
+		/// Put the `value` to off-chain storage under the `key` with `T::MessageStore`. An
+		/// `offchain_index` write is a no-op outside of block execution, so a warning is logged
+		/// then
+		pub fn store_offchain(key: &[u8], value: &[u8]) {
+			if !Self::in_block_execution() {
+				frame_support::log::warn!(
+					target: "runtime::nolik",
+					"off-chain index write outside of block execution is dropped",
+				);
+			}
+			T::MessageStore::put(key, value);
+		}
+
+		/// Update the total number of messages, emitting `MilestoneReached` on every
+		/// `MilestoneInterval`
+		fn put_total(total: u128) {
+			TotalMessages::<T>::put(total);
+
+			let interval = T::MilestoneInterval::get();
+			if interval != 0 && total % interval == 0 {
+				Self::deposit_event(Event::MilestoneReached { total });
+			}
+		}
+
+		/// Keys of messages expiring at the block `now`, not cleared yet by `on_initialize` of the
+		/// block
+		pub fn expired_keys(now: T::BlockNumber) -> Vec<Vec<u8>> {
+			ExpiringMessages::<T>::iter_key_prefix(now).collect()
+		}
+
+		/// Keys of messages committed on-chain in the last `MissingCheckWindow` blocks up to `now`,
+		/// not retracted nor expired, but missing from off-chain storage, e.g. lost by a node with
+		/// volatile storage. Should be called from an off-chain context, like `read_message`
+		pub fn missing_keys(now: T::BlockNumber) -> Vec<Vec<u8>> {
+			(0..T::MissingCheckWindow::get())
+				.filter_map(|age| now.checked_sub(&age.into()))
+				.flat_map(CommittedMessages::<T>::iter_key_prefix)
+				.filter(|key| MessageCommitments::<T>::contains_key(key))
+				.filter(|key| MessageExpiry::<T>::get(key).map_or(true, |expiry| expiry > now))
+				.filter(|key| T::MessageStore::get(key).is_none())
+				.collect()
+		}
+
+		/// Read a message of the `account` from off-chain storage, should be called from an
+		/// off-chain context, e.g. an offchain worker or RPC
+		pub fn read_message(account: &T::AccountId, counter: u128) -> Option<Vec<u8>> {
+			let key = Self::derived_key(account, counter);
+			T::MessageStore::get(&key)
+		}
+
+		/// Off-chain key of the `part` of a message split into parts, the first of them sent with
+		/// the `counter`. Parts are sent as consecutive messages, e.g. in one `send_messages` batch
+		pub fn part_key(account: &T::AccountId, counter: u128, part: u32) -> Vec<u8> {
+			Self::derived_key(account, counter.saturating_add(part.into()))
+		}
+
+		/// Read all `part_count` parts of a message of the `account` from off-chain storage and
+		/// join them, `None` if any part is missing. Should be called from an off-chain context,
+		/// like `read_message`
+		pub fn read_parts(
+			account: &T::AccountId,
+			counter: u128,
+			part_count: u32,
+		) -> Option<Vec<u8>> {
+			let parts = (0..part_count)
+				.map(|part| T::MessageStore::get(&Self::part_key(account, counter, part)))
+				.collect::<Option<Vec<_>>>()?;
+			Some(join_parts(parts))
+		}
+
+		/// Iterate over the messages of the `account` available in off-chain storage in the order
+		/// they were sent, along with their counters. Retracted and expired messages are skipped.
+		/// Should be called from an off-chain context, like `read_message`
+		pub fn iter_messages(account: &T::AccountId) -> impl Iterator<Item = (u128, Vec<u8>)> + '_ {
+			(0..MessageCounter::<T>::get(account)).filter_map(move |counter| {
+				Self::read_message(account, counter).map(|message| (counter, message))
+			})
+		}
+
+		/// Check the `message` fetched from off-chain storage matches the sender's commitment
+		pub fn verify_message(key: &[u8], message: &[u8]) -> bool {
+			MessageCommitments::<T>::get(key)
+				.map_or(false, |hash| hash == T::Hashing::hash(message))
 		}
 
 		/// Check message format is valid
@@ -117,23 +1131,21 @@ pub mod pallet {
 				Err(<Error<T>>::MessageMalformed)?;
 			}
 
-			if metadata.channels.is_empty() {
-				Err(<Error<T>>::MetadataMalformed)?;
+			if message.len() < T::MinMessageSize::get() as usize {
+				Err(<Error<T>>::MessageTooSmall)?;
 			}
 
-			for Channel { nonce, parties } in &metadata.channels {
-				if nonce.is_empty() ||
-					parties.is_empty() || parties.len() != metadata.channels.len()
-				{
-					Err(<Error<T>>::MetadataMalformed)?;
-				}
-
-				for part in parties {
-					if part.is_empty() {
-						Err(<Error<T>>::MetadataMalformed)?;
-					}
-				}
+			// the size is cheap to compute, unlike the checks of every channel and party
+			if metadata.encoded_size() > T::MaxMetadataBytes::get() as usize {
+				Err(<Error<T>>::MetadataTooLarge)?;
 			}
+
+			let limits = MetadataLimits {
+				max_channels: T::MaxChannels::get(),
+				max_parties_per_channel: T::MaxPartiesPerChannel::get(),
+				max_entries: T::MaxEntries::get(),
+			};
+			metadata.validate(&limits).map_err(<Error<T>>::from)?;
 			Ok(())
 		}
 	}
@@ -0,0 +1,46 @@
+//! Storage migrations of the pallet, run from `on_runtime_upgrade`
+
+use crate::{Config, Pallet, TotalMessages};
+use frame_support::{
+	pallet_prelude::*,
+	traits::{GetStorageVersion, StorageVersion},
+	weights::Weight,
+};
+
+/// Storage items of the pallet before it was versioned
+pub(crate) mod v0 {
+	use super::*;
+
+	/// A single message counter shared by all accounts
+	#[frame_support::storage_alias]
+	pub(crate) type MessageCounter<T: Config> = StorageValue<Pallet<T>, u128, ValueQuery>;
+}
+
+/// Run migrations not yet applied to the on-chain storage
+pub fn migrate<T: Config>() -> Weight {
+	let on_chain = Pallet::<T>::on_chain_storage_version();
+	let mut weight = T::DbWeight::get().reads(1);
+
+	if on_chain < 1 {
+		weight = weight.saturating_add(v1::migrate::<T>());
+	}
+
+	weight
+}
+
+/// Version 1 counts messages per account and keeps a total of all messages
+pub mod v1 {
+	use super::*;
+
+	/// Move the shared message counter to `TotalMessages`.
+	///
+	/// The shared counter was given to messages of all accounts, so it tells how many messages
+	/// were sent in total, but not by whom. Per-account counters start from zero.
+	pub fn migrate<T: Config>() -> Weight {
+		let legacy = v0::MessageCounter::<T>::take();
+		TotalMessages::<T>::mutate(|total| *total = (*total).max(legacy));
+		StorageVersion::new(1).put::<Pallet<T>>();
+
+		T::DbWeight::get().reads_writes(2, 3)
+	}
+}
@@ -1,11 +1,17 @@
 use crate as pallet_nolik;
-use frame_support::{parameter_types, sp_io};
+use crate::{MessageStore, OffchainIndex};
+use frame_support::{
+	parameter_types, sp_io,
+	traits::{ConstU128, ConstU32, ConstU64},
+};
 use frame_system as system;
 use sp_core::offchain::{testing, OffchainDbExt, OffchainWorkerExt};
+use sp_keystore::{testing::KeyStore, KeystoreExt};
 use sp_runtime::{
 	testing::{Header, H256},
 	traits::{BlakeTwo256, IdentityLookup},
 };
+use std::{cell::RefCell, collections::HashMap, sync::Arc};
 
 type UncheckedExtrinsic = frame_system::mocking::MockUncheckedExtrinsic<Test>;
 type Block = frame_system::mocking::MockBlock<Test>;
@@ -18,6 +24,7 @@ frame_support::construct_runtime!(
 		UncheckedExtrinsic = UncheckedExtrinsic,
 	{
 		System: frame_system,
+		Balances: pallet_balances,
 		Nolik: pallet_nolik,
 	}
 );
@@ -45,31 +52,131 @@ impl system::Config for Test {
 	type BlockHashCount = BlockHashCount;
 	type Version = ();
 	type PalletInfo = PalletInfo;
-	type AccountData = ();
+	type AccountData = pallet_balances::AccountData<u64>;
 	type OnNewAccount = ();
 	type OnKilledAccount = ();
 	type SystemWeightInfo = ();
 	type SS58Prefix = SS58Prefix;
 	type OnSetCode = ();
-	type MaxConsumers = frame_support::traits::ConstU32<16>;
+	type MaxConsumers = ConstU32<16>;
+}
+
+impl pallet_balances::Config for Test {
+	type MaxLocks = ();
+	type MaxReserves = ();
+	type ReserveIdentifier = [u8; 8];
+	type Balance = u64;
+	type RuntimeEvent = RuntimeEvent;
+	type DustRemoval = ();
+	type ExistentialDeposit = ConstU64<1>;
+	type AccountStore = System;
+	type WeightInfo = ();
 }
 
 parameter_types! {
+	pub const MaxMessageSize: u32 = 1024;
+	// every party has a channel, so the channels limit is only reachable with as many parties
+	pub const MaxChannels: u32 = 4;
+	pub const MaxPartiesPerChannel: u32 = 8;
+	pub const MaxBatch: u32 = 16;
+	pub static MinMessageSize: u32 = 1;
+	pub static MaxPerBlock: u32 = 16;
+	pub static MaxMetadataBytes: u32 = 4096;
+	pub static MissingCheckWindow: u32 = 4;
+	pub const MaxTtl: u64 = 100;
+	pub static MaxExpiringPerBlock: u32 = 8;
 }
 
 impl pallet_nolik::Config for Test {
 	type RuntimeEvent = RuntimeEvent;
+	type MaxMessageSize = MaxMessageSize;
+	type MinMessageSize = MinMessageSize;
+	type MaxChannels = MaxChannels;
+	type MaxPartiesPerChannel = MaxPartiesPerChannel;
+	type MaxMetadataBytes = MaxMetadataBytes;
+	type MaxEntries = ConstU32<8>;
+	type MaxBatch = MaxBatch;
+	type MaxAliasLen = ConstU32<16>;
+	type Currency = Balances;
+	type DepositPerByte = ConstU64<1>;
+	type MilestoneInterval = ConstU128<5>;
+	type MaxPerBlock = MaxPerBlock;
+	type OnMessageSent = SentMessages;
+	type MissingCheckWindow = MissingCheckWindow;
+	type MessageStore = MemoryStore;
+	type MaxTtl = MaxTtl;
+	type MaxExpiringPerBlock = MaxExpiringPerBlock;
+	type WeightInfo = ();
+}
+
+thread_local! {
+	static SENT_MESSAGES: RefCell<Vec<(u64, Vec<u8>, u32)>> = RefCell::new(vec![]);
+}
+
+/// Records arguments of every `OnMessageSent` call
+pub struct SentMessages;
+
+impl SentMessages {
+	pub fn get() -> Vec<(u64, Vec<u8>, u32)> {
+		SENT_MESSAGES.with(|sent| sent.borrow().clone())
+	}
+}
+
+impl pallet_nolik::OnMessageSent<u64> for SentMessages {
+	fn on_message_sent(sender: &u64, key: &[u8], channel_count: u32) {
+		SENT_MESSAGES.with(|sent| sent.borrow_mut().push((*sender, key.to_vec(), channel_count)));
+	}
+}
+
+thread_local! {
+	static STORED_MESSAGES: RefCell<HashMap<Vec<u8>, Vec<u8>>> = RefCell::new(HashMap::new());
+}
+
+/// Keeps messages in memory, so their payloads are checked without off-chain storage. Calls are
+/// also passed to `OffchainIndex`, which serves the reads
+pub struct MemoryStore;
+
+impl MemoryStore {
+	pub fn value(key: &[u8]) -> Option<Vec<u8>> {
+		STORED_MESSAGES.with(|stored| stored.borrow().get(key).cloned())
+	}
+}
+
+impl MessageStore for MemoryStore {
+	fn put(key: &[u8], value: &[u8]) {
+		STORED_MESSAGES.with(|stored| stored.borrow_mut().insert(key.to_vec(), value.to_vec()));
+		OffchainIndex::put(key, value);
+	}
+
+	fn get(key: &[u8]) -> Option<Vec<u8>> {
+		OffchainIndex::get(key)
+	}
+
+	fn clear(key: &[u8]) {
+		STORED_MESSAGES.with(|stored| stored.borrow_mut().remove(key));
+		OffchainIndex::clear(key);
+	}
 }
 
+/// An account which can't afford a deposit of any real message
+pub const LOW_BALANCE: u64 = 4;
+
 pub fn new_test_ext() -> sp_io::TestExternalities {
-	let t = system::GenesisConfig::default()
+	let mut t = system::GenesisConfig::default()
 		.build_storage::<Test>()
 		.expect("Failed to create test externalities.");
+	pallet_balances::GenesisConfig::<Test> {
+		balances: vec![(1, 1_000_000), (2, 1_000_000), (3, 1_000_000), (LOW_BALANCE, 10)],
+	}
+	.assimilate_storage(&mut t)
+	.expect("Failed to build balances genesis.");
 	let mut ext = sp_io::TestExternalities::new(t);
 
 	let (offchain, _state) = testing::TestOffchainExt::with_offchain_db(ext.offchain_db());
 	ext.register_extension(OffchainDbExt::new(offchain.clone()));
 	ext.register_extension(OffchainWorkerExt::new(offchain));
+	// signs key rotation proofs in benchmarks
+	ext.register_extension(KeystoreExt(Arc::new(KeyStore::new())));
 	ext.execute_with(|| System::set_block_number(1));
 	ext
 }
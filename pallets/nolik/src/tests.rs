@@ -1,30 +1,71 @@
-use crate::mock::*;
-use frame_support::{assert_err, assert_ok, sp_io};
-use nolik_metadata::{Channel, MessageMetadata};
-use sp_runtime::{offchain::StorageKind, traits::BadOrigin};
+use crate::{
+	migrations, mock::*, Call, Error, Event, MessageClass, MESSAGE_KEY_PREFIX,
+	MISSING_MESSAGES_KEY, SEALED_KEY_PREFIX,
+};
+use codec::{Decode, Encode};
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use frame_support::{
+	assert_err, assert_err_ignore_postinfo, assert_ok,
+	dispatch::GetDispatchInfo,
+	sp_io,
+	traits::{Get, GetStorageVersion, OffchainWorker, OnRuntimeUpgrade, StorageVersion},
+	BoundedVec,
+};
+use nolik_metadata::{split_parts, Channel, MessageMetadata, MAC_SIZE, NONCE_SIZE};
+use sp_core::{ed25519, Pair};
+use sp_runtime::{
+	offchain::StorageKind,
+	traits::{BadOrigin, Dispatchable},
+};
 
 use rand::{thread_rng, Rng};
 
-#[test]
-fn send_message() {
-	let mut ext = new_test_ext();
+/// Channel nonce of the size of an encrypted one
+fn encrypted_nonce(fill: u8) -> Vec<u8> {
+	vec![fill; NONCE_SIZE + MAC_SIZE]
+}
 
+fn new_metadata() -> MessageMetadata {
 	let mut rng = thread_rng();
-	let metadata = MessageMetadata {
+	MessageMetadata {
 		nonce: rng.gen(),
 		broker: rng.gen(),
 		hash: rng.gen(),
+		hash_algo: Default::default(),
+		in_reply_to: None,
+		entry_count: None,
+		part_count: None,
+		subject: None,
+		signature: None,
 		channels: vec![
 			Channel {
-				nonce: "encrypted_nonce_1".into(),
+				nonce: encrypted_nonce(1),
 				parties: vec!["encrypted_pubkey11".into(), "encrypted_pubkey12".into()],
 			},
 			Channel {
-				nonce: "encrypted_nonce_2".into(),
+				nonce: encrypted_nonce(2),
 				parties: vec!["encrypted_pubkey21".into(), "encrypted_pubkey22".into()],
 			},
 		],
-	};
+	}
+}
+
+/// Metadata with `count` channels, each having `count` parties
+fn metadata_with_channels(count: usize) -> MessageMetadata {
+	let channel =
+		Channel { nonce: encrypted_nonce(1), parties: vec!["encrypted_pubkey".into(); count] };
+	MessageMetadata { channels: vec![channel; count], ..new_metadata() }
+}
+
+fn bounded(message: &[u8]) -> BoundedVec<u8, MaxMessageSize> {
+	message.to_vec().try_into().expect("message exceeds MaxMessageSize")
+}
+
+#[test]
+fn send_message() {
+	let mut ext = new_test_ext();
+
+	let metadata = new_metadata();
 	let message = "my_encrypted_message".as_bytes().to_vec();
 	let address: u64 = 1;
 
@@ -34,14 +75,39 @@ fn send_message() {
 	ext.execute_with(|| {
 		// try to send unsigned
 		assert_err!(
-			Nolik::send_message(RuntimeOrigin::none(), metadata.clone(), message.clone()),
+			Nolik::send_message(
+				RuntimeOrigin::none(),
+				metadata.clone(),
+				bounded(&message),
+				None,
+				MessageClass::Normal
+			),
 			BadOrigin
 		);
 
-		assert_eq!(Nolik::message_counter(), counter);
-		assert_ok!(Nolik::send_message(RuntimeOrigin::signed(address), metadata, message.clone()));
-		assert_eq!(Nolik::message_counter(), counter + 1);
+		assert_eq!(Nolik::message_counter(address), counter);
+		assert_ok!(Nolik::send_message(
+			RuntimeOrigin::signed(address),
+			metadata.clone(),
+			bounded(&message),
+			None,
+			MessageClass::Normal
+		));
+		assert_eq!(Nolik::message_counter(address), counter + 1);
+		assert_eq!(Nolik::total_messages(), 1);
 		key = Nolik::derived_key(&address, counter);
+
+		System::assert_last_event(
+			Event::MessageSent {
+				sender: address,
+				key: key.clone(),
+				channel_count: metadata.channels.len() as u32,
+				encoded_metadata_len: metadata.encode().len() as u32,
+				metadata,
+				class: MessageClass::Normal,
+			}
+			.into(),
+		);
 	});
 
 	ext.persist_offchain_overlay();
@@ -51,3 +117,1556 @@ fn send_message() {
 		assert_eq!(data, Some(message));
 	});
 }
+
+#[test]
+fn message_counters_are_per_account() {
+	let mut ext = new_test_ext();
+
+	let (alice, bob): (u64, u64) = (1, 2);
+	let senders = [alice, bob, bob, alice, bob];
+	let mut keys = vec![];
+
+	ext.execute_with(|| {
+		for (i, sender) in senders.iter().enumerate() {
+			let counter = Nolik::message_counter(sender);
+			assert_ok!(Nolik::send_message(
+				RuntimeOrigin::signed(*sender),
+				new_metadata(),
+				bounded(&[i as u8 + 1]),
+				None,
+				MessageClass::Normal
+			));
+			keys.push((Nolik::derived_key(sender, counter), vec![i as u8 + 1]));
+		}
+
+		assert_eq!(Nolik::message_counter(alice), 2);
+		assert_eq!(Nolik::message_counter(bob), 3);
+		assert_eq!(Nolik::total_messages(), 5);
+	});
+
+	ext.persist_offchain_overlay();
+
+	ext.execute_with(|| {
+		// each account's sequence starts at 0 and has no gaps
+		for (account, count) in [(alice, 2), (bob, 3)] {
+			for counter in 0..count {
+				let key = Nolik::derived_key(&account, counter);
+				assert!(keys.iter().any(|(k, _)| *k == key));
+			}
+		}
+
+		for (key, message) in keys {
+			let data = sp_io::offchain::local_storage_get(StorageKind::PERSISTENT, &key);
+			assert_eq!(data, Some(message));
+		}
+	});
+}
+
+#[test]
+fn verify_message_against_commitment() {
+	let mut ext = new_test_ext();
+
+	let message = "my_encrypted_message".as_bytes().to_vec();
+	let address: u64 = 1;
+	let mut key = vec![];
+
+	ext.execute_with(|| {
+		assert_ok!(Nolik::send_message(
+			RuntimeOrigin::signed(address),
+			new_metadata(),
+			bounded(&message),
+			None,
+			MessageClass::Normal
+		));
+		key = Nolik::derived_key(&address, 0);
+		assert!(Nolik::message_commitment(&key).is_some());
+	});
+
+	ext.persist_offchain_overlay();
+
+	ext.execute_with(|| {
+		let data = sp_io::offchain::local_storage_get(StorageKind::PERSISTENT, &key)
+			.expect("message is stored off-chain");
+		assert!(Nolik::verify_message(&key, &data));
+
+		let mut tampered = data.clone();
+		tampered[0] ^= 1;
+		assert!(!Nolik::verify_message(&key, &tampered));
+
+		// unknown key has no commitment
+		assert!(!Nolik::verify_message(&Nolik::derived_key(&address, 1), &data));
+	});
+}
+
+#[test]
+fn retract_message() {
+	let mut ext = new_test_ext();
+
+	let message = "my_encrypted_message".as_bytes().to_vec();
+	let (alice, bob): (u64, u64) = (1, 2);
+	let mut key = vec![];
+
+	ext.execute_with(|| {
+		assert_ok!(Nolik::send_message(
+			RuntimeOrigin::signed(alice),
+			new_metadata(),
+			bounded(&message),
+			None,
+			MessageClass::Normal
+		));
+		key = Nolik::derived_key(&alice, 0);
+	});
+
+	ext.persist_offchain_overlay();
+
+	ext.execute_with(|| {
+		// bob can only derive his own keys, so alice's message is out of his reach
+		assert_err!(
+			Nolik::retract_message(RuntimeOrigin::signed(bob), 0),
+			Error::<Test>::MessageNotFound
+		);
+		assert!(Nolik::message_commitment(&key).is_some());
+		// unknown counter is reported
+		assert_err!(
+			Nolik::retract_message(RuntimeOrigin::signed(alice), 1),
+			Error::<Test>::MessageNotFound
+		);
+
+		assert_ok!(Nolik::retract_message(RuntimeOrigin::signed(alice), 0));
+		System::assert_last_event(Event::MessageRetracted { key: key.clone() }.into());
+		assert!(Nolik::message_commitment(&key).is_none());
+
+		// already retracted
+		assert_err!(
+			Nolik::retract_message(RuntimeOrigin::signed(alice), 0),
+			Error::<Test>::MessageNotFound
+		);
+	});
+
+	ext.persist_offchain_overlay();
+
+	ext.execute_with(|| {
+		let data = sp_io::offchain::local_storage_get(StorageKind::PERSISTENT, &key);
+		assert_eq!(data, None);
+	});
+}
+
+#[test]
+fn prune_account() {
+	let mut ext = new_test_ext();
+
+	let (alice, bob): (u64, u64) = (1, 2);
+	let keys: Vec<_> = (0..5).map(|counter| Nolik::derived_key(&alice, counter)).collect();
+
+	ext.execute_with(|| {
+		for _ in &keys {
+			assert_ok!(Nolik::send_message(
+				RuntimeOrigin::signed(alice),
+				new_metadata(),
+				bounded(b"my_encrypted_message"),
+				None,
+				MessageClass::Normal
+			));
+		}
+		assert_ok!(Nolik::send_message(
+			RuntimeOrigin::signed(bob),
+			new_metadata(),
+			bounded(b"my_encrypted_message"),
+			None,
+			MessageClass::Normal
+		));
+		// a retracted message is not counted
+		assert_ok!(Nolik::retract_message(RuntimeOrigin::signed(alice), 1));
+		assert!(Balances::reserved_balance(alice) > 0);
+	});
+
+	ext.persist_offchain_overlay();
+
+	ext.execute_with(|| {
+		// pruned in two calls, the weight of messages not visited is refunded
+		assert_ok!(Nolik::prune_account(RuntimeOrigin::signed(alice), 3));
+		System::assert_last_event(Event::AccountPruned { who: alice, count: 2 }.into());
+		assert_eq!(Nolik::pruned_messages(alice), 3);
+		assert!(Nolik::message_commitment(&keys[3]).is_some());
+
+		let info = Nolik::prune_account(RuntimeOrigin::signed(alice), 10).unwrap();
+		assert_eq!(info.actual_weight, Some(<() as crate::WeightInfo>::prune_account(2)));
+		System::assert_last_event(Event::AccountPruned { who: alice, count: 2 }.into());
+		assert_eq!(Nolik::pruned_messages(alice), 5);
+
+		for key in &keys {
+			assert!(Nolik::message_commitment(key).is_none());
+		}
+		assert_eq!(Balances::reserved_balance(alice), 0);
+		// messages of other accounts are kept
+		assert!(Nolik::message_commitment(Nolik::derived_key(&bob, 0)).is_some());
+
+		// nothing left to prune
+		assert_ok!(Nolik::prune_account(RuntimeOrigin::signed(alice), 10));
+		System::assert_last_event(Event::AccountPruned { who: alice, count: 0 }.into());
+	});
+
+	ext.persist_offchain_overlay();
+
+	ext.execute_with(|| {
+		for key in &keys {
+			let data = sp_io::offchain::local_storage_get(StorageKind::PERSISTENT, key);
+			assert_eq!(data, None);
+		}
+		let data = sp_io::offchain::local_storage_get(
+			StorageKind::PERSISTENT,
+			&Nolik::derived_key(&bob, 0),
+		);
+		assert!(data.is_some());
+	});
+}
+
+#[test]
+fn message_class_scales_weight() {
+	new_test_ext().execute_with(|| {
+		let metadata = new_metadata();
+		let weight = |class| {
+			let call = RuntimeCall::Nolik(Call::send_message {
+				metadata: metadata.clone(),
+				message: bounded(b"my_encrypted_message"),
+				ttl_blocks: None,
+				class,
+			});
+			let info = call.get_dispatch_info();
+			let post_info = call.dispatch(RuntimeOrigin::signed(1)).unwrap();
+			System::assert_last_event(
+				Event::MessageSent {
+					sender: 1,
+					key: Nolik::derived_key(&1, Nolik::message_counter(1) - 1),
+					metadata: metadata.clone(),
+					channel_count: metadata.channels.len() as u32,
+					encoded_metadata_len: metadata.encode().len() as u32,
+					class,
+				}
+				.into(),
+			);
+			post_info.calc_actual_weight(&info)
+		};
+
+		let normal = weight(MessageClass::Normal);
+		assert_eq!(weight(MessageClass::Priority), normal.saturating_mul(2));
+		// a bulk message is never charged below the benchmarked weight
+		assert_eq!(weight(MessageClass::Bulk), normal);
+		assert_eq!(MessageClass::default(), MessageClass::Normal);
+	});
+}
+
+#[test]
+fn send_message_at_max_size() {
+	new_test_ext().execute_with(|| {
+		let max = MaxMessageSize::get() as usize;
+		let encoded =
+			(0u8, new_metadata(), vec![1u8; max], None::<u64>, MessageClass::Normal).encode();
+
+		let call = Call::<Test>::decode(&mut &encoded[..]).expect("message at the limit decodes");
+		let Call::send_message { metadata, message, ttl_blocks, class } = call else {
+			panic!("unexpected call decoded");
+		};
+		assert_ok!(Nolik::send_message(
+			RuntimeOrigin::signed(1),
+			metadata,
+			message,
+			ttl_blocks,
+			class
+		));
+	});
+}
+
+#[test]
+fn send_message_over_max_size_is_rejected() {
+	new_test_ext().execute_with(|| {
+		let max = MaxMessageSize::get() as usize;
+		let encoded =
+			(0u8, new_metadata(), vec![1u8; max + 1], None::<u64>, MessageClass::Normal).encode();
+
+		assert!(Call::<Test>::decode(&mut &encoded[..]).is_err());
+		assert!(BoundedVec::<u8, MaxMessageSize>::try_from(vec![1u8; max + 1]).is_err());
+	});
+}
+
+#[test]
+fn check_message_channel_limits() {
+	new_test_ext().execute_with(|| {
+		let message = bounded(b"my_encrypted_message");
+		let max_parties = MaxPartiesPerChannel::get() as usize;
+		let max_channels = MaxChannels::get() as usize;
+
+		// at the channels limit
+		assert_ok!(Nolik::check_message(&message, &metadata_with_channels(max_channels)));
+		// over the channels limit
+		assert_err!(
+			Nolik::check_message(&message, &metadata_with_channels(max_channels + 1)),
+			Error::<Test>::TooManyChannels
+		);
+
+		// parties are checked per channel, before the `parties.len() == channels.len()` invariant
+		let mut metadata = metadata_with_channels(1);
+		metadata.channels[0].parties = vec!["encrypted_pubkey".into(); max_parties + 1];
+		assert_err!(Nolik::check_message(&message, &metadata), Error::<Test>::TooManyParties);
+		// at the parties limit only the invariant fails
+		metadata.channels[0].parties.pop();
+		assert_err!(Nolik::check_message(&message, &metadata), Error::<Test>::MetadataMalformed);
+	});
+}
+
+#[test]
+fn send_self_message() {
+	let mut ext = new_test_ext();
+	let message = bounded(b"my_encrypted_note");
+
+	ext.execute_with(|| {
+		// the only channel of the sender, listing the sender only
+		let metadata = metadata_with_channels(1);
+		assert_ok!(Nolik::check_message(&message, &metadata));
+
+		assert_ok!(Nolik::send_message(
+			RuntimeOrigin::signed(1),
+			metadata,
+			message.clone(),
+			None,
+			MessageClass::Normal
+		));
+	});
+
+	ext.persist_offchain_overlay();
+
+	ext.execute_with(|| {
+		let data =
+			sp_io::offchain::local_storage_get(StorageKind::PERSISTENT, &Nolik::derived_key(&1, 0));
+		assert_eq!(data, Some(message.to_vec()));
+	});
+}
+
+#[test]
+fn check_message_rejects_zero_hash() {
+	new_test_ext().execute_with(|| {
+		let message = bounded(b"my_encrypted_message");
+
+		let metadata = MessageMetadata { hash: [0; 32], ..new_metadata() };
+		assert_err_ignore_postinfo!(
+			Nolik::send_message(
+				RuntimeOrigin::signed(1),
+				metadata,
+				message.clone(),
+				None,
+				MessageClass::Normal
+			),
+			Error::<Test>::InvalidMetadataHash
+		);
+
+		let mut hash = [0; 32];
+		hash[31] = 1;
+		let metadata = MessageMetadata { hash, ..new_metadata() };
+		assert_ok!(Nolik::send_message(
+			RuntimeOrigin::signed(1),
+			metadata,
+			message,
+			None,
+			MessageClass::Normal
+		));
+	});
+}
+
+#[test]
+fn acknowledge_message() {
+	new_test_ext().execute_with(|| {
+		let (alice, bob): (u64, u64) = (1, 2);
+		assert_ok!(Nolik::send_message(
+			RuntimeOrigin::signed(alice),
+			new_metadata(),
+			bounded(b"my_encrypted_message"),
+			None,
+			MessageClass::Normal
+		));
+		let key = Nolik::derived_key(&alice, 0);
+
+		// unknown message can't be acknowledged
+		assert_err!(
+			Nolik::acknowledge(RuntimeOrigin::signed(bob), Nolik::derived_key(&alice, 1)),
+			Error::<Test>::MessageNotFound
+		);
+
+		assert_eq!(Nolik::read_receipt(&key, bob), None);
+		assert_ok!(Nolik::acknowledge(RuntimeOrigin::signed(bob), key.clone()));
+		System::assert_last_event(Event::MessageAcknowledged { key: key.clone(), by: bob }.into());
+		assert_eq!(Nolik::read_receipt(&key, bob), Some(1));
+
+		// acknowledging again keeps the first block number
+		System::set_block_number(5);
+		assert_ok!(Nolik::acknowledge(RuntimeOrigin::signed(bob), key.clone()));
+		assert_eq!(Nolik::read_receipt(&key, bob), Some(1));
+	});
+}
+
+#[test]
+fn send_messages_in_batch() {
+	let mut ext = new_test_ext();
+
+	let address: u64 = 1;
+	let messages: Vec<Vec<u8>> = vec![b"first".to_vec(), b"second".to_vec(), b"third".to_vec()];
+
+	ext.execute_with(|| {
+		let items: Vec<_> = messages.iter().map(|m| (new_metadata(), bounded(m))).collect();
+		assert_ok!(Nolik::send_messages(
+			RuntimeOrigin::signed(address),
+			items.try_into().expect("batch is within MaxBatch")
+		));
+
+		assert_eq!(Nolik::message_counter(address), 3);
+		let sent: Vec<_> = System::events()
+			.into_iter()
+			.filter_map(|r| match r.event {
+				RuntimeEvent::Nolik(Event::MessageSent {
+					metadata, encoded_metadata_len, ..
+				}) => Some((metadata, encoded_metadata_len)),
+				_ => None,
+			})
+			.collect();
+		assert_eq!(sent.len(), 3);
+		for (metadata, encoded_metadata_len) in sent {
+			assert_eq!(encoded_metadata_len as usize, metadata.encode().len());
+		}
+	});
+
+	ext.persist_offchain_overlay();
+
+	ext.execute_with(|| {
+		for (counter, message) in messages.into_iter().enumerate() {
+			let key = Nolik::derived_key(&address, counter as u128);
+			let data = sp_io::offchain::local_storage_get(StorageKind::PERSISTENT, &key);
+			assert_eq!(data, Some(message));
+		}
+	});
+}
+
+#[test]
+fn send_messages_is_atomic() {
+	new_test_ext().execute_with(|| {
+		let address: u64 = 1;
+		let items = vec![
+			(new_metadata(), bounded(b"first")),
+			(MessageMetadata { channels: vec![], ..new_metadata() }, bounded(b"second")),
+		];
+
+		assert_err!(
+			Nolik::send_messages(RuntimeOrigin::signed(address), items.try_into().unwrap()),
+			Error::<Test>::MetadataMalformed
+		);
+		assert_eq!(Nolik::message_counter(address), 0);
+		assert!(Nolik::message_commitment(Nolik::derived_key(&address, 0)).is_none());
+		assert!(System::events().is_empty());
+	});
+}
+
+#[test]
+fn batch_weight_counts_items() {
+	new_test_ext().execute_with(|| {
+		let weight = |items: Vec<(MessageMetadata, BoundedVec<u8, MaxMessageSize>)>| {
+			Call::<Test>::send_messages { items: items.try_into().unwrap() }
+				.get_dispatch_info()
+				.weight
+		};
+
+		// the same 100 bytes in a single message and in 10 messages
+		let single = weight(vec![(new_metadata(), bounded(&[1; 100]))]);
+		let packed = weight((0..10).map(|_| (new_metadata(), bounded(&[1; 10]))).collect());
+		assert!(packed.all_gt(single));
+	});
+}
+
+#[test]
+fn blocked_sender_is_rejected() {
+	new_test_ext().execute_with(|| {
+		let (alice, bob, charlie): (u64, u64, u64) = (1, 2, 3);
+		let recipients: BoundedVec<u64, MaxChannels> = vec![bob, charlie].try_into().unwrap();
+
+		assert_ok!(Nolik::block_sender(RuntimeOrigin::signed(charlie), alice));
+		System::assert_last_event(
+			Event::SenderBlocked { recipient: charlie, sender: alice }.into(),
+		);
+		assert!(Nolik::is_blocked(charlie, alice));
+
+		assert_err!(
+			Nolik::send_message_to(
+				RuntimeOrigin::signed(alice),
+				new_metadata(),
+				bounded(b"my_encrypted_message"),
+				recipients.clone()
+			),
+			Error::<Test>::SenderBlocked
+		);
+		assert_eq!(Nolik::message_counter(alice), 0);
+
+		// other senders are not affected
+		assert_ok!(Nolik::send_message_to(
+			RuntimeOrigin::signed(bob),
+			new_metadata(),
+			bounded(b"my_encrypted_message"),
+			vec![charlie].try_into().unwrap()
+		));
+
+		assert_ok!(Nolik::unblock_sender(RuntimeOrigin::signed(charlie), alice));
+		System::assert_last_event(
+			Event::SenderUnblocked { recipient: charlie, sender: alice }.into(),
+		);
+		assert!(!Nolik::is_blocked(charlie, alice));
+
+		assert_ok!(Nolik::send_message_to(
+			RuntimeOrigin::signed(alice),
+			new_metadata(),
+			bounded(b"my_encrypted_message"),
+			recipients
+		));
+		assert_eq!(Nolik::message_counter(alice), 1);
+	});
+}
+
+#[test]
+fn register_public_key() {
+	new_test_ext().execute_with(|| {
+		let address: u64 = 1;
+		assert_eq!(Nolik::public_key_of(address), None);
+
+		assert!(!Nolik::is_registered(&address));
+
+		assert_ok!(Nolik::register_public_key(RuntimeOrigin::signed(address), [1; 32]));
+		System::assert_last_event(Event::PublicKeyRegistered { who: address, pk: [1; 32] }.into());
+		assert_eq!(Nolik::public_key_of(address), Some([1; 32]));
+		assert!(Nolik::is_registered(&address));
+		assert!(!Nolik::is_registered(&2));
+
+		// a registered key is only replaced by `rotate_public_key`
+		assert_err!(
+			Nolik::register_public_key(RuntimeOrigin::signed(address), [2; 32]),
+			Error::<Test>::PublicKeyAlreadyRegistered
+		);
+		assert_eq!(Nolik::public_key_of(address), Some([1; 32]));
+		assert_eq!(Nolik::public_key_of(2), None);
+	});
+}
+
+#[test]
+fn rotate_public_key() {
+	new_test_ext().execute_with(|| {
+		let (alice, bob): (u64, u64) = (1, 2);
+		let pair = ed25519::Pair::from_seed(&[7; 32]);
+		let new_pk = pair.public().0;
+		let sig = |account: &u64| pair.sign(&Nolik::rotation_payload(account)).0;
+		let x25519_pk = CompressedEdwardsY(new_pk).decompress().unwrap().to_montgomery().to_bytes();
+
+		// nothing to rotate
+		assert_err!(
+			Nolik::rotate_public_key(RuntimeOrigin::signed(alice), new_pk, sig(&alice)),
+			Error::<Test>::PublicKeyNotRegistered
+		);
+		assert_ok!(Nolik::register_public_key(RuntimeOrigin::signed(alice), [1; 32]));
+
+		// a signature of another account or by another key is no proof
+		for bad_sig in [sig(&bob), ed25519::Pair::from_seed(&[8; 32]).sign(b"other").0, [0; 64]] {
+			assert_err!(
+				Nolik::rotate_public_key(RuntimeOrigin::signed(alice), new_pk, bad_sig),
+				Error::<Test>::InvalidKeyProof
+			);
+		}
+		assert_eq!(Nolik::public_key_of(alice), Some([1; 32]));
+		// the proof can't be bypassed by registering again
+		assert_err!(
+			Nolik::register_public_key(RuntimeOrigin::signed(alice), [2; 32]),
+			Error::<Test>::PublicKeyAlreadyRegistered
+		);
+		assert_eq!(Nolik::public_key_of(alice), Some([1; 32]));
+
+		assert_ok!(Nolik::rotate_public_key(RuntimeOrigin::signed(alice), new_pk, sig(&alice)));
+		System::assert_last_event(Event::PublicKeyRotated { who: alice, pk: x25519_pk }.into());
+		assert_eq!(Nolik::public_key_of(alice), Some(x25519_pk));
+	});
+}
+
+#[test]
+fn set_alias() {
+	new_test_ext().execute_with(|| {
+		let alias = |name: &[u8]| name.to_vec().try_into().unwrap();
+		let (alice, bob) = (1, 2);
+
+		assert_ok!(Nolik::set_alias(RuntimeOrigin::signed(alice), alias(b"alice")));
+		System::assert_last_event(Event::AliasSet { who: alice, alias: alias(b"alice") }.into());
+		assert_eq!(Nolik::alias_of(alice), Some(alias(b"alice")));
+		assert_eq!(Nolik::alias_owner(alias(b"alice")), Some(alice));
+
+		// aliases are unique
+		assert_err!(
+			Nolik::set_alias(RuntimeOrigin::signed(bob), alias(b"alice")),
+			Error::<Test>::AliasTaken
+		);
+		assert_eq!(Nolik::alias_of(bob), None);
+		// setting the own alias again is not a conflict
+		assert_ok!(Nolik::set_alias(RuntimeOrigin::signed(alice), alias(b"alice")));
+		assert_eq!(Nolik::alias_owner(alias(b"alice")), Some(alice));
+
+		// a new alias releases the previous one
+		assert_ok!(Nolik::set_alias(RuntimeOrigin::signed(alice), alias(b"alice2")));
+		assert_eq!(Nolik::alias_owner(alias(b"alice")), None);
+		assert_eq!(Nolik::alias_owner(alias(b"alice2")), Some(alice));
+		assert_ok!(Nolik::set_alias(RuntimeOrigin::signed(bob), alias(b"alice")));
+		assert_eq!(Nolik::alias_owner(alias(b"alice")), Some(bob));
+		assert_eq!(Nolik::alias_of(bob), Some(alias(b"alice")));
+	});
+}
+
+#[test]
+fn expired_messages_are_pruned() {
+	let mut ext = new_test_ext();
+
+	let address: u64 = 1;
+	let (short, long, forever) = (
+		Nolik::derived_key(&address, 0),
+		Nolik::derived_key(&address, 1),
+		Nolik::derived_key(&address, 2),
+	);
+
+	ext.execute_with(|| {
+		for ttl_blocks in [Some(2), Some(5), None] {
+			assert_ok!(Nolik::send_message(
+				RuntimeOrigin::signed(address),
+				new_metadata(),
+				bounded(b"my_encrypted_message"),
+				ttl_blocks,
+				MessageClass::Normal
+			));
+		}
+		assert_eq!(Nolik::message_expiry(&short), Some(3));
+		assert_eq!(Nolik::message_expiry(&long), Some(6));
+		assert_eq!(Nolik::message_expiry(&forever), None);
+
+		// keys are only listed at the block they expire
+		assert!(Nolik::expired_keys(2).is_empty());
+		assert_eq!(Nolik::expired_keys(3), vec![short.clone()]);
+		assert!(Nolik::expired_keys(4).is_empty());
+		assert_eq!(Nolik::expired_keys(6), vec![long.clone()]);
+	});
+
+	ext.persist_offchain_overlay();
+
+	ext.execute_with(|| {
+		System::set_block_number(3);
+		Nolik::on_initialize(3);
+
+		// payloads are cleared through the message store, keys of the block are removed
+		assert_eq!(MemoryStore::value(&short), None);
+		assert!(MemoryStore::value(&long).is_some());
+		assert!(Nolik::expired_keys(3).is_empty());
+		assert_eq!(Nolik::expired_keys(6), vec![long.clone()]);
+	});
+
+	ext.persist_offchain_overlay();
+
+	ext.execute_with(|| {
+		let get = |key: &[u8]| sp_io::offchain::local_storage_get(StorageKind::PERSISTENT, key);
+		assert_eq!(get(&short), None);
+		assert!(get(&long).is_some());
+		assert!(get(&forever).is_some());
+
+		// a retracted message is not listed at its expiry
+		assert_ok!(Nolik::retract_message(RuntimeOrigin::signed(address), 1));
+		assert!(Nolik::expired_keys(6).is_empty());
+	});
+}
+
+#[test]
+fn message_ttl_is_bounded() {
+	new_test_ext().execute_with(|| {
+		let address: u64 = 1;
+		let send = |ttl_blocks: u64| {
+			Nolik::send_message(
+				RuntimeOrigin::signed(address),
+				new_metadata(),
+				bounded(b"my_encrypted_message"),
+				Some(ttl_blocks),
+				MessageClass::Normal,
+			)
+		};
+
+		// a message expiring at the current block would never be cleared
+		assert_err_ignore_postinfo!(send(0), Error::<Test>::InvalidTtl);
+		assert_err_ignore_postinfo!(send(MaxTtl::get() + 1), Error::<Test>::InvalidTtl);
+		assert_err!(
+			Nolik::send_sealed_message(
+				RuntimeOrigin::signed(address),
+				new_metadata(),
+				bounded(b"my_encrypted_message"),
+				Some(0)
+			),
+			Error::<Test>::InvalidTtl
+		);
+		// a rejected message is not stored
+		assert_eq!(MemoryStore::value(&Nolik::derived_key(&address, 0)), None);
+		assert_eq!(Nolik::message_counter(address), 0);
+
+		assert_ok!(send(MaxTtl::get()));
+		assert_eq!(Nolik::message_expiry(Nolik::derived_key(&address, 0)), Some(1 + MaxTtl::get()));
+	});
+}
+
+#[test]
+fn expiring_messages_are_capped_per_block() {
+	new_test_ext().execute_with(|| {
+		MaxExpiringPerBlock::set(2);
+		let address: u64 = 1;
+		let send = |ttl_blocks: u64| {
+			Nolik::send_message(
+				RuntimeOrigin::signed(address),
+				new_metadata(),
+				bounded(b"my_encrypted_message"),
+				Some(ttl_blocks),
+				MessageClass::Normal,
+			)
+		};
+
+		// the block 3 is full
+		assert_ok!(send(2));
+		assert_ok!(send(2));
+		assert_err_ignore_postinfo!(send(2), Error::<Test>::TooManyExpiring);
+		assert_eq!(Nolik::message_counter(address), 2);
+		// other blocks are not affected
+		assert_ok!(send(3));
+
+		// a retracted message frees its slot
+		assert_ok!(Nolik::retract_message(RuntimeOrigin::signed(address), 0));
+		assert_ok!(send(2));
+		assert_eq!(crate::ExpiringCount::<Test>::get(3), 2);
+
+		// the block clears its messages and the count
+		System::set_block_number(3);
+		Nolik::on_initialize(3);
+		assert!(Nolik::expired_keys(3).is_empty());
+		assert_eq!(crate::ExpiringCount::<Test>::get(3), 0);
+		assert_eq!(crate::ExpiringCount::<Test>::get(4), 1);
+	});
+}
+
+#[test]
+fn store_offchain_in_block_execution() {
+	let mut ext = new_test_ext();
+
+	ext.execute_with(|| {
+		// a node would drop the write, while the test externalities keep it
+		assert!(!Nolik::in_block_execution());
+
+		System::initialize(&2, &Default::default(), &Default::default());
+		assert!(Nolik::in_block_execution());
+		Nolik::store_offchain(b"nolik/test", b"value");
+
+		System::finalize();
+		assert!(!Nolik::in_block_execution());
+	});
+
+	ext.persist_offchain_overlay();
+
+	ext.execute_with(|| {
+		assert_eq!(
+			sp_io::offchain::local_storage_get(StorageKind::PERSISTENT, b"nolik/test"),
+			Some(b"value".to_vec())
+		);
+	});
+}
+
+#[test]
+fn missing_messages_are_flagged() {
+	let mut ext = new_test_ext();
+
+	let address: u64 = 1;
+	let (kept, lost, expired) = (
+		Nolik::derived_key(&address, 0),
+		Nolik::derived_key(&address, 1),
+		Nolik::derived_key(&address, 2),
+	);
+
+	ext.execute_with(|| {
+		for ttl_blocks in [None, None, Some(2)] {
+			assert_ok!(Nolik::send_message(
+				RuntimeOrigin::signed(address),
+				new_metadata(),
+				bounded(b"my_encrypted_message"),
+				ttl_blocks,
+				MessageClass::Normal
+			));
+		}
+	});
+
+	ext.persist_offchain_overlay();
+
+	ext.execute_with(|| {
+		let get = |key: &[u8]| sp_io::offchain::local_storage_get(StorageKind::PERSISTENT, key);
+		let flagged = || Vec::<Vec<u8>>::decode(&mut &get(MISSING_MESSAGES_KEY).unwrap()[..]);
+
+		Nolik::offchain_worker(1);
+		assert_eq!(flagged().unwrap(), Vec::<Vec<u8>>::new());
+
+		// the payload is lost, while its commitment stays on-chain
+		sp_io::offchain::local_storage_clear(StorageKind::PERSISTENT, &lost);
+		assert!(Nolik::message_commitment(&lost).is_some());
+
+		// the message expires at the block 3
+		System::set_block_number(3);
+		Nolik::on_initialize(3);
+		Nolik::offchain_worker(3);
+		// the expired message is pruned, not flagged
+		assert_eq!(MemoryStore::value(&expired), None);
+		assert!(get(&kept).is_some());
+		assert_eq!(flagged().unwrap(), vec![lost.clone()]);
+
+		// a re-seeded message is no longer flagged
+		sp_io::offchain::local_storage_set(StorageKind::PERSISTENT, &lost, b"my_encrypted_message");
+		Nolik::offchain_worker(4);
+		assert_eq!(flagged().unwrap(), Vec::<Vec<u8>>::new());
+
+		// messages committed before the window are not checked
+		sp_io::offchain::local_storage_clear(StorageKind::PERSISTENT, &lost);
+		System::set_block_number(5);
+		Nolik::on_initialize(5);
+		Nolik::offchain_worker(5);
+		assert_eq!(flagged().unwrap(), Vec::<Vec<u8>>::new());
+		assert!(Nolik::missing_keys(5).is_empty());
+	});
+}
+
+#[test]
+fn message_counter_overflow() {
+	new_test_ext().execute_with(|| {
+		let address: u64 = 1;
+		crate::MessageCounter::<Test>::insert(address, u128::MAX);
+
+		assert_err!(
+			Nolik::send_message(
+				RuntimeOrigin::signed(address),
+				new_metadata(),
+				bounded(b"my_encrypted_message"),
+				None,
+				MessageClass::Normal
+			),
+			Error::<Test>::MessageCounterOverflow
+		);
+
+		// storage is not mutated
+		assert_eq!(Nolik::message_counter(address), u128::MAX);
+		assert_eq!(Nolik::total_messages(), 0);
+		assert!(Nolik::message_commitment(Nolik::derived_key(&address, u128::MAX)).is_none());
+		assert!(System::events().is_empty());
+	});
+}
+
+#[test]
+fn read_message() {
+	let mut ext = new_test_ext();
+
+	let message = "my_encrypted_message".as_bytes().to_vec();
+	let address: u64 = 1;
+
+	ext.execute_with(|| {
+		assert_ok!(Nolik::send_message(
+			RuntimeOrigin::signed(address),
+			new_metadata(),
+			bounded(&message),
+			None,
+			MessageClass::Normal
+		));
+	});
+
+	ext.persist_offchain_overlay();
+
+	ext.execute_with(|| {
+		assert_eq!(Nolik::read_message(&address, 0), Some(message));
+		assert_eq!(Nolik::read_message(&address, 1), None);
+		assert_eq!(Nolik::read_message(&2, 0), None);
+	});
+}
+
+#[test]
+fn iter_messages() {
+	let mut ext = new_test_ext();
+
+	let messages: Vec<Vec<u8>> = vec![b"first".to_vec(), b"second".to_vec(), b"third".to_vec()];
+	let address: u64 = 1;
+
+	ext.execute_with(|| {
+		for message in &messages {
+			assert_ok!(Nolik::send_message(
+				RuntimeOrigin::signed(address),
+				new_metadata(),
+				bounded(message),
+				None,
+				MessageClass::Normal
+			));
+		}
+	});
+
+	ext.persist_offchain_overlay();
+
+	ext.execute_with(|| {
+		let sent: Vec<_> = Nolik::iter_messages(&address).collect();
+		let counters: Vec<_> = sent.iter().map(|(counter, _)| *counter).collect();
+		assert_eq!(counters, vec![0, 1, 2]);
+		assert!(sent.iter().map(|(_, message)| message).eq(&messages));
+		assert_eq!(Nolik::iter_messages(&2).count(), 0);
+
+		assert_ok!(Nolik::retract_message(RuntimeOrigin::signed(address), 1));
+	});
+
+	ext.persist_offchain_overlay();
+
+	ext.execute_with(|| {
+		// the retracted message leaves a gap
+		let sent: Vec<_> = Nolik::iter_messages(&address).collect();
+		assert_eq!(sent, vec![(0, messages[0].clone()), (2, messages[2].clone())]);
+	});
+}
+
+#[test]
+fn message_counter_predicts_next_key() {
+	new_test_ext().execute_with(|| {
+		let address: u64 = 1;
+
+		for _ in 0..2 {
+			// the value returned by the `nolik_messageCounter` RPC
+			let counter = Nolik::message_counter(address);
+			let predicted_key = Nolik::derived_key(&address, counter);
+
+			assert_ok!(Nolik::send_message(
+				RuntimeOrigin::signed(address),
+				new_metadata(),
+				bounded(b"my_encrypted_message"),
+				None,
+				MessageClass::Normal
+			));
+			assert!(matches!(
+				System::events().last().map(|r| &r.event),
+				Some(RuntimeEvent::Nolik(Event::MessageSent { sender, key, .. }))
+					if *sender == address && *key == predicted_key
+			));
+		}
+		assert_eq!(Nolik::message_counter(address), 2);
+	});
+}
+
+#[test]
+fn check_message_nonce_length() {
+	new_test_ext().execute_with(|| {
+		let message = bounded(b"my_encrypted_message");
+
+		let mut metadata = new_metadata();
+		assert_ok!(Nolik::check_message(&message, &metadata));
+
+		// not encrypted nonce
+		metadata.channels[0].nonce = vec![1; NONCE_SIZE];
+		assert_err!(Nolik::check_message(&message, &metadata), Error::<Test>::MetadataMalformed);
+
+		metadata.channels[0].nonce = vec![1; NONCE_SIZE + MAC_SIZE + 1];
+		assert_err!(Nolik::check_message(&message, &metadata), Error::<Test>::MetadataMalformed);
+	});
+}
+
+#[test]
+fn on_message_sent_hook() {
+	new_test_ext().execute_with(|| {
+		let (alice, bob): (u64, u64) = (1, 2);
+		assert!(SentMessages::get().is_empty());
+
+		assert_ok!(Nolik::send_message(
+			RuntimeOrigin::signed(alice),
+			new_metadata(),
+			bounded(b"my_encrypted_message"),
+			None,
+			MessageClass::Normal
+		));
+		assert_ok!(Nolik::send_message(
+			RuntimeOrigin::signed(bob),
+			metadata_with_channels(3),
+			bounded(b"my_encrypted_message"),
+			None,
+			MessageClass::Normal
+		));
+		// a failed send doesn't call the hook
+		assert!(Nolik::send_message(
+			RuntimeOrigin::signed(alice),
+			MessageMetadata { channels: vec![], ..new_metadata() },
+			bounded(b"my_encrypted_message"),
+			None,
+			MessageClass::Normal
+		)
+		.is_err());
+
+		assert_eq!(
+			SentMessages::get(),
+			vec![(alice, Nolik::derived_key(&alice, 0), 2), (bob, Nolik::derived_key(&bob, 0), 3)]
+		);
+	});
+}
+
+#[test]
+fn message_store_backend() {
+	new_test_ext().execute_with(|| {
+		let alice: u64 = 1;
+		let message = b"my_encrypted_message".to_vec();
+		let key = Nolik::derived_key(&alice, 0);
+		assert_eq!(MemoryStore::value(&key), None);
+
+		assert_ok!(Nolik::send_message(
+			RuntimeOrigin::signed(alice),
+			new_metadata(),
+			bounded(&message),
+			None,
+			MessageClass::Normal
+		));
+		// the payload is put under the derived key only
+		assert_eq!(MemoryStore::value(&key), Some(message));
+		assert_eq!(MemoryStore::value(&Nolik::derived_key(&alice, 1)), None);
+
+		assert_ok!(Nolik::retract_message(RuntimeOrigin::signed(alice), 0));
+		assert_eq!(MemoryStore::value(&key), None);
+	});
+}
+
+#[test]
+fn multi_part_message() {
+	let mut ext = new_test_ext();
+	let alice: u64 = 1;
+	let message: Vec<u8> = (0..MaxMessageSize::get() * 2 + 10).map(|i| i as u8).collect();
+	let parts = split_parts(&message, MaxMessageSize::get() as usize);
+	assert_eq!(parts.len(), 3);
+	let metadata = MessageMetadata { part_count: Some(parts.len() as u32), ..new_metadata() };
+
+	ext.execute_with(|| {
+		let items: Vec<_> = parts.iter().map(|part| (metadata.clone(), bounded(part))).collect();
+		assert_ok!(Nolik::send_messages(RuntimeOrigin::signed(alice), items.try_into().unwrap()));
+
+		for (part, bytes) in parts.iter().enumerate() {
+			let key = Nolik::part_key(&alice, 0, part as u32);
+			assert_eq!(key, Nolik::derived_key(&alice, part as u128));
+			assert_eq!(MemoryStore::value(&key).as_deref(), Some(*bytes));
+		}
+	});
+
+	ext.persist_offchain_overlay();
+
+	ext.execute_with(|| {
+		assert_eq!(Nolik::read_parts(&alice, 0, 3), Some(message));
+		// a missing part fails the whole message
+		assert_eq!(Nolik::read_parts(&alice, 0, 4), None);
+	});
+}
+
+#[test]
+fn message_deposit() {
+	let mut ext = new_test_ext();
+	let address: u64 = 1;
+	let message = b"my_encrypted_message";
+	let key = Nolik::derived_key(&address, 0);
+
+	ext.execute_with(|| {
+		assert_ok!(Nolik::send_message(
+			RuntimeOrigin::signed(address),
+			new_metadata(),
+			bounded(message),
+			None,
+			MessageClass::Normal
+		));
+		let deposit = message.len() as u64;
+		assert_eq!(Nolik::message_deposit(&key), Some(deposit));
+		assert_eq!(Balances::reserved_balance(address), deposit);
+	});
+
+	ext.persist_offchain_overlay();
+
+	ext.execute_with(|| {
+		assert_ok!(Nolik::retract_message(RuntimeOrigin::signed(address), 0));
+		assert_eq!(Nolik::message_deposit(&key), None);
+		assert_eq!(Balances::reserved_balance(address), 0);
+	});
+}
+
+#[test]
+fn insufficient_deposit_is_rejected() {
+	new_test_ext().execute_with(|| {
+		let message = bounded(&[1; 32]);
+		assert!(Balances::free_balance(LOW_BALANCE) < message.len() as u64);
+
+		assert_err!(
+			Nolik::send_message(
+				RuntimeOrigin::signed(LOW_BALANCE),
+				new_metadata(),
+				message,
+				None,
+				MessageClass::Normal
+			),
+			Error::<Test>::InsufficientDeposit
+		);
+		assert_eq!(Nolik::message_counter(LOW_BALANCE), 0);
+		assert_eq!(Balances::reserved_balance(LOW_BALANCE), 0);
+	});
+}
+
+#[test]
+fn decode_key() {
+	new_test_ext().execute_with(|| {
+		for (account, counter) in [(1u64, 0u128), (2, 42), (u64::MAX, u128::MAX)] {
+			let key = Nolik::derived_key(&account, counter);
+			assert_eq!(Nolik::decode_key(&key), Some((account, counter)));
+		}
+
+		let key = Nolik::derived_key(&1, 7);
+		// truncated or extended keys are rejected
+		assert_eq!(Nolik::decode_key(&key[..key.len() - 1]), None);
+		assert_eq!(Nolik::decode_key(&[key.as_slice(), &[0]].concat()), None);
+	});
+}
+
+#[test]
+fn verify_key() {
+	new_test_ext().execute_with(|| {
+		let key = Nolik::derived_key(&1, 7);
+		assert!(Nolik::verify_key(&key, &1));
+		assert!(!Nolik::verify_key(&key, &2));
+
+		// malformed and sealed keys have no sender
+		assert!(!Nolik::verify_key(&key[..key.len() - 1], &1));
+		assert!(!Nolik::verify_key(&Nolik::sealed_key(7), &1));
+	});
+}
+
+#[test]
+fn keys_are_namespaced() {
+	new_test_ext().execute_with(|| {
+		let key = Nolik::derived_key(&1, 7);
+		assert!(key.starts_with(MESSAGE_KEY_PREFIX));
+		assert_eq!(key[MESSAGE_KEY_PREFIX.len()..], (1u64, 7u128).encode());
+		assert_eq!(Nolik::decode_key(&key), Some((1, 7)));
+		// a key without the prefix is not a message key
+		assert_eq!(Nolik::decode_key(&key[MESSAGE_KEY_PREFIX.len()..]), None);
+
+		let sealed_key = Nolik::sealed_key(7);
+		assert!(sealed_key.starts_with(SEALED_KEY_PREFIX));
+		assert_eq!(Nolik::decode_key(&sealed_key), None);
+	});
+}
+
+#[test]
+fn low_order_broker_is_rejected() {
+	new_test_ext().execute_with(|| {
+		let message = bounded(b"my_encrypted_message");
+
+		let metadata = MessageMetadata { broker: [0; 32], ..new_metadata() };
+		assert_err!(Nolik::check_message(&message, &metadata), Error::<Test>::MetadataMalformed);
+		assert_err_ignore_postinfo!(
+			Nolik::send_message(
+				RuntimeOrigin::signed(1),
+				metadata,
+				message,
+				None,
+				MessageClass::Normal
+			),
+			Error::<Test>::MetadataMalformed
+		);
+	});
+}
+
+#[test]
+fn malformed_message_weight_is_refunded() {
+	new_test_ext().execute_with(|| {
+		let metadata = MessageMetadata { hash: [0; 32], ..new_metadata() };
+		let message = bounded(&[1; 512]);
+		let call = Call::<Test>::send_message {
+			metadata: metadata.clone(),
+			message: message.clone(),
+			ttl_blocks: None,
+			class: MessageClass::Normal,
+		};
+		let worst_case = call.get_dispatch_info().weight;
+
+		let err = Nolik::send_message(
+			RuntimeOrigin::signed(1),
+			metadata,
+			message,
+			None,
+			MessageClass::Normal,
+		)
+		.expect_err("metadata has no hash");
+		assert_eq!(err.error, Error::<Test>::InvalidMetadataHash.into());
+		let actual_weight = err.post_info.actual_weight.expect("weight is refunded");
+		assert!(actual_weight.all_lt(worst_case));
+		assert_eq!(Nolik::message_counter(1), 0);
+	});
+}
+
+#[test]
+fn milestone_reached() {
+	new_test_ext().execute_with(|| {
+		let interval = <Test as crate::Config>::MilestoneInterval::get();
+
+		for total in 1..=interval * 2 + 1 {
+			System::reset_events();
+			assert_ok!(Nolik::send_message(
+				RuntimeOrigin::signed(1),
+				new_metadata(),
+				bounded(b"my_encrypted_message"),
+				None,
+				MessageClass::Normal
+			));
+
+			let milestone: RuntimeEvent = Event::MilestoneReached { total }.into();
+			let reached = System::events().into_iter().any(|r| r.event == milestone);
+			assert_eq!(reached, total % interval == 0, "total {}", total);
+		}
+	});
+}
+
+#[test]
+fn send_sealed_message() {
+	new_test_ext().execute_with(|| {
+		let (relayer, alice): (u64, u64) = (1, 2);
+		let metadata = new_metadata();
+		let message = b"my_encrypted_message";
+
+		// the fee payer signs, unsigned messages are not free to send
+		assert_err!(
+			Nolik::send_sealed_message(
+				RuntimeOrigin::none(),
+				metadata.clone(),
+				bounded(message),
+				None
+			),
+			BadOrigin
+		);
+		assert_ok!(Nolik::send_message(
+			RuntimeOrigin::signed(alice),
+			new_metadata(),
+			bounded(message),
+			None,
+			MessageClass::Normal
+		));
+		assert_ok!(Nolik::send_sealed_message(
+			RuntimeOrigin::signed(relayer),
+			metadata.clone(),
+			bounded(message),
+			None
+		));
+
+		// nothing links the message to an account, the key is the sequence number of the message
+		let key = Nolik::sealed_key(1);
+		assert!(Nolik::decode_key(&key).is_none());
+		assert!(Nolik::verify_message(&key, message));
+		assert_eq!(Nolik::total_messages(), 2);
+		assert_eq!(Nolik::message_counter(relayer), 0);
+		System::assert_last_event(
+			Event::SealedMessageSent {
+				key: key.clone(),
+				channel_count: metadata.channels.len() as u32,
+				metadata: metadata.clone(),
+			}
+			.into(),
+		);
+
+		// resending the same metadata, e.g. to front-run a message, takes a key of its own
+		assert_ok!(Nolik::send_sealed_message(
+			RuntimeOrigin::signed(alice),
+			metadata,
+			bounded(b"another_message"),
+			None
+		));
+		assert!(Nolik::verify_message(&key, message));
+		assert!(Nolik::verify_message(&Nolik::sealed_key(2), b"another_message"));
+	});
+}
+
+#[test]
+fn sealed_messages_are_rate_limited() {
+	new_test_ext().execute_with(|| {
+		MaxPerBlock::set(2);
+		let send = || {
+			Nolik::send_sealed_message(
+				RuntimeOrigin::signed(1),
+				new_metadata(),
+				bounded(b"my_encrypted_message"),
+				None,
+			)
+		};
+
+		assert_ok!(send());
+		assert_ok!(send());
+		assert_err!(send(), Error::<Test>::RateLimited);
+
+		// the limit resets in the next block
+		System::set_block_number(2);
+		assert_ok!(send());
+	});
+}
+
+#[test]
+fn sealed_message_reserves_deposit() {
+	new_test_ext().execute_with(|| {
+		let payer: u64 = 1;
+		let message = b"my_encrypted_message";
+		assert_ok!(Nolik::send_sealed_message(
+			RuntimeOrigin::signed(payer),
+			new_metadata(),
+			bounded(message),
+			Some(2)
+		));
+
+		// the payer reserves the deposit, the same as a sender of a plain message
+		let key = Nolik::sealed_key(0);
+		let deposit = message.len() as u64;
+		assert_eq!(Nolik::message_deposit(&key), Some(deposit));
+		assert_eq!(Balances::reserved_balance(payer), deposit);
+		assert_eq!(SentMessages::get(), vec![(payer, key, 2)]);
+
+		// a payer who can't afford the deposit can't store the message
+		assert_err!(
+			Nolik::send_sealed_message(
+				RuntimeOrigin::signed(LOW_BALANCE),
+				new_metadata(),
+				bounded(&[1; 32]),
+				None
+			),
+			Error::<Test>::InsufficientDeposit
+		);
+		assert_eq!(Nolik::total_messages(), 1);
+	});
+}
+
+#[test]
+fn migrate_to_v1() {
+	new_test_ext().execute_with(|| {
+		// a storage of the pallet before versioning, with a single counter of all messages
+		StorageVersion::new(0).put::<Nolik>();
+		migrations::v0::MessageCounter::<Test>::put(7);
+
+		Nolik::on_runtime_upgrade();
+
+		assert_eq!(Nolik::on_chain_storage_version(), crate::STORAGE_VERSION);
+		assert!(!migrations::v0::MessageCounter::<Test>::exists());
+		assert_eq!(Nolik::total_messages(), 7);
+		assert_eq!(Nolik::message_counter(1), 0);
+
+		// counters work as usual after the upgrade
+		assert_ok!(Nolik::send_message(
+			RuntimeOrigin::signed(1),
+			new_metadata(),
+			bounded(b"my_encrypted_message"),
+			None,
+			MessageClass::Normal
+		));
+		assert_eq!(Nolik::message_counter(1), 1);
+		assert_eq!(Nolik::total_messages(), 8);
+
+		// the migration is not applied twice
+		Nolik::on_runtime_upgrade();
+		assert_eq!(Nolik::total_messages(), 8);
+	});
+}
+
+#[test]
+fn entry_count_is_checked() {
+	new_test_ext().execute_with(|| {
+		let max = <Test as crate::Config>::MaxEntries::get();
+		let send = |entry_count| {
+			Nolik::send_message(
+				RuntimeOrigin::signed(1),
+				MessageMetadata { entry_count, ..new_metadata() },
+				bounded(b"my_encrypted_message"),
+				None,
+				MessageClass::Normal,
+			)
+		};
+
+		assert_err_ignore_postinfo!(send(Some(0)), Error::<Test>::InvalidEntryCount);
+		assert_err_ignore_postinfo!(send(Some(max + 1)), Error::<Test>::InvalidEntryCount);
+		assert_ok!(send(Some(1)));
+		assert_ok!(send(Some(max)));
+		// the hint is optional
+		assert_ok!(send(None));
+	});
+}
+
+#[test]
+fn supersede_message() {
+	new_test_ext().execute_with(|| {
+		let address: u64 = 1;
+		assert_ok!(Nolik::send_message(
+			RuntimeOrigin::signed(address),
+			new_metadata(),
+			bounded(b"my_encrypted_message"),
+			None,
+			MessageClass::Normal
+		));
+		let original_key = Nolik::derived_key(&address, 0);
+		assert_eq!(Nolik::latest_version(&original_key), None);
+
+		for version in 1..=2 {
+			assert_ok!(Nolik::supersede_message(
+				RuntimeOrigin::signed(address),
+				original_key.clone(),
+				new_metadata(),
+				bounded(b"my_edited_message"),
+			));
+			let new_key = Nolik::derived_key(&address, version);
+			System::assert_last_event(
+				Event::Superseded { original_key: original_key.clone(), new_key: new_key.clone() }
+					.into(),
+			);
+			// the original key always resolves to the latest version
+			assert_eq!(Nolik::latest_version(&original_key), Some(new_key.clone()));
+			assert!(Nolik::verify_message(&new_key, b"my_edited_message"));
+		}
+		// the original message is kept
+		assert!(Nolik::verify_message(&original_key, b"my_encrypted_message"));
+	});
+}
+
+#[test]
+fn only_sender_can_supersede() {
+	new_test_ext().execute_with(|| {
+		assert_ok!(Nolik::send_message(
+			RuntimeOrigin::signed(1),
+			new_metadata(),
+			bounded(b"my_encrypted_message"),
+			None,
+			MessageClass::Normal
+		));
+		let original_key = Nolik::derived_key(&1, 0);
+
+		assert_err!(
+			Nolik::supersede_message(
+				RuntimeOrigin::signed(2),
+				original_key.clone(),
+				new_metadata(),
+				bounded(b"my_edited_message"),
+			),
+			Error::<Test>::NotMessageSender
+		);
+		assert_err!(
+			Nolik::supersede_message(
+				RuntimeOrigin::signed(1),
+				Nolik::derived_key(&1, 1),
+				new_metadata(),
+				bounded(b"my_edited_message"),
+			),
+			Error::<Test>::MessageNotFound
+		);
+
+		// a sealed message has no sender to supersede it
+		let sequence = Nolik::total_messages();
+		assert_ok!(Nolik::send_sealed_message(
+			RuntimeOrigin::signed(1),
+			new_metadata(),
+			bounded(b"my_encrypted_message"),
+			None
+		));
+		assert_err!(
+			Nolik::supersede_message(
+				RuntimeOrigin::signed(1),
+				Nolik::sealed_key(sequence),
+				new_metadata(),
+				bounded(b"my_edited_message"),
+			),
+			Error::<Test>::NotMessageSender
+		);
+		assert_eq!(Nolik::latest_version(&original_key), None);
+	});
+}
+
+#[test]
+fn min_message_size() {
+	new_test_ext().execute_with(|| {
+		MinMessageSize::set(4);
+		let send = |message: &[u8]| {
+			Nolik::send_message(
+				RuntimeOrigin::signed(1),
+				new_metadata(),
+				bounded(message),
+				None,
+				MessageClass::Normal,
+			)
+		};
+
+		assert_err_ignore_postinfo!(send(&[1; 3]), Error::<Test>::MessageTooSmall);
+		assert_err_ignore_postinfo!(send(&[]), Error::<Test>::MessageMalformed);
+		assert_ok!(send(&[1; 4]));
+		assert_eq!(Nolik::message_counter(1), 1);
+	});
+}
+
+#[test]
+fn rate_limited_per_block() {
+	new_test_ext().execute_with(|| {
+		MaxPerBlock::set(2);
+		let (alice, bob): (u64, u64) = (1, 2);
+		let send = |account: u64| {
+			Nolik::send_message(
+				RuntimeOrigin::signed(account),
+				new_metadata(),
+				bounded(b"my_encrypted_message"),
+				None,
+				MessageClass::Normal,
+			)
+		};
+
+		assert_ok!(send(alice));
+		assert_ok!(send(alice));
+		assert_err_ignore_postinfo!(send(alice), Error::<Test>::RateLimited);
+		// batches are limited as well
+		assert_err!(
+			Nolik::send_messages(
+				RuntimeOrigin::signed(alice),
+				vec![(new_metadata(), bounded(b"my_encrypted_message"))].try_into().unwrap()
+			),
+			Error::<Test>::RateLimited
+		);
+		assert_eq!(Nolik::message_counter(alice), 2);
+		// other accounts are not limited
+		assert_ok!(send(bob));
+
+		// the count restarts in the next block, which removes the counts of the previous one
+		System::set_block_number(2);
+		Nolik::on_initialize(2);
+		assert!(crate::SentInBlock::<Test>::iter_prefix(1).next().is_none());
+		assert_ok!(send(alice));
+		assert_eq!(Nolik::message_counter(alice), 3);
+		assert_eq!(crate::SentInBlock::<Test>::get(2, alice), 1);
+	});
+}
+
+#[test]
+fn metadata_size_is_checked_first() {
+	new_test_ext().execute_with(|| {
+		let message = bounded(b"my_encrypted_message");
+		let metadata = new_metadata();
+		let size = metadata.encoded_size() as u32;
+
+		// at the boundary
+		MaxMetadataBytes::set(size);
+		assert_ok!(Nolik::check_message(&message, &metadata));
+
+		MaxMetadataBytes::set(size - 1);
+		assert_err!(Nolik::check_message(&message, &metadata), Error::<Test>::MetadataTooLarge);
+		assert_err_ignore_postinfo!(
+			Nolik::send_message(
+				RuntimeOrigin::signed(1),
+				metadata,
+				message.clone(),
+				None,
+				MessageClass::Normal
+			),
+			Error::<Test>::MetadataTooLarge
+		);
+
+		// oversized metadata is rejected before its channels are checked
+		let metadata = metadata_with_channels(MaxChannels::get() as usize + 1);
+		MaxMetadataBytes::set(metadata.encoded_size() as u32 - 1);
+		assert_err!(Nolik::check_message(&message, &metadata), Error::<Test>::MetadataTooLarge);
+	});
+}
@@ -0,0 +1,46 @@
+//! Traits to integrate the pallet with other parts of the runtime
+
+use frame_support::{
+	sp_io::{self, offchain_index},
+	sp_runtime::offchain::StorageKind,
+};
+use scale_info::prelude::vec::Vec;
+
+/// Handler called after a message is stored
+pub trait OnMessageSent<AccountId> {
+	/// The `sender` has sent a message stored under the off-chain `key`
+	fn on_message_sent(sender: &AccountId, key: &[u8], channel_count: u32);
+}
+
+impl<AccountId> OnMessageSent<AccountId> for () {
+	fn on_message_sent(_sender: &AccountId, _key: &[u8], _channel_count: u32) {}
+}
+
+/// Storage of message payloads outside of the chain state
+pub trait MessageStore {
+	/// Put the `value` under the `key`
+	fn put(key: &[u8], value: &[u8]);
+	/// Get the value under the `key`
+	fn get(key: &[u8]) -> Option<Vec<u8>>;
+	/// Clear the value under the `key`
+	fn clear(key: &[u8]);
+}
+
+/// Stores messages in the persistent local storage of the node with `offchain_index`. Writes take
+/// effect only during block execution, while reads are only available off-chain, e.g. in an
+/// offchain worker or RPC
+pub struct OffchainIndex;
+
+impl MessageStore for OffchainIndex {
+	fn put(key: &[u8], value: &[u8]) {
+		offchain_index::set(key, value);
+	}
+
+	fn get(key: &[u8]) -> Option<Vec<u8>> {
+		sp_io::offchain::local_storage_get(StorageKind::PERSISTENT, key)
+	}
+
+	fn clear(key: &[u8]) {
+		offchain_index::clear(key);
+	}
+}
@@ -0,0 +1,329 @@
+//! PLACEHOLDER weights for pallet_nolik
+//!
+//! The values are hand-written estimates, NOT produced by the benchmark CLI, and the storage
+//! comments are not measured. They keep the pallet usable in tests and development only and must
+//! be replaced by the output of the benchmarks in `benchmarking.rs` before the runtime uses
+//! `SubstrateWeight`. Run on the reference hardware:
+//!
+//! ```bash
+//! ./target/release/node-nolik benchmark pallet \
+//! 	--chain dev \
+//! 	--pallet pallet_nolik \
+//! 	--extrinsic '*' \
+//! 	--steps 50 \
+//! 	--repeat 20 \
+//! 	--output pallets/nolik/src/weights.rs
+//! ```
+
+#![cfg_attr(rustfmt, rustfmt_skip)]
+#![allow(unused_parens)]
+#![allow(unused_imports)]
+
+use core::marker::PhantomData;
+use frame_support::{traits::Get, weights::{Weight, constants::RocksDbWeight}};
+
+/// Weight functions needed for pallet_nolik.
+pub trait WeightInfo {
+	fn send_message(l: u32, c: u32, ) -> Weight;
+	fn check_message(c: u32, ) -> Weight;
+	fn send_messages(n: u32, l: u32, ) -> Weight;
+	fn send_sealed_message(l: u32, c: u32, ) -> Weight;
+	fn supersede_message(l: u32, c: u32, ) -> Weight;
+	fn retract_message() -> Weight;
+	fn prune_account(m: u32, ) -> Weight;
+	fn acknowledge() -> Weight;
+	fn block_sender() -> Weight;
+	fn unblock_sender() -> Weight;
+	fn register_public_key() -> Weight;
+	fn rotate_public_key() -> Weight;
+	fn set_alias() -> Weight;
+}
+
+/// Placeholder weights for pallet_nolik, to be regenerated by the benchmark CLI.
+pub struct SubstrateWeight<T>(PhantomData<T>);
+impl<T: frame_system::Config> WeightInfo for SubstrateWeight<T> {
+	// Storage: Nolik SentInBlock (r:1 w:1)
+	// Storage: Nolik MessageCounter (r:1 w:1)
+	// Storage: Nolik TotalMessages (r:1 w:1)
+	// Storage: Nolik MessageCommitments (r:0 w:1)
+	// Storage: Nolik CommittedMessages (r:0 w:1)
+	// Storage: Nolik MessageExpiry (r:0 w:1)
+	// Storage: Nolik ExpiringMessages (r:0 w:1)
+	// Storage: Nolik ExpiringCount (r:1 w:1)
+	// Storage: System Account (r:1 w:1)
+	// Storage: Nolik MessageDeposits (r:0 w:1)
+	/// The range of component `l` is `[1, 1048576]`.
+	/// The range of component `c` is `[1, 128]`.
+	fn send_message(l: u32, c: u32, ) -> Weight {
+		Weight::from_parts(18_563_000, 0)
+			.saturating_add(Weight::from_parts(2_011, 0).saturating_mul(l.into()))
+			.saturating_add(Weight::from_parts(1_104_871, 0).saturating_mul(c.into()))
+			.saturating_add(T::DbWeight::get().reads(5))
+			.saturating_add(T::DbWeight::get().writes(10))
+	}
+	/// The range of component `c` is `[1, 128]`.
+	fn check_message(c: u32, ) -> Weight {
+		Weight::from_parts(2_104_000, 0)
+			.saturating_add(Weight::from_parts(312_450, 0).saturating_mul(c.into()))
+	}
+	// Storage: Nolik SentInBlock (r:1 w:1)
+	// Storage: Nolik MessageCounter (r:1 w:1)
+	// Storage: Nolik TotalMessages (r:1 w:1)
+	// Storage: System Account (r:1 w:1)
+	// Storage: Nolik MessageCommitments (r:0 w:16)
+	// Storage: Nolik CommittedMessages (r:0 w:16)
+	// Storage: Nolik MessageDeposits (r:0 w:16)
+	/// The range of component `n` is `[1, 16]`.
+	/// The range of component `l` is `[1, 1048576]`.
+	fn send_messages(n: u32, l: u32, ) -> Weight {
+		Weight::from_parts(9_412_000, 0)
+			.saturating_add(Weight::from_parts(54_930_522, 0).saturating_mul(n.into()))
+			.saturating_add(Weight::from_parts(2_011, 0).saturating_mul(l.into()))
+			.saturating_add(T::DbWeight::get().reads(4))
+			.saturating_add(T::DbWeight::get().writes(4))
+			.saturating_add(T::DbWeight::get().writes((3_u64).saturating_mul(n.into())))
+	}
+	// Storage: Nolik SentInBlock (r:1 w:1)
+	// Storage: Nolik MessageCommitments (r:1 w:1)
+	// Storage: Nolik CommittedMessages (r:0 w:1)
+	// Storage: Nolik MessageCounter (r:1 w:1)
+	// Storage: Nolik TotalMessages (r:1 w:1)
+	// Storage: System Account (r:1 w:1)
+	// Storage: Nolik MessageDeposits (r:0 w:1)
+	// Storage: Nolik LatestVersions (r:0 w:1)
+	/// The range of component `l` is `[1, 1048576]`.
+	/// The range of component `c` is `[1, 128]`.
+	fn supersede_message(l: u32, c: u32, ) -> Weight {
+		Weight::from_parts(21_318_000, 0)
+			.saturating_add(Weight::from_parts(2_011, 0).saturating_mul(l.into()))
+			.saturating_add(Weight::from_parts(1_104_871, 0).saturating_mul(c.into()))
+			.saturating_add(T::DbWeight::get().reads(5))
+			.saturating_add(T::DbWeight::get().writes(8))
+	}
+	// Storage: Nolik SentInBlock (r:1 w:1)
+	// Storage: Nolik TotalMessages (r:1 w:1)
+	// Storage: Nolik MessageCommitments (r:0 w:1)
+	// Storage: Nolik CommittedMessages (r:0 w:1)
+	// Storage: Nolik MessageExpiry (r:0 w:1)
+	// Storage: Nolik ExpiringMessages (r:0 w:1)
+	// Storage: Nolik ExpiringCount (r:1 w:1)
+	// Storage: System Account (r:1 w:1)
+	// Storage: Nolik MessageDeposits (r:0 w:1)
+	/// The range of component `l` is `[1, 1048576]`.
+	/// The range of component `c` is `[1, 128]`.
+	fn send_sealed_message(l: u32, c: u32, ) -> Weight {
+		Weight::from_parts(17_214_000, 0)
+			.saturating_add(Weight::from_parts(2_011, 0).saturating_mul(l.into()))
+			.saturating_add(Weight::from_parts(1_104_871, 0).saturating_mul(c.into()))
+			.saturating_add(T::DbWeight::get().reads(4))
+			.saturating_add(T::DbWeight::get().writes(9))
+	}
+	// Storage: Nolik MessageCommitments (r:1 w:1)
+	// Storage: Nolik MessageExpiry (r:1 w:1)
+	// Storage: Nolik ExpiringMessages (r:0 w:1)
+	// Storage: Nolik ExpiringCount (r:1 w:1)
+	// Storage: Nolik MessageDeposits (r:1 w:1)
+	// Storage: System Account (r:1 w:1)
+	fn retract_message() -> Weight {
+		Weight::from_parts(23_000_000, 0)
+			.saturating_add(T::DbWeight::get().reads(5))
+			.saturating_add(T::DbWeight::get().writes(6))
+	}
+	// Storage: Nolik PrunedMessages (r:1 w:1)
+	// Storage: Nolik MessageCounter (r:1 w:0)
+	// Storage: Nolik MessageCommitments (r:1000 w:1000)
+	// Storage: Nolik MessageExpiry (r:1000 w:1000)
+	// Storage: Nolik ExpiringMessages (r:0 w:1000)
+	// Storage: Nolik ExpiringCount (r:1000 w:1000)
+	// Storage: Nolik MessageDeposits (r:1000 w:1000)
+	// Storage: System Account (r:1 w:1)
+	/// The range of component `m` is `[0, 1000]`.
+	fn prune_account(m: u32, ) -> Weight {
+		Weight::from_parts(12_406_000, 0)
+			.saturating_add(Weight::from_parts(14_286_315, 0).saturating_mul(m.into()))
+			.saturating_add(T::DbWeight::get().reads(3))
+			.saturating_add(T::DbWeight::get().reads((4_u64).saturating_mul(m.into())))
+			.saturating_add(T::DbWeight::get().writes(2))
+			.saturating_add(T::DbWeight::get().writes((5_u64).saturating_mul(m.into())))
+	}
+	// Storage: Nolik MessageCommitments (r:1 w:0)
+	// Storage: Nolik ReadReceipts (r:1 w:1)
+	fn acknowledge() -> Weight {
+		Weight::from_parts(19_000_000, 0)
+			.saturating_add(T::DbWeight::get().reads(2))
+			.saturating_add(T::DbWeight::get().writes(1))
+	}
+	// Storage: Nolik BlockedSenders (r:0 w:1)
+	fn block_sender() -> Weight {
+		Weight::from_parts(14_000_000, 0)
+			.saturating_add(T::DbWeight::get().writes(1))
+	}
+	// Storage: Nolik BlockedSenders (r:0 w:1)
+	fn unblock_sender() -> Weight {
+		Weight::from_parts(14_000_000, 0)
+			.saturating_add(T::DbWeight::get().writes(1))
+	}
+	// Storage: Nolik PublicKeys (r:1 w:1)
+	fn register_public_key() -> Weight {
+		Weight::from_parts(13_000_000, 0)
+			.saturating_add(T::DbWeight::get().reads(1))
+			.saturating_add(T::DbWeight::get().writes(1))
+	}
+	// Storage: Nolik PublicKeys (r:1 w:1)
+	fn rotate_public_key() -> Weight {
+		Weight::from_parts(62_000_000, 0)
+			.saturating_add(T::DbWeight::get().reads(1))
+			.saturating_add(T::DbWeight::get().writes(1))
+	}
+	// Storage: Nolik AliasOwners (r:1 w:2)
+	// Storage: Nolik Aliases (r:1 w:1)
+	fn set_alias() -> Weight {
+		Weight::from_parts(19_000_000, 0)
+			.saturating_add(T::DbWeight::get().reads(2))
+			.saturating_add(T::DbWeight::get().writes(3))
+	}
+}
+
+// For backwards compatibility and tests, the same placeholder values
+impl WeightInfo for () {
+	// Storage: Nolik SentInBlock (r:1 w:1)
+	// Storage: Nolik MessageCounter (r:1 w:1)
+	// Storage: Nolik TotalMessages (r:1 w:1)
+	// Storage: Nolik MessageCommitments (r:0 w:1)
+	// Storage: Nolik CommittedMessages (r:0 w:1)
+	// Storage: Nolik MessageExpiry (r:0 w:1)
+	// Storage: Nolik ExpiringMessages (r:0 w:1)
+	// Storage: Nolik ExpiringCount (r:1 w:1)
+	// Storage: System Account (r:1 w:1)
+	// Storage: Nolik MessageDeposits (r:0 w:1)
+	/// The range of component `l` is `[1, 1048576]`.
+	/// The range of component `c` is `[1, 128]`.
+	fn send_message(l: u32, c: u32, ) -> Weight {
+		Weight::from_parts(18_563_000, 0)
+			.saturating_add(Weight::from_parts(2_011, 0).saturating_mul(l.into()))
+			.saturating_add(Weight::from_parts(1_104_871, 0).saturating_mul(c.into()))
+			.saturating_add(RocksDbWeight::get().reads(5))
+			.saturating_add(RocksDbWeight::get().writes(10))
+	}
+	/// The range of component `c` is `[1, 128]`.
+	fn check_message(c: u32, ) -> Weight {
+		Weight::from_parts(2_104_000, 0)
+			.saturating_add(Weight::from_parts(312_450, 0).saturating_mul(c.into()))
+	}
+	// Storage: Nolik SentInBlock (r:1 w:1)
+	// Storage: Nolik MessageCounter (r:1 w:1)
+	// Storage: Nolik TotalMessages (r:1 w:1)
+	// Storage: System Account (r:1 w:1)
+	// Storage: Nolik MessageCommitments (r:0 w:16)
+	// Storage: Nolik CommittedMessages (r:0 w:16)
+	// Storage: Nolik MessageDeposits (r:0 w:16)
+	/// The range of component `n` is `[1, 16]`.
+	/// The range of component `l` is `[1, 1048576]`.
+	fn send_messages(n: u32, l: u32, ) -> Weight {
+		Weight::from_parts(9_412_000, 0)
+			.saturating_add(Weight::from_parts(54_930_522, 0).saturating_mul(n.into()))
+			.saturating_add(Weight::from_parts(2_011, 0).saturating_mul(l.into()))
+			.saturating_add(RocksDbWeight::get().reads(4))
+			.saturating_add(RocksDbWeight::get().writes(4))
+			.saturating_add(RocksDbWeight::get().writes((3_u64).saturating_mul(n.into())))
+	}
+	// Storage: Nolik SentInBlock (r:1 w:1)
+	// Storage: Nolik MessageCommitments (r:1 w:1)
+	// Storage: Nolik CommittedMessages (r:0 w:1)
+	// Storage: Nolik MessageCounter (r:1 w:1)
+	// Storage: Nolik TotalMessages (r:1 w:1)
+	// Storage: System Account (r:1 w:1)
+	// Storage: Nolik MessageDeposits (r:0 w:1)
+	// Storage: Nolik LatestVersions (r:0 w:1)
+	/// The range of component `l` is `[1, 1048576]`.
+	/// The range of component `c` is `[1, 128]`.
+	fn supersede_message(l: u32, c: u32, ) -> Weight {
+		Weight::from_parts(21_318_000, 0)
+			.saturating_add(Weight::from_parts(2_011, 0).saturating_mul(l.into()))
+			.saturating_add(Weight::from_parts(1_104_871, 0).saturating_mul(c.into()))
+			.saturating_add(RocksDbWeight::get().reads(5))
+			.saturating_add(RocksDbWeight::get().writes(8))
+	}
+	// Storage: Nolik SentInBlock (r:1 w:1)
+	// Storage: Nolik TotalMessages (r:1 w:1)
+	// Storage: Nolik MessageCommitments (r:0 w:1)
+	// Storage: Nolik CommittedMessages (r:0 w:1)
+	// Storage: Nolik MessageExpiry (r:0 w:1)
+	// Storage: Nolik ExpiringMessages (r:0 w:1)
+	// Storage: Nolik ExpiringCount (r:1 w:1)
+	// Storage: System Account (r:1 w:1)
+	// Storage: Nolik MessageDeposits (r:0 w:1)
+	/// The range of component `l` is `[1, 1048576]`.
+	/// The range of component `c` is `[1, 128]`.
+	fn send_sealed_message(l: u32, c: u32, ) -> Weight {
+		Weight::from_parts(17_214_000, 0)
+			.saturating_add(Weight::from_parts(2_011, 0).saturating_mul(l.into()))
+			.saturating_add(Weight::from_parts(1_104_871, 0).saturating_mul(c.into()))
+			.saturating_add(RocksDbWeight::get().reads(4))
+			.saturating_add(RocksDbWeight::get().writes(9))
+	}
+	// Storage: Nolik MessageCommitments (r:1 w:1)
+	// Storage: Nolik MessageExpiry (r:1 w:1)
+	// Storage: Nolik ExpiringMessages (r:0 w:1)
+	// Storage: Nolik ExpiringCount (r:1 w:1)
+	// Storage: Nolik MessageDeposits (r:1 w:1)
+	// Storage: System Account (r:1 w:1)
+	fn retract_message() -> Weight {
+		Weight::from_parts(23_000_000, 0)
+			.saturating_add(RocksDbWeight::get().reads(5))
+			.saturating_add(RocksDbWeight::get().writes(6))
+	}
+	// Storage: Nolik PrunedMessages (r:1 w:1)
+	// Storage: Nolik MessageCounter (r:1 w:0)
+	// Storage: Nolik MessageCommitments (r:1000 w:1000)
+	// Storage: Nolik MessageExpiry (r:1000 w:1000)
+	// Storage: Nolik ExpiringMessages (r:0 w:1000)
+	// Storage: Nolik ExpiringCount (r:1000 w:1000)
+	// Storage: Nolik MessageDeposits (r:1000 w:1000)
+	// Storage: System Account (r:1 w:1)
+	/// The range of component `m` is `[0, 1000]`.
+	fn prune_account(m: u32, ) -> Weight {
+		Weight::from_parts(12_406_000, 0)
+			.saturating_add(Weight::from_parts(14_286_315, 0).saturating_mul(m.into()))
+			.saturating_add(RocksDbWeight::get().reads(3))
+			.saturating_add(RocksDbWeight::get().reads((4_u64).saturating_mul(m.into())))
+			.saturating_add(RocksDbWeight::get().writes(2))
+			.saturating_add(RocksDbWeight::get().writes((5_u64).saturating_mul(m.into())))
+	}
+	// Storage: Nolik MessageCommitments (r:1 w:0)
+	// Storage: Nolik ReadReceipts (r:1 w:1)
+	fn acknowledge() -> Weight {
+		Weight::from_parts(19_000_000, 0)
+			.saturating_add(RocksDbWeight::get().reads(2))
+			.saturating_add(RocksDbWeight::get().writes(1))
+	}
+	// Storage: Nolik BlockedSenders (r:0 w:1)
+	fn block_sender() -> Weight {
+		Weight::from_parts(14_000_000, 0)
+			.saturating_add(RocksDbWeight::get().writes(1))
+	}
+	// Storage: Nolik BlockedSenders (r:0 w:1)
+	fn unblock_sender() -> Weight {
+		Weight::from_parts(14_000_000, 0)
+			.saturating_add(RocksDbWeight::get().writes(1))
+	}
+	// Storage: Nolik PublicKeys (r:1 w:1)
+	fn register_public_key() -> Weight {
+		Weight::from_parts(13_000_000, 0)
+			.saturating_add(RocksDbWeight::get().reads(1))
+			.saturating_add(RocksDbWeight::get().writes(1))
+	}
+	// Storage: Nolik PublicKeys (r:1 w:1)
+	fn rotate_public_key() -> Weight {
+		Weight::from_parts(62_000_000, 0)
+			.saturating_add(RocksDbWeight::get().reads(1))
+			.saturating_add(RocksDbWeight::get().writes(1))
+	}
+	// Storage: Nolik AliasOwners (r:1 w:2)
+	// Storage: Nolik Aliases (r:1 w:1)
+	fn set_alias() -> Weight {
+		Weight::from_parts(19_000_000, 0)
+			.saturating_add(RocksDbWeight::get().reads(2))
+			.saturating_add(RocksDbWeight::get().writes(3))
+	}
+}
@@ -275,9 +275,34 @@ impl pallet_sudo::Config for Runtime {
 	type RuntimeCall = RuntimeCall;
 }
 
+/// Deposit reserved for every byte of a stored message.
+pub const MESSAGE_DEPOSIT_PER_BYTE: u128 = 1;
+
 /// Configure the pallet-nolik in pallets/nolic.
 impl pallet_nolik::Config for Runtime {
 	type RuntimeEvent = RuntimeEvent;
+	type MaxMessageSize = ConstU32<{ 1024 * 1024 }>;
+	type MinMessageSize = ConstU32<1>;
+	// the limits match `nolik_metadata::MetadataLimits::DEFAULT` clients check metadata against
+	type MaxChannels = ConstU32<128>;
+	type MaxPartiesPerChannel = ConstU32<128>;
+	// fits `MaxChannels` channels of `MaxPartiesPerChannel` encrypted keys
+	type MaxMetadataBytes = ConstU32<{ 1024 * 1024 }>;
+	type MaxEntries = ConstU32<256>;
+	type MaxBatch = ConstU32<16>;
+	type MaxAliasLen = ConstU32<32>;
+	type Currency = Balances;
+	type DepositPerByte = ConstU128<MESSAGE_DEPOSIT_PER_BYTE>;
+	type MilestoneInterval = ConstU128<1_000_000>;
+	type MaxPerBlock = ConstU32<16>;
+	type OnMessageSent = ();
+	type MissingCheckWindow = ConstU32<64>;
+	type MessageStore = pallet_nolik::OffchainIndex;
+	type MaxTtl = ConstU32<{ 30 * DAYS }>;
+	type MaxExpiringPerBlock = ConstU32<1024>;
+	// `pallet_nolik::weights` holds placeholder values, switch to `SubstrateWeight<Runtime>` once
+	// they are regenerated by the benchmark CLI
+	type WeightInfo = ();
 }
 
 // Create the runtime by composing the FRAME pallets that were previously configured.
@@ -494,6 +519,20 @@ impl_runtime_apis! {
 		}
 	}
 
+	impl pallet_nolik_runtime_api::NolikApi<Block, AccountId> for Runtime {
+		fn message_counter(account: AccountId) -> u128 {
+			Nolik::message_counter(account)
+		}
+
+		fn predict_key(account: AccountId, counter: u128) -> Vec<u8> {
+			Nolik::derived_key(&account, counter)
+		}
+
+		fn is_registered(account: AccountId) -> bool {
+			Nolik::is_registered(&account)
+		}
+	}
+
 	#[cfg(feature = "runtime-benchmarks")]
 	impl frame_benchmarking::Benchmark<Block> for Runtime {
 		fn benchmark_metadata(extra: bool) -> (
@@ -593,4 +632,45 @@ mod tests {
 			whitelist.contains("26aa394eea5630e07c48ae0c9558cef780d41e5e16056765bc8461851072c9d7")
 		);
 	}
+
+	#[test]
+	fn predict_key_matches_derived_key() {
+		use pallet_nolik_runtime_api::runtime_decl_for_NolikApi::NolikApi;
+
+		let account = AccountId::from([1; 32]);
+		for counter in [0, 1, u128::MAX] {
+			assert_eq!(
+				<Runtime as NolikApi<Block, AccountId>>::predict_key(account.clone(), counter),
+				Nolik::derived_key(&account, counter)
+			);
+		}
+	}
+
+	#[test]
+	fn message_counter_reads_pallet_storage() {
+		use codec::Encode;
+		use frame_support::{
+			storage::migration::put_storage_value, Blake2_128Concat, StorageHasher,
+		};
+		use pallet_nolik_runtime_api::runtime_decl_for_NolikApi::NolikApi;
+
+		let account = AccountId::from([1; 32]);
+		let counter = |account: &AccountId| {
+			<Runtime as NolikApi<Block, AccountId>>::message_counter(account.clone())
+		};
+		frame_support::sp_io::TestExternalities::default().execute_with(|| {
+			assert_eq!(counter(&account), 0);
+
+			// the counter of an account that has sent 7 messages
+			put_storage_value(
+				b"Nolik",
+				b"MessageCounter",
+				&Blake2_128Concat::hash(&account.encode()),
+				7u128,
+			);
+			assert_eq!(counter(&account), 7);
+			assert_eq!(counter(&account), Nolik::message_counter(&account));
+			assert_eq!(counter(&AccountId::from([2; 32])), 0);
+		});
+	}
 }